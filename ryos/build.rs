@@ -20,4 +20,10 @@ fn main() {
     // pass the disk image paths via environment variables
     println!("cargo:rustc-env=UEFI_IMAGE={}", uefi_path.display());
     println!("cargo:rustc-env=BIOS_IMAGE={}", bios_path.display());
+
+    // the user programs the runner installs in /bin
+    println!("cargo:rustc-env=HELLO_PROGRAM={}", env::var("CARGO_BIN_FILE_HELLO").unwrap());
+    println!("cargo:rustc-env=SPAWNTEST_PROGRAM={}", env::var("CARGO_BIN_FILE_SPAWNTEST").unwrap());
+    println!("cargo:rustc-env=UPPER_PROGRAM={}", env::var("CARGO_BIN_FILE_UPPER").unwrap());
+    println!("cargo:rustc-env=IPCTEST_PROGRAM={}", env::var("CARGO_BIN_FILE_IPCTEST").unwrap());
 }
\ No newline at end of file
@@ -0,0 +1,400 @@
+//AHCI DRIVER
+//Driver for SATA disks behind an AHCI host bus adapter, one command slot per port
+use crate::file_system::block_device::BlockDevice;
+use crate::file_system::disk_driver::{decode_error_register, SECTOR_SIZE};
+use crate::file_system::errors::FileSystemError;
+use crate::memory::dma::DmaRegion;
+use crate::memory::mmio::map_mmio;
+use crate::pci::bus::{self, Bar};
+use crate::eprintln;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+// pci class of an ahci controller
+const CLASS_MASS_STORAGE: u8 = 0x01;
+const SUBCLASS_SATA: u8 = 0x06;
+const PROG_IF_AHCI: u8 = 0x01;
+const ABAR_INDEX: u8 = 5;
+const ABAR_SIZE: u64 = 0x1100;
+
+// generic host control registers
+const HBA_CAP: usize = 0x00;
+const HBA_GHC: usize = 0x04;
+const HBA_PI: usize = 0x0c;
+const GHC_AHCI_ENABLE: u32 = 1 << 31;
+const CAP_64BIT: u32 = 1 << 31;
+
+// port registers, relative to the port's register block
+const PORTS_OFFSET: usize = 0x100;
+const PORT_SIZE: usize = 0x80;
+const PORT_CLB: usize = 0x00;
+const PORT_CLBU: usize = 0x04;
+const PORT_FB: usize = 0x08;
+const PORT_FBU: usize = 0x0c;
+const PORT_IS: usize = 0x10;
+const PORT_IE: usize = 0x14;
+const PORT_CMD: usize = 0x18;
+const PORT_TFD: usize = 0x20;
+const PORT_SIG: usize = 0x24;
+const PORT_SSTS: usize = 0x28;
+const PORT_SERR: usize = 0x30;
+const PORT_CI: usize = 0x38;
+
+const CMD_ST: u32 = 1 << 0;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+const IS_TFES: u32 = 1 << 30;
+
+const SSTS_DET_PRESENT: u32 = 3;
+const SSTS_IPM_ACTIVE: u32 = 1;
+const SIG_ATA: u32 = 0x0000_0101;
+
+// task file status bits (same meaning as the ata status register)
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_DF: u32 = 1 << 5;
+const TFD_BSY: u32 = 1 << 7;
+
+// fis types and ata commands
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+const FIS_TYPE_REG_D2H: u8 = 0x34;
+const FIS_COMMAND_BIT: u8 = 0x80;
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+// for drives without lba48, they reach the first 2^28 sectors
+const ATA_READ_DMA: u8 = 0xc8;
+const ATA_WRITE_DMA: u8 = 0xca;
+const ATA_IDENTIFY: u8 = 0xec;
+const DEVICE_LBA: u8 = 1 << 6;
+// identify word 83 bit 10, the drive supports the lba48 commands
+const IDENTIFY_LBA48: u16 = 1 << 10;
+
+// layout of the per port page: command list, received fis area and the command table
+const COMMAND_LIST_OFFSET: usize = 0;
+const RECEIVED_FIS_OFFSET: usize = 1024;
+const D2H_FIS_OFFSET: usize = RECEIVED_FIS_OFFSET + 0x40;
+const COMMAND_TABLE_OFFSET: usize = 2048;
+const PRDT_OFFSET: usize = COMMAND_TABLE_OFFSET + 0x80;
+const HEADER_WRITE: u32 = 1 << 6;
+const H2D_FIS_DWORDS: u32 = 5;
+
+// data goes through a bounce buffer of this many pages, bigger transfers are split
+const BOUNCE_PAGES: usize = 16;
+const BOUNCE_SECTORS: usize = BOUNCE_PAGES * 4096 / SECTOR_SIZE;
+// register polls before giving up on the port
+const TIMEOUT_POLLS: u32 = 1_000_000;
+
+struct PortRegisters {
+    base: VirtAddr,
+}
+
+impl PortRegisters {
+    fn read(&self, register: usize) -> u32 {
+        unsafe { read_volatile((self.base + register as u64).as_ptr::<u32>()) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { write_volatile((self.base + register as u64).as_mut_ptr::<u32>(), value) }
+    }
+
+    // poll until all bits in 'mask' of 'register' are clear
+    fn wait_clear(&self, register: usize, mask: u32) -> Result<(), FileSystemError> {
+        for _ in 0..TIMEOUT_POLLS {
+            if self.read(register) & mask == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(FileSystemError::DiskTimeout)
+    }
+}
+
+struct PortMemory {
+    page: DmaRegion,
+    bounce: DmaRegion,
+}
+
+pub struct AhciPort {
+    registers: PortRegisters,
+    memory: Mutex<PortMemory>,
+    sectors: u64,
+    lba48: bool,
+}
+
+impl AhciPort {
+    /*
+    this function stops the port, points it to our command list and fis area and starts it again.
+    without 'addressing_64bit' the controller ignores the upper address registers, so the command
+    list, fis area and bounce buffer have to be below 4 GiB
+     */
+    fn init(registers: PortRegisters, addressing_64bit: bool) -> Result<Self, FileSystemError> {
+        let allocate = match addressing_64bit {
+            true => DmaRegion::allocate,
+            false => DmaRegion::allocate_32bit,
+        };
+        let memory = PortMemory {
+            page: allocate(1).ok_or(FileSystemError::OutOfSpace)?,
+            bounce: allocate(BOUNCE_PAGES).ok_or(FileSystemError::OutOfSpace)?,
+        };
+
+        Self::stop(&registers)?;
+
+        let command_list = memory.page.phys(COMMAND_LIST_OFFSET);
+        let received_fis = memory.page.phys(RECEIVED_FIS_OFFSET);
+        registers.write(PORT_CLB, command_list as u32);
+        registers.write(PORT_CLBU, (command_list >> 32) as u32);
+        registers.write(PORT_FB, received_fis as u32);
+        registers.write(PORT_FBU, (received_fis >> 32) as u32);
+
+        // polled driver, clear stale errors and keep the port interrupts off
+        registers.write(PORT_SERR, 0xffff_ffff);
+        registers.write(PORT_IS, 0xffff_ffff);
+        registers.write(PORT_IE, 0);
+
+        registers.write(PORT_CMD, registers.read(PORT_CMD) | CMD_FRE);
+        registers.write(PORT_CMD, registers.read(PORT_CMD) | CMD_ST);
+
+        let mut port = AhciPort {
+            registers,
+            memory: Mutex::new(memory),
+            sectors: 0,
+            lba48: false,
+        };
+        match port.identify() {
+            Ok((sectors, lba48)) => {
+                port.sectors = sectors;
+                port.lba48 = lba48;
+                Ok(port)
+            }
+            Err(e) => {
+                // the memory is given back when it is dropped, unless the port may still write to it
+                let AhciPort { registers, memory, .. } = port;
+                if Self::stop(&registers).is_err() {
+                    core::mem::forget(memory);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // stop processing commands and receiving fises, the port doesn't use its memory afterwards
+    fn stop(registers: &PortRegisters) -> Result<(), FileSystemError> {
+        registers.write(PORT_CMD, registers.read(PORT_CMD) & !CMD_ST);
+        registers.wait_clear(PORT_CMD, CMD_CR)?;
+        registers.write(PORT_CMD, registers.read(PORT_CMD) & !CMD_FRE);
+        registers.wait_clear(PORT_CMD, CMD_FR)
+    }
+
+    // the number of sectors and whether the drive takes lba48 commands
+    fn identify(&self) -> Result<(u64, bool), FileSystemError> {
+        let mut identity = [0u16; SECTOR_SIZE / 2];
+        self.command(ATA_IDENTIFY, false, 0, 1, identity.as_mut_ptr() as *mut u8)?;
+        if identity[83] & IDENTIFY_LBA48 == 0 {
+            // words 60-61 hold the number of lba28 addressable sectors
+            return Ok(((identity[61] as u64) << 16 | identity[60] as u64, false));
+        }
+        // words 100-103 hold the number of lba48 addressable sectors
+        let sectors = (identity[103] as u64) << 48
+            | (identity[102] as u64) << 32
+            | (identity[101] as u64) << 16
+            | identity[100] as u64;
+        Ok((sectors, true))
+    }
+
+    // build a command in slot 0, issue it and wait for the device to finish
+    fn command(&self, command: u8, write: bool, lba: u64, sectors: usize, data: *mut u8) -> Result<(), FileSystemError> {
+        let memory = self.memory.lock();
+        let length = sectors * SECTOR_SIZE;
+        if write {
+            unsafe {
+                core::ptr::copy_nonoverlapping(data, memory.bounce.ptr::<u8>(0), length);
+            }
+        }
+
+        // command header: fis length, direction and one prdt entry
+        let mut flags = H2D_FIS_DWORDS | 1 << 16;
+        if write {
+            flags |= HEADER_WRITE;
+        }
+        let table = memory.page.phys(COMMAND_TABLE_OFFSET);
+        let header = memory.page.ptr::<[u32; 8]>(COMMAND_LIST_OFFSET);
+        unsafe {
+            write_volatile(header, [flags, 0, table as u32, (table >> 32) as u32, 0, 0, 0, 0]);
+        }
+
+        // host to device register fis, the lba28 commands take the lba bits 24-27 in the device register
+        let count = sectors as u16;
+        let (device, high) = match command {
+            ATA_READ_DMA | ATA_WRITE_DMA => (DEVICE_LBA | (lba >> 24) as u8 & 0xf, 0),
+            _ => (DEVICE_LBA, lba >> 24),
+        };
+        let fis: [u8; 20] = [
+            FIS_TYPE_REG_H2D,
+            FIS_COMMAND_BIT,
+            command,
+            0,
+            lba as u8,
+            (lba >> 8) as u8,
+            (lba >> 16) as u8,
+            device,
+            high as u8,
+            (high >> 8) as u8,
+            (high >> 16) as u8,
+            0,
+            count as u8,
+            (count >> 8) as u8,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ];
+        unsafe {
+            write_volatile(memory.page.ptr::<[u8; 20]>(COMMAND_TABLE_OFFSET), fis);
+        }
+
+        // physical region descriptor, the byte count is stored minus one
+        let buffer = memory.bounce.phys(0);
+        let prdt = [buffer as u32, (buffer >> 32) as u32, 0, (length - 1) as u32];
+        unsafe {
+            write_volatile(memory.page.ptr::<[u32; 4]>(PRDT_OFFSET), prdt);
+        }
+
+        self.registers.wait_clear(PORT_TFD, TFD_BSY | TFD_DRQ)?;
+        self.registers.write(PORT_IS, 0xffff_ffff);
+        self.registers.write(PORT_CI, 1);
+
+        let mut polls = 0;
+        while self.registers.read(PORT_CI) & 1 != 0 {
+            if self.registers.read(PORT_IS) & IS_TFES != 0 {
+                break;
+            }
+            polls += 1;
+            if polls > TIMEOUT_POLLS {
+                self.restart();
+                return Err(FileSystemError::DiskTimeout);
+            }
+            core::hint::spin_loop();
+        }
+
+        let task_file = self.registers.read(PORT_TFD);
+        if self.registers.read(PORT_IS) & IS_TFES != 0 || task_file & (TFD_ERR | TFD_DF) != 0 {
+            let error = self.received_error(&memory, task_file);
+            self.restart();
+            return Err(error);
+        }
+
+        if !write {
+            unsafe {
+                core::ptr::copy_nonoverlapping(memory.bounce.ptr::<u8>(0), data, length);
+            }
+        }
+        Ok(())
+    }
+
+    // decode the device to host fis the drive sent back, falling back to the task file register
+    fn received_error(&self, memory: &PortMemory, task_file: u32) -> FileSystemError {
+        let fis = unsafe { read_volatile(memory.page.ptr::<[u8; 4]>(D2H_FIS_OFFSET)) };
+        let (status, error) = if fis[0] == FIS_TYPE_REG_D2H {
+            (fis[2] as u32, fis[3])
+        } else {
+            (task_file, (task_file >> 8) as u8)
+        };
+        if status & TFD_DF != 0 {
+            return FileSystemError::DriveFault;
+        }
+        decode_error_register(error)
+    }
+
+    // a port that hit an error stops processing commands until it is restarted
+    fn restart(&self) {
+        let registers = &self.registers;
+        registers.write(PORT_CMD, registers.read(PORT_CMD) & !CMD_ST);
+        let _ = registers.wait_clear(PORT_CMD, CMD_CR);
+        registers.write(PORT_SERR, 0xffff_ffff);
+        registers.write(PORT_IS, 0xffff_ffff);
+        registers.write(PORT_CMD, registers.read(PORT_CMD) | CMD_ST);
+    }
+
+    fn transfer(&self, write: bool, data: *mut u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        if lba + sectors as u64 > self.sectors {
+            return Err(FileSystemError::IndexOutOfBounds);
+        }
+        let command = match (self.lba48, write) {
+            (true, true) => ATA_WRITE_DMA_EXT,
+            (true, false) => ATA_READ_DMA_EXT,
+            (false, true) => ATA_WRITE_DMA,
+            (false, false) => ATA_READ_DMA,
+        };
+        let mut done = 0;
+        while done < sectors as usize {
+            let count = (sectors as usize - done).min(BOUNCE_SECTORS);
+            let chunk = unsafe { data.add(done * SECTOR_SIZE) };
+            self.command(command, write, lba + done as u64, count, chunk)?;
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AhciPort {
+    fn read(&self, target: *mut u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        self.transfer(false, target, lba, sectors)
+    }
+
+    fn write(&self, source: *const u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        self.transfer(true, source as *mut u8, lba, sectors)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+// find every ahci controller and return a device for each port with a sata disk attached
+pub fn probe() -> Vec<AhciPort> {
+    let mut disks = Vec::new();
+    let controllers = bus::enumerate().into_iter().filter(|d| {
+        d.class == CLASS_MASS_STORAGE && d.subclass == SUBCLASS_SATA && d.prog_if == PROG_IF_AHCI
+    });
+    for controller in controllers {
+        let Bar::Memory(abar) = controller.bar(ABAR_INDEX) else {
+            eprintln!("ahci controller without a memory bar, skipping it");
+            continue;
+        };
+        controller.enable();
+        let Some(hba) = map_mmio(PhysAddr::new(abar), ABAR_SIZE) else {
+            eprintln!("can't map ahci registers");
+            continue;
+        };
+        let hba_registers = PortRegisters { base: hba };
+        hba_registers.write(HBA_GHC, hba_registers.read(HBA_GHC) | GHC_AHCI_ENABLE);
+
+        // our dma memory may be above 4GiB only if the controller can reach it
+        let addressing_64bit = hba_registers.read(HBA_CAP) & CAP_64BIT != 0;
+
+        let implemented = hba_registers.read(HBA_PI);
+        for port in 0..32 {
+            if implemented & (1 << port) == 0 {
+                continue;
+            }
+            let registers = PortRegisters {
+                base: hba + (PORTS_OFFSET + port * PORT_SIZE) as u64,
+            };
+            let status = registers.read(PORT_SSTS);
+            let detected = status & 0xf == SSTS_DET_PRESENT && (status >> 8) & 0xf == SSTS_IPM_ACTIVE;
+            if !detected || registers.read(PORT_SIG) != SIG_ATA {
+                continue;
+            }
+            match AhciPort::init(registers, addressing_64bit) {
+                Ok(disk) => disks.push(disk),
+                Err(e) => eprintln!("ahci port {} init failed {:?}", port, e),
+            }
+        }
+    }
+    disks
+}
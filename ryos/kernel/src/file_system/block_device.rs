@@ -0,0 +1,106 @@
+//BLOCK DEVICES
+//Common interface of everything the file systems can live on (whole disks and partitions)
+use crate::file_system::disk_driver::{Disk, Drive};
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::partition::{self, Partition, PartitionKind};
+use crate::file_system::{ahci, virtio_blk};
+use crate::{eprintln, println};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+// sectors at the start of the master drive used before partition tables were supported
+const LEGACY_VOLUME_START: u64 = 100;
+
+pub trait BlockDevice: Send + Sync {
+    //read 'sectors' sectors starting at 'lba' into target
+    fn read(&self, target: *mut u8, lba: u64, sectors: u16) -> Result<(), FileSystemError>;
+    //write 'sectors' sectors from source starting at 'lba'
+    fn write(&self, source: *const u8, lba: u64, sectors: u16) -> Result<(), FileSystemError>;
+    //number of addressable sectors
+    fn sector_count(&self) -> u64;
+}
+
+pub struct DeviceEntry {
+    pub name: String,
+    pub device: Arc<dyn BlockDevice>,
+    // set for partitions, None for whole disks
+    pub partition: Option<(PartitionKind, u64)>,
+}
+
+pub static DEVICES: Mutex<Vec<DeviceEntry>> = Mutex::new(Vec::new());
+
+fn register(name: String, device: Arc<dyn BlockDevice>, partition: Option<(PartitionKind, u64)>) {
+    DEVICES.lock().push(DeviceEntry {
+        name,
+        device,
+        partition,
+    });
+}
+
+/*
+this function registers a whole disk and every partition found on it under 'name', 'name'p1, ...
+ */
+pub fn add_disk(name: &str, disk: Arc<dyn BlockDevice>) {
+    register(String::from(name), disk.clone(), None);
+    match partition::scan(&disk) {
+        Ok(partitions) => {
+            for part in partitions {
+                let entry_name = format!("{}p{}", name, part.number);
+                let info = Some((part.kind, part.start));
+                register(entry_name, Arc::new(part), info);
+            }
+        }
+        Err(e) => eprintln!("{}: can't read partition table {:?}", name, e),
+    }
+}
+
+// find all the ata, sata and virtio drives and their partitions, does nothing when already probed
+pub fn probe() {
+    if !DEVICES.lock().is_empty() {
+        return;
+    }
+    for (name, drive) in [("ata0", Drive::Master), ("ata1", Drive::Slave)] {
+        if let Ok(disk) = Disk::open(drive) {
+            add_disk(name, Arc::new(disk));
+        }
+    }
+    for (index, disk) in ahci::probe().into_iter().enumerate() {
+        add_disk(&format!("sd{}", (b'a' + index as u8) as char), Arc::new(disk));
+    }
+    for (index, disk) in virtio_blk::probe().into_iter().enumerate() {
+        add_disk(&format!("vd{}", (b'a' + index as u8) as char), Arc::new(disk));
+    }
+}
+
+/*
+this function returns the device the ryos file system should be mounted on:
+the first ryos partition of any disk, or the old fixed area of the master drive when there is none
+ */
+pub fn root_volume() -> Result<Arc<dyn BlockDevice>, FileSystemError> {
+    probe();
+    let devices = DEVICES.lock();
+    if let Some(entry) = devices
+        .iter()
+        .find(|d| matches!(d.partition, Some((PartitionKind::Ryos, _))))
+    {
+        println!("mounting ryos file system from {}", entry.name);
+        return Ok(entry.device.clone());
+    }
+
+    let master = devices
+        .iter()
+        .find(|d| d.name == "ata0")
+        .ok_or(FileSystemError::DiskNotAvailable)?;
+    println!("no ryos partition found, using the legacy area of ata0");
+    let length = master.device.sector_count().saturating_sub(LEGACY_VOLUME_START);
+    Ok(Arc::new(Partition::new(
+        master.device.clone(),
+        0,
+        PartitionKind::Ryos,
+        LEGACY_VOLUME_START,
+        length,
+    )))
+}
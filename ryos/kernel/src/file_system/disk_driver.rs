@@ -2,13 +2,18 @@
 //Driver for ATA disk supporting PIO MODE
 use core::arch::asm;
 use spin::Mutex;
+use crate::file_system::block_device::BlockDevice;
 use crate::file_system::errors::FileSystemError;
 pub const SECTOR_SIZE: usize = 512;
 //Warning! Mutable static here
-pub static mut DISK: Mutex<Disk> = Mutex::new(Disk { enabled: false });
+pub static mut DISK: Mutex<Disk> = Mutex::new(Disk { enabled: false, drive: Drive::Master, sectors: 0 });
+
+//master and slave share the same registers, only one command can run on the bus at a time
+static ATA_BUS: Mutex<()> = Mutex::new(());
 
 //controller registers ports
 const DATA_REGISTER: u16 = 0x1f0;
+const ERROR_REGISTER: u16 = 0x1f1;
 const SECTOR_COUNT_REGISTER: u16 = 0x1f2;
 const LBA_LOW_REGISTER: u16 = 0x1f3;
 const LBA_MID_REGISTER: u16 = 0x1f4;
@@ -18,58 +23,187 @@ const DRIVE_REGISTER: u16 = 0x1f6;
 //port used for both sending command and getting status
 const STATUS_COMMAND_REGISTER: u16 = 0x1f7;
 
+//device control register (write) / alternate status register (read)
+const CONTROL_REGISTER: u16 = 0x3f6;
+
 //read write command codes
 const READ_COMMAND: u8 = 0x20;
 const WRITE_COMMAND: u8 = 0x30;
+const IDENTIFY_COMMAND: u8 = 0xEC;
 
 //status register bits
 const STATUS_BSY: u8 = 0b10000000;
 const STATUS_RDY: u8 = 0b01000000;
-//const STATUS_DFE: u8 = 0b00100000;
-//const STATUS_DRQ: u8 = 0b00001000;
-//const STATUS_ERR: u8 = 0b00000001;
+const STATUS_DFE: u8 = 0b00100000;
+const STATUS_DRQ: u8 = 0b00001000;
+const STATUS_ERR: u8 = 0b00000001;
+
+//error register bits (valid only when STATUS_ERR is set)
+const ERROR_AMNF: u8 = 0b00000001; // address mark not found
+const ERROR_TKZNF: u8 = 0b00000010; // track zero not found
+const ERROR_ABRT: u8 = 0b00000100; // command aborted
+const ERROR_MCR: u8 = 0b00001000; // media change request
+const ERROR_IDNF: u8 = 0b00010000; // id (sector) not found
+const ERROR_MC: u8 = 0b00100000; // media changed
+const ERROR_UNC: u8 = 0b01000000; // uncorrectable data error
+const ERROR_BBK: u8 = 0b10000000; // bad block detected
+
+//control register bits
+const CONTROL_NIEN: u8 = 0b00000010; // disable ata interrupt
+const CONTROL_SRST: u8 = 0b00000100; // software reset
+
+//how many times the status register is polled before giving up
+const TIMEOUT_POLLS: u32 = 1_000_000;
+//how many times a failed transfer is retried (with a reset in between)
+const MAX_RETRIES: u32 = 3;
+
+//lba28 commands can't address more sectors than this
+const MAX_LBA28_SECTORS: u64 = 1 << 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drive {
+    Master,
+    Slave,
+}
 
+impl Drive {
+    //value of the drive select bit in the drive register
+    fn select_bit(self) -> u8 {
+        match self {
+            Drive::Master => 0,
+            Drive::Slave => 0b00010000,
+        }
+    }
+}
 
 pub struct Disk {
     pub enabled: bool,
+    drive: Drive,
+    sectors: u64,
 }
 
 impl Disk {
     pub fn new() -> Self {
-        let mut disk = Disk { enabled: false };
-        disk.check().expect("Error init disk");
-        disk
+        Self::open(Drive::Master).expect("Error init disk")
     }
-    //read multiple sectors from lba to specified target
+
+    //identify 'drive' on the primary bus, fails if there is no ata disk there
+    pub fn open(drive: Drive) -> Result<Self, FileSystemError> {
+        let mut disk = Disk { enabled: false, drive, sectors: 0 };
+        disk.check()?;
+        disk.sectors = disk.identify()?;
+        Ok(disk)
+    }
+
+    //read multiple sectors from lba to specified target, retrying after a reset on failure
     pub fn read<T>(&self, target: *mut T, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
-        let lba = lba + 100;
+        self.with_retries(|| self.read_once(target, lba, sectors))
+    }
+
+    //write multiple sectors from source to lba, retrying after a reset on failure
+    pub fn write<T>(&self, source: *const T, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        self.with_retries(|| self.write_once(source, lba, sectors))
+    }
+
+    //send identify and return the number of sectors reported by the drive
+    fn identify(&self) -> Result<u64, FileSystemError> {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let _bus = ATA_BUS.lock();
+            unsafe {
+                asm!("out dx, al", in("dx") CONTROL_REGISTER, in("al") CONTROL_NIEN);
+                asm!("out dx, al", in("dx") DRIVE_REGISTER, in("al") 0xA0 | self.drive.select_bit());
+                asm!("out dx, al", in("dx") SECTOR_COUNT_REGISTER, in("al") 0u8);
+                asm!("out dx, al", in("dx") LBA_LOW_REGISTER, in("al") 0u8);
+                asm!("out dx, al", in("dx") LBA_MID_REGISTER, in("al") 0u8);
+                asm!("out dx, al", in("dx") LBA_HIGH_REGISTER, in("al") 0u8);
+                asm!("out dx, al", in("dx") STATUS_COMMAND_REGISTER, in("al") IDENTIFY_COMMAND);
+            }
+
+            //no drive on this position
+            if self.status() == 0 {
+                return Err(FileSystemError::DiskNotAvailable);
+            }
+            self.wait_not_busy()?;
+
+            //atapi and sata devices report a signature in the lba registers
+            let (mid, high): (u8, u8);
+            unsafe {
+                asm!("in al, dx", out("al") mid, in("dx") LBA_MID_REGISTER);
+                asm!("in al, dx", out("al") high, in("dx") LBA_HIGH_REGISTER);
+            }
+            if mid != 0 || high != 0 {
+                return Err(FileSystemError::DiskNotAvailable);
+            }
+            self.wait_data_request()?;
+
+            let mut identity = [0u16; SECTOR_SIZE / 2];
+            for word in identity.iter_mut() {
+                unsafe {
+                    asm!("in ax, dx", out("ax") *word, in("dx") DATA_REGISTER);
+                }
+            }
+
+            //words 60-61 hold the number of lba28 addressable sectors
+            let sectors = (identity[61] as u64) << 16 | identity[60] as u64;
+            Ok(sectors.min(MAX_LBA28_SECTORS))
+        })
+    }
+
+    fn with_retries<F>(&self, mut transfer: F) -> Result<(), FileSystemError>
+    where
+        F: FnMut() -> Result<(), FileSystemError>,
+    {
         if !self.enabled {
             return Err(FileSystemError::DiskNotAvailable);
         }
 
+        //transfers run in task context, so the bus is held with interrupts enabled. a failing drive
+        //can take seconds of timeouts and resets, which would otherwise cost timer ticks and keystrokes
+        let _bus = ATA_BUS.lock();
+        self.retry_loop(&mut transfer)
+    }
+
+    fn retry_loop<F>(&self, transfer: &mut F) -> Result<(), FileSystemError>
+    where
+        F: FnMut() -> Result<(), FileSystemError>,
+    {
+        let mut attempt = 0;
+        loop {
+            match transfer() {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt + 1 >= MAX_RETRIES || !e.is_retryable() => {
+                    //leave the drive in a usable state for the next command
+                    let _ = self.reset();
+                    return Err(e);
+                }
+                Err(_) => {
+                    attempt += 1;
+                    self.reset()?;
+                }
+            }
+        }
+    }
+
+    fn read_once<T>(&self, target: *mut T, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
         //wait until not busy
-        while self.is_busy() {}
+        self.wait_not_busy()?;
 
         self.send_command(lba, sectors, true);
 
         let mut sectors_left = sectors;
         let mut target_pointer = target;
         while sectors_left > 0 {
+            //wait until the drive has a sector ready for us
+            self.wait_data_request()?;
+
             //a sector is 512 byte, buffer size is 4 byte, so loop for 512/4
             for _i in 0..SECTOR_SIZE / 4 {
-                //wait until not busy
-                while self.is_busy() {}
-
-                //wait until ready
-                while !self.is_ready() {}
-
                 let buffer: u32;
                 unsafe {
-                    //read 16 bit from controller buffer
+                    //read 32 bit from controller buffer
                     asm!("in eax, dx", out("eax") buffer, in("dx") DATA_REGISTER);
 
                     //copy buffer in memory pointed by target
-                    //*(target_pointer as *mut u32) = buffer;
                     core::ptr::write_unaligned(target_pointer as *mut u32, buffer);
 
                     target_pointer = target_pointer.byte_add(4);
@@ -78,28 +212,20 @@ impl Disk {
             sectors_left -= 1;
         }
 
-        self.reset();
-        Ok(())
+        self.reset()
     }
-    pub fn write<T>(&self, source: *const T, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
-        let lba = lba + 100;
-        if !self.enabled {
-            return  Err(FileSystemError::DiskNotAvailable)
-        }
 
+    fn write_once<T>(&self, source: *const T, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
         //wait until not busy
-        while self.is_busy() {}
+        self.wait_not_busy()?;
 
         self.send_command(lba, sectors, false);
 
         let mut sectors_left = sectors;
         let mut source_pointer = source;
         while sectors_left > 0 {
-            //wait until not busy
-            while self.is_busy() {}
-
-            //wait until ready
-            while !self.is_ready() {}
+            //wait until the drive is ready to accept a sector
+            self.wait_data_request()?;
 
             //a sector is 512 bytes, buffer size is 4 bytes, so loop for 512/4
             for _i in 0..SECTOR_SIZE / 4 {
@@ -116,21 +242,24 @@ impl Disk {
             sectors_left -= 1;
         }
 
-        self.reset();
-        Ok(())
+        //the last sector is committed once the drive is no longer busy
+        self.wait_not_busy()?;
+        self.check_error()?;
+
+        self.reset()
     }
 
     fn send_command(&self, lba: u64, sectors: u16, read: bool) {
         unsafe {
             //disable ata interrupt
-            asm!("out dx, al", in("dx") 0x3f6, in("al") 0b00000010u8);
+            asm!("out dx, al", in("dx") CONTROL_REGISTER, in("al") CONTROL_NIEN);
 
             //setup registers
             asm!("out dx, al", in("dx") SECTOR_COUNT_REGISTER, in("al") sectors as u8); //number of sectors to write
             asm!("out dx, al", in("dx") LBA_LOW_REGISTER, in("al") lba as u8); //low 8 bits of lba
             asm!("out dx, al", in("dx") LBA_MID_REGISTER, in("al") (lba >> 8) as u8); //next 8 bits of lba
             asm!("out dx, al", in("dx") LBA_HIGH_REGISTER, in("al") (lba >> 16) as u8); //next 8 bits of lba
-            asm!("out dx, al", in("dx") DRIVE_REGISTER, in("al") (0xE0 | ((lba >> 24) & 0xF)) as u8); //0xe0 (master drive) ORed with highest 4 bits of lba
+            asm!("out dx, al", in("dx") DRIVE_REGISTER, in("al") 0xE0 | self.drive.select_bit() | ((lba >> 24) & 0xF) as u8); //0xe0 (lba mode) ORed with the drive bit and highest 4 bits of lba
 
             //send write command to port
             if read {
@@ -142,34 +271,99 @@ impl Disk {
             }
         }
     }
-    //check if disk is busy
-    pub fn is_busy(&self) -> bool {
+
+    fn status(&self) -> u8 {
         let status: u8;
         unsafe {
             asm!("in al, dx", out("al") status, in("dx") STATUS_COMMAND_REGISTER);
         }
+        status
+    }
+
+    //reading the alternate status doesn't acknowledge a pending interrupt
+    fn alternate_status(&self) -> u8 {
+        let status: u8;
+        unsafe {
+            asm!("in al, dx", out("al") status, in("dx") CONTROL_REGISTER);
+        }
+        status
+    }
 
+    fn error(&self) -> u8 {
+        let error: u8;
+        unsafe {
+            asm!("in al, dx", out("al") error, in("dx") ERROR_REGISTER);
+        }
+        error
+    }
+
+    //check if disk is busy
+    pub fn is_busy(&self) -> bool {
         //if bsy bit is not 0 return true
-        (status & STATUS_BSY) != 0
+        (self.status() & STATUS_BSY) != 0
     }
 
     //check if disk is ready
     pub fn is_ready(&self) -> bool {
-        let status: u8;
-        unsafe {
-            asm!("in al, dx", out("al") status, in("dx") STATUS_COMMAND_REGISTER);
+        //if rdy bit is not 0 return true
+        (self.status() & STATUS_RDY) != 0
+    }
+
+    //wait until the bsy bit clears, giving up after TIMEOUT_POLLS reads
+    fn wait_not_busy(&self) -> Result<(), FileSystemError> {
+        for _ in 0..TIMEOUT_POLLS {
+            if !self.is_busy() {
+                return Ok(());
+            }
+            core::hint::spin_loop();
         }
+        Err(FileSystemError::DiskTimeout)
+    }
 
-        //if rdy bit is not 0 return true
-        (status & STATUS_RDY) != 0
+    //wait until the drive requests data (drq), failing early on err/df
+    fn wait_data_request(&self) -> Result<(), FileSystemError> {
+        for _ in 0..TIMEOUT_POLLS {
+            let status = self.status();
+            if status & STATUS_BSY == 0 {
+                if status & (STATUS_ERR | STATUS_DFE) != 0 {
+                    return Err(self.decode_error(status));
+                }
+                if status & STATUS_DRQ != 0 {
+                    return Ok(());
+                }
+            }
+            core::hint::spin_loop();
+        }
+        Err(FileSystemError::DiskTimeout)
+    }
+
+    fn check_error(&self) -> Result<(), FileSystemError> {
+        let status = self.status();
+        if status & (STATUS_ERR | STATUS_DFE) != 0 {
+            return Err(self.decode_error(status));
+        }
+        Ok(())
+    }
+
+    //translate the status and error registers into a specific error
+    fn decode_error(&self, status: u8) -> FileSystemError {
+        if status & STATUS_DFE != 0 {
+            return FileSystemError::DriveFault;
+        }
+
+        decode_error_register(self.error())
     }
 
     //check if ata drive is working
     pub fn check(&mut self) -> Result<(), FileSystemError> {
-        let status: u8;
         unsafe {
-            asm!("in al, dx", out("al") status, in("dx") STATUS_COMMAND_REGISTER);
+            asm!("out dx, al", in("dx") DRIVE_REGISTER, in("al") 0xA0 | self.drive.select_bit());
+        }
+        //give the drive 400ns to answer on the bus
+        for _ in 0..4 {
+            self.alternate_status();
         }
+        let status = self.status();
 
         if status != 0 && status != 0xff {
             self.enabled = true;
@@ -180,12 +374,61 @@ impl Disk {
         }
     }
 
-    pub fn reset(&self) {
+    //software reset of the ata bus, waits for the drive to come back
+    pub fn reset(&self) -> Result<(), FileSystemError> {
         unsafe {
-            asm!("out dx, al", in("dx") 0x3f6, in("al") 0b00000110u8);
-            asm!("out dx, al", in("dx") 0x3f6, in("al") 0b00000010u8);
+            asm!("out dx, al", in("dx") CONTROL_REGISTER, in("al") CONTROL_SRST | CONTROL_NIEN);
+        }
+
+        //srst must stay asserted for at least 5us, each alternate status read takes ~100ns
+        for _ in 0..50 {
+            self.alternate_status();
         }
+
+        unsafe {
+            asm!("out dx, al", in("dx") CONTROL_REGISTER, in("al") CONTROL_NIEN);
+        }
+
+        //give the drive 400ns before bsy is valid
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+
+        self.wait_not_busy()
     }
 }
 
+impl BlockDevice for Disk {
+    fn read(&self, target: *mut u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        Disk::read(self, target, lba, sectors)
+    }
+
+    fn write(&self, source: *const u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        Disk::write(self, source, lba, sectors)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
 
+//translate the ata error register into a specific error, shared by every ata transport
+pub fn decode_error_register(error: u8) -> FileSystemError {
+    if error & ERROR_BBK != 0 {
+        FileSystemError::BadSector
+    } else if error & ERROR_UNC != 0 {
+        FileSystemError::UncorrectableData
+    } else if error & ERROR_IDNF != 0 {
+        FileSystemError::SectorNotFound
+    } else if error & ERROR_AMNF != 0 {
+        FileSystemError::AddressMarkNotFound
+    } else if error & ERROR_TKZNF != 0 {
+        FileSystemError::TrackZeroNotFound
+    } else if error & (ERROR_MC | ERROR_MCR) != 0 {
+        FileSystemError::MediaChanged
+    } else if error & ERROR_ABRT != 0 {
+        FileSystemError::CommandAborted
+    } else {
+        FileSystemError::DriveFault
+    }
+}
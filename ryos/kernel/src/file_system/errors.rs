@@ -14,4 +14,48 @@ pub enum FileSystemError {
     DirAlreadyExists,
     NotAFile,
     NotADirectory,
+    DiskTimeout,
+    DriveFault,
+    CommandAborted,
+    SectorNotFound,
+    UncorrectableData,
+    AddressMarkNotFound,
+    TrackZeroNotFound,
+    MediaChanged,
+    InvalidPartitionTable,
+    InvalidFileSystem,
+    UnsupportedFeature,
+    // a blocking read or write gave up because a signal arrived
+    Interrupted,
+    // a write to a pipe nobody reads anymore
+    BrokenPipe,
+    // the file is too big to be read at once
+    FileTooLarge,
+}
+
+impl FileSystemError {
+    // errors that mean the sector itself can't be trusted anymore
+    pub fn is_media_error(&self) -> bool {
+        matches!(
+            self,
+            FileSystemError::BadSector
+                | FileSystemError::UncorrectableData
+                | FileSystemError::SectorNotFound
+                | FileSystemError::AddressMarkNotFound
+        )
+    }
+
+    // errors that may go away after resetting the drive
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            FileSystemError::DiskTimeout
+                | FileSystemError::DriveFault
+                | FileSystemError::CommandAborted
+                | FileSystemError::UncorrectableData
+                | FileSystemError::AddressMarkNotFound
+                | FileSystemError::TrackZeroNotFound
+                | FileSystemError::MediaChanged
+        )
+    }
 }
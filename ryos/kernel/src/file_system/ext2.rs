@@ -0,0 +1,342 @@
+//EXT2
+//Read only driver for ext2 file systems, as created by mke2fs on linux
+use crate::file_system::block_device::BlockDevice;
+use crate::file_system::disk_driver::SECTOR_SIZE;
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::partition::{read_u16, read_u32};
+use crate::memory::mmap::MappedFile;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xEF53;
+const ROOT_INODE: u32 = 2;
+const GOOD_OLD_REV: u32 = 0;
+const GOOD_OLD_INODE_SIZE: u16 = 128;
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+// incompatible features we can still read: typed directory entries and flexible group layout
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+// inode block pointers
+const DIRECT_BLOCKS: u32 = 12;
+const SINGLE_INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_REGULAR: u16 = 0x8000;
+const MODE_SYMLINK: u16 = 0xA000;
+
+// read_file reads the whole file into the kernel heap, bigger files have to be read in parts with read_at
+pub const MAX_WHOLE_FILE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+impl FileKind {
+    fn from_mode(mode: u16) -> Self {
+        match mode & MODE_TYPE_MASK {
+            MODE_REGULAR => FileKind::File,
+            MODE_DIRECTORY => FileKind::Directory,
+            MODE_SYMLINK => FileKind::Symlink,
+            _ => FileKind::Other,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Inode {
+    pub kind: FileKind,
+    pub size: u64,
+    blocks: [u32; 15],
+}
+
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u32,
+}
+
+pub struct Ext2 {
+    device: Arc<dyn BlockDevice>,
+    block_size: u32,
+    inodes_per_group: u32,
+    inode_size: u32,
+    // first block of the inode table of every block group
+    inode_tables: Vec<u32>,
+}
+
+impl Ext2 {
+    /*
+    this function reads the superblock and the group descriptors, it fails if 'device' doesn't hold
+    an ext2 file system or uses features this driver can't read
+     */
+    pub fn mount(device: Arc<dyn BlockDevice>) -> Result<Self, FileSystemError> {
+        let mut superblock = [0u8; SUPERBLOCK_SIZE];
+        device.read(
+            superblock.as_mut_ptr(),
+            SUPERBLOCK_OFFSET / SECTOR_SIZE as u64,
+            (SUPERBLOCK_SIZE / SECTOR_SIZE) as u16,
+        )?;
+        if read_u16(&superblock, 56) != EXT2_MAGIC {
+            return Err(FileSystemError::InvalidFileSystem);
+        }
+
+        let revision = read_u32(&superblock, 76);
+        if revision != GOOD_OLD_REV && read_u32(&superblock, 96) & !SUPPORTED_INCOMPAT != 0 {
+            return Err(FileSystemError::UnsupportedFeature);
+        }
+        let inode_size = if revision == GOOD_OLD_REV {
+            GOOD_OLD_INODE_SIZE
+        } else {
+            read_u16(&superblock, 88)
+        } as u32;
+
+        let blocks_count = read_u32(&superblock, 4);
+        let first_data_block = read_u32(&superblock, 20);
+        let log_block_size = read_u32(&superblock, 24);
+        let blocks_per_group = read_u32(&superblock, 32);
+        let inodes_per_group = read_u32(&superblock, 40);
+        if log_block_size > 2
+            || blocks_per_group == 0
+            || inodes_per_group == 0
+            || inode_size == 0
+            || first_data_block >= blocks_count
+        {
+            return Err(FileSystemError::InvalidFileSystem);
+        }
+
+        let mut fs = Ext2 {
+            device,
+            block_size: 1024 << log_block_size,
+            inodes_per_group,
+            inode_size,
+            inode_tables: Vec::new(),
+        };
+
+        // the group descriptor table starts in the block after the superblock
+        let groups = (blocks_count - first_data_block).div_ceil(blocks_per_group) as usize;
+        let table_blocks = (groups * GROUP_DESCRIPTOR_SIZE).div_ceil(fs.block_size as usize);
+        let mut table = vec![0u8; table_blocks * fs.block_size as usize];
+        for i in 0..table_blocks {
+            let start = i * fs.block_size as usize;
+            fs.read_block(first_data_block + 1 + i as u32, &mut table[start..])?;
+        }
+        fs.inode_tables = (0..groups)
+            .map(|group| read_u32(&table, group * GROUP_DESCRIPTOR_SIZE + 8))
+            .collect();
+
+        if fs.read_inode(ROOT_INODE)?.kind != FileKind::Directory {
+            return Err(FileSystemError::InvalidFileSystem);
+        }
+        Ok(fs)
+    }
+
+    fn sectors_per_block(&self) -> u64 {
+        self.block_size as u64 / SECTOR_SIZE as u64
+    }
+
+    fn read_block(&self, block: u32, target: &mut [u8]) -> Result<(), FileSystemError> {
+        if target.len() < self.block_size as usize {
+            return Err(FileSystemError::IndexOutOfBounds);
+        }
+        let spb = self.sectors_per_block();
+        self.device.read(target.as_mut_ptr(), block as u64 * spb, spb as u16)
+    }
+
+    // read only the sector holding byte 'offset' of 'block'
+    fn read_block_sector(&self, block: u32, offset: u32) -> Result<[u8; SECTOR_SIZE], FileSystemError> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let lba = block as u64 * self.sectors_per_block() + (offset as usize / SECTOR_SIZE) as u64;
+        self.device.read(sector.as_mut_ptr(), lba, 1)?;
+        Ok(sector)
+    }
+
+    pub fn read_inode(&self, number: u32) -> Result<Inode, FileSystemError> {
+        if number == 0 || ((number - 1) / self.inodes_per_group) as usize >= self.inode_tables.len() {
+            return Err(FileSystemError::IndexOutOfBounds);
+        }
+        let group = ((number - 1) / self.inodes_per_group) as usize;
+        let offset = (number - 1) % self.inodes_per_group * self.inode_size;
+        let block = self.inode_tables[group] + offset / self.block_size;
+        let sector = self.read_block_sector(block, offset % self.block_size)?;
+        let raw = &sector[offset as usize % SECTOR_SIZE..];
+
+        let kind = FileKind::from_mode(read_u16(raw, 0));
+        let mut size = read_u32(raw, 4) as u64;
+        // large files keep the upper half of the size where directories keep their acl
+        if kind == FileKind::File {
+            size |= (read_u32(raw, 108) as u64) << 32;
+        }
+        let mut blocks = [0u32; 15];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = read_u32(raw, 40 + i * 4);
+        }
+        Ok(Inode { kind, size, blocks })
+    }
+
+    /*
+    this function translates the 'index'th block of a file to a block on the disk, following the
+    indirect blocks. 0 means a hole in a sparse file
+     */
+    fn block_number(&self, inode: &Inode, index: u32) -> Result<u32, FileSystemError> {
+        let per_block = self.block_size / 4;
+        if index < DIRECT_BLOCKS {
+            return Ok(inode.blocks[index as usize]);
+        }
+        let index = index - DIRECT_BLOCKS;
+        if index < per_block {
+            return self.follow(inode.blocks[SINGLE_INDIRECT], &[index]);
+        }
+        let index = index - per_block;
+        if index < per_block * per_block {
+            return self.follow(inode.blocks[DOUBLE_INDIRECT], &[index / per_block, index % per_block]);
+        }
+        let index = index - per_block * per_block;
+        self.follow(
+            inode.blocks[TRIPLE_INDIRECT],
+            &[index / (per_block * per_block), index / per_block % per_block, index % per_block],
+        )
+    }
+
+    fn follow(&self, mut block: u32, path: &[u32]) -> Result<u32, FileSystemError> {
+        for &entry in path {
+            if block == 0 {
+                return Ok(0);
+            }
+            let offset = entry * 4;
+            let sector = self.read_block_sector(block, offset)?;
+            block = read_u32(&sector, offset as usize % SECTOR_SIZE);
+        }
+        Ok(block)
+    }
+
+    /*
+    this function reads up to 'len' bytes of an inode starting at byte 'offset', only the blocks in
+    that range are read from the disk. holes are read as zeros and the result is shorter at the end
+    of the file
+     */
+    pub fn read_at(&self, inode: &Inode, offset: u64, len: usize) -> Result<Vec<u8>, FileSystemError> {
+        let end = inode.size.min(offset.saturating_add(len as u64));
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let block_size = self.block_size as u64;
+        let mut data = Vec::with_capacity((end - offset) as usize);
+        let mut block_data = vec![0u8; block_size as usize];
+        let mut position = offset;
+        while position < end {
+            let within = position % block_size;
+            let count = (block_size - within).min(end - position) as usize;
+            match self.block_number(inode, (position / block_size) as u32)? {
+                0 => data.resize(data.len() + count, 0),
+                block => {
+                    self.read_block(block, &mut block_data)?;
+                    data.extend_from_slice(&block_data[within as usize..within as usize + count]);
+                }
+            }
+            position += count as u64;
+        }
+        Ok(data)
+    }
+
+    // read the whole content of an inode, for directories and small files
+    pub fn read_data(&self, inode: &Inode) -> Result<Vec<u8>, FileSystemError> {
+        self.read_at(inode, 0, inode.size as usize)
+    }
+
+    pub fn read_dir(&self, inode: &Inode) -> Result<Vec<DirEntry>, FileSystemError> {
+        if inode.kind != FileKind::Directory {
+            return Err(FileSystemError::NotADirectory);
+        }
+        let data = self.read_data(inode)?;
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let number = read_u32(&data, offset);
+            let record_length = read_u16(&data, offset + 4) as usize;
+            let name_length = data[offset + 6] as usize;
+            if record_length < 8 || offset + 8 + name_length > data.len() {
+                return Err(FileSystemError::InvalidDirectory);
+            }
+            if number != 0 {
+                let name = &data[offset + 8..offset + 8 + name_length];
+                entries.push(DirEntry {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    inode: number,
+                });
+            }
+            offset += record_length;
+        }
+        Ok(entries)
+    }
+
+    // walk 'path' (relative to the root of this file system) down from the root directory
+    pub fn lookup(&self, path: &str) -> Result<Inode, FileSystemError> {
+        let mut inode = self.read_inode(ROOT_INODE)?;
+        let mut parts = path.split('/').filter(|s| !s.is_empty()).peekable();
+        while let Some(name) = parts.next() {
+            let entry = self
+                .read_dir(&inode)
+                .map_err(|_| FileSystemError::DirectoryNotFound)?
+                .into_iter()
+                .find(|entry| entry.name == name);
+            match entry {
+                Some(entry) => inode = self.read_inode(entry.inode)?,
+                None if parts.peek().is_some() => return Err(FileSystemError::DirectoryNotFound),
+                None => return Err(FileSystemError::FileNotFound),
+            }
+        }
+        Ok(inode)
+    }
+
+    // the inode of the regular file at 'path', to read it in parts with read_at
+    pub fn open_file(&self, path: &str) -> Result<Inode, FileSystemError> {
+        let inode = self.lookup(path)?;
+        if inode.kind != FileKind::File {
+            return Err(FileSystemError::NotAFile);
+        }
+        Ok(inode)
+    }
+
+    // the whole file at 'path', used for program images. files above MAX_WHOLE_FILE are refused
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, FileSystemError> {
+        let inode = self.open_file(path)?;
+        if inode.size > MAX_WHOLE_FILE {
+            return Err(FileSystemError::FileTooLarge);
+        }
+        self.read_data(&inode)
+    }
+
+    // the sectors of the file at 'path' for mapping it into memory, read only like the whole driver
+    pub fn map_file(&self, path: &str) -> Result<MappedFile, FileSystemError> {
+        let inode = self.open_file(path)?;
+        let spb = self.sectors_per_block();
+        let mut sectors = Vec::new();
+        for index in 0..inode.size.div_ceil(self.block_size as u64) {
+            let block = self.block_number(&inode, index as u32)? as u64;
+            sectors.extend((0..spb).map(|i| (block != 0).then_some(block * spb + i)));
+        }
+        Ok(MappedFile::new(self.device.clone(), sectors, inode.size, false))
+    }
+
+    pub fn list(&self, path: &str) -> Result<Vec<(String, FileKind)>, FileSystemError> {
+        let directory = self.lookup(path)?;
+        let mut listing = Vec::new();
+        for entry in self.read_dir(&directory)? {
+            listing.push((entry.name, self.read_inode(entry.inode)?.kind));
+        }
+        Ok(listing)
+    }
+}
@@ -1,13 +1,17 @@
-use crate::file_system::disk_driver::{Disk, SECTOR_SIZE};
+use crate::file_system::block_device::{self, BlockDevice};
+use crate::file_system::disk_driver::SECTOR_SIZE;
 use crate::file_system::errors::FileSystemError;
+use crate::memory::mmap::MappedFile;
 use crate::file_system::errors::FileSystemError::{
     BadSector, DirAlreadyExists, DirectoryNotFound, FileAlreadyExists, FileNotFound,
     IndexOutOfBounds, OutOfSpace, UnusedSector,
 };
-use crate::terminal::interface::{OUTPUT_COLOR, WORKING_DIR};
+use crate::terminal::interface::OUTPUT_COLOR;
 use crate::terminal::output::framebuffer::{Color, DEFAULT_COLOR};
 use crate::{change_writer_color, eprintln, print, println};
 use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::ops::ControlFlow::Break;
 use spin::Mutex;
@@ -36,6 +40,14 @@ impl FATEntry {
         FATEntry(Self::TYPE_EOF)
     }
 
+    fn new_bad(sector: u16) -> Result<Self, FileSystemError> {
+        // Keep the sector number so the bad sector is never handed out again
+        if sector > Self::SECTOR_MASK {
+            return Err(BadSector);
+        }
+        Ok(FATEntry(Self::TYPE_BAD | sector))
+    }
+
     fn new_used(sector: u16) -> Result<Self, FileSystemError> {
         // Ensure next_sector fits in 12 bits
         if sector > Self::SECTOR_MASK {
@@ -52,6 +64,8 @@ impl FATEntry {
     fn get_sector(&self) -> Result<u16, FileSystemError> {
         if self.is_used() {
             Ok(self.0 & Self::SECTOR_MASK)
+        } else if self.is_bad() {
+            Err(BadSector)
         } else {
             Err(UnusedSector)
         }
@@ -96,7 +110,7 @@ impl FAT {
     fn is_valid(&self) -> bool {
         self.entries[0].as_bin() == Self::MAGIC_NUMBER
     }
-    fn load_or_create(disk_manager: &Disk) -> FAT {
+    fn load_or_create(disk_manager: &dyn BlockDevice) -> FAT {
         match FAT::load(disk_manager, None) {
             Ok(fat) if fat.is_valid() => {
                 println!("FAT loaded successfully and is valid.");
@@ -121,7 +135,7 @@ impl FAT {
      sector - when None the current Fat is the main so save it on const place on the disk,
      when some it's the sector where should the Fat saved on
      */
-    fn save(&self, disk_manager: &Disk, sector: Option<u16>) -> Result<(), FileSystemError> {
+    fn save(&self, disk_manager: &dyn BlockDevice, sector: Option<u16>) -> Result<(), FileSystemError> {
         if sector.is_none() {
             return disk_manager.write(
                 self as *const FAT as *const u8,
@@ -132,7 +146,7 @@ impl FAT {
         disk_manager.write(self as *const FAT as *const u8, sector.unwrap() as u64, 1)
     }
 
-    fn load(disk_manager: &Disk, sector: Option<u16>) -> Result<FAT, FileSystemError> {
+    fn load(disk_manager: &dyn BlockDevice, sector: Option<u16>) -> Result<FAT, FileSystemError> {
         let mut buffer: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
         if sector.is_none() {
             return match disk_manager.read(buffer.as_mut_ptr(), FIRST_USABLE_SECTOR as u64 - 1, 1) {
@@ -174,26 +188,46 @@ impl FAT {
     }
 }
 
+// the ryos volume, the shell and the system calls share it
+static VOLUME: Mutex<Option<FAtApi>> = Mutex::new(None);
+
+pub fn set_volume(fs: FAtApi) {
+    *VOLUME.lock() = Some(fs);
+}
+
+/*
+this function runs 'f' on the ryos volume. interrupts stay enabled during the disk access, the volume
+is never used by interrupt handlers so a task spinning on the lock only waits until the holder ran
+ */
+pub fn with_volume<R>(f: impl FnOnce(&mut FAtApi) -> Result<R, FileSystemError>) -> Result<R, FileSystemError> {
+    f(VOLUME.lock().as_mut().ok_or(FileSystemError::DiskNotAvailable)?)
+}
+
 pub struct FAtApi {
     table: FAT,
-    disk_manager: Disk,
+    disk_manager: Arc<dyn BlockDevice>,
     directory: Directory,
     allocator: SectorAllocator,
 }
 
 impl FAtApi {
     pub fn new() -> Self {
-        let disk = Disk::new();
+        let volume = block_device::root_volume().expect("Error init disk");
+        Self::mount(volume)
+    }
+
+    // load the file system stored on 'disk', creating an empty one if there is none yet
+    pub fn mount(disk: Arc<dyn BlockDevice>) -> Self {
         FAtApi {
-            table: FAT::load_or_create(&disk),
-            directory: Directory::load_or_create_dir(&disk),
-            allocator: SectorAllocator::load_or_create(&disk),
+            table: FAT::load_or_create(&*disk),
+            directory: Directory::load_or_create_dir(&*disk),
+            allocator: SectorAllocator::load_or_create(&*disk),
             disk_manager: disk,
         }
     }
 
     pub fn save(&self) -> Result<(), FileSystemError> {
-        self.allocator.save(&self.disk_manager)
+        self.allocator.save(&*self.disk_manager)
     }
 
     pub fn add_entry(&mut self, entry: FATEntry) -> Result<(), FileSystemError> {
@@ -207,41 +241,110 @@ impl FAtApi {
         Ok(self.table.entries[entry_index])
     }
 
-    pub fn get_data(&self, file_name: &str) -> Result<[u8; SECTOR_SIZE], FileSystemError> {
+    // the file operations take the absolute path of the directory they work in, like "/" or "/docs/"
+    pub fn get_data(&self, dir: &str, file_name: &str) -> Result<[u8; SECTOR_SIZE], FileSystemError> {
         let mut buffer: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
-        let dir = self.get_current_directory()?;
+        let dir = self.open_directory(dir)?;
         let fat = self.get_current_fat(&dir.0)?;
         let entry = dir.0.get_entry(file_name)?;
 
-        if entry.entry_type == DIR_ENTRY_TYPE {
+        if entry.entry_type != FILE_ENTRY_TYPE {
             return Err(FileSystemError::NotAFile);
         }
-        self.disk_manager.read(
-            buffer.as_mut_ptr(),
-            fat.entries[entry.first_cluster as usize].get_sector()? as u64,
-            1,
-        )?;
+        let sector = fat.entries[entry.first_cluster as usize].get_sector()?;
+        // a sector that fails to read keeps its entry, writing the file moves it to a new sector
+        self.disk_manager.read(buffer.as_mut_ptr(), sector as u64, 1)?;
         Ok(buffer)
     }
 
+    // the sectors of 'file_name' for mapping it into memory, every file is a single sector
+    pub fn map_file(&self, dir: &str, file_name: &str) -> Result<MappedFile, FileSystemError> {
+        let dir = self.open_directory(dir)?;
+        let fat = self.get_current_fat(&dir.0)?;
+        let entry = dir.0.get_entry(file_name)?;
+        if entry.entry_type != FILE_ENTRY_TYPE {
+            return Err(FileSystemError::NotAFile);
+        }
+        let sector = fat.entries[entry.first_cluster as usize].get_sector()?;
+        Ok(MappedFile::new(
+            self.disk_manager.clone(),
+            vec![Some(sector as u64)],
+            SECTOR_SIZE as u64,
+            true,
+        ))
+    }
+
     pub fn change_data(
         &mut self,
+        dir: &str,
         file_name: &str,
         buffer: &[u8; SECTOR_SIZE],
     ) -> Result<(), FileSystemError> {
-        let dir = self.get_current_directory()?;
+        let dir = self.open_directory(dir)?;
         let fat = self.get_current_fat(&dir.0)?;
         let entry = dir.0.get_entry(file_name)?;
-        if entry.entry_type == DIR_ENTRY_TYPE {
+        if entry.entry_type != FILE_ENTRY_TYPE {
             return Err(FileSystemError::NotAFile);
         }
 
-        self.disk_manager.write(
-            buffer.as_ptr(),
-            fat.entries[entry.first_cluster as usize].get_sector()? as u64,
-            1,
-        )?;
-        Ok(())
+        let sector = fat.entries[entry.first_cluster as usize].get_sector()?;
+        match self.disk_manager.write(buffer.as_ptr(), sector as u64, 1) {
+            Err(e) if e.is_media_error() => {
+                eprintln!("sector {} is bad, moving {} to a new one", sector, file_name);
+                self.move_to_new_sector(&dir.0, entry.first_cluster, sector, buffer)
+            }
+            result => result,
+        }
+    }
+
+    /*
+    this function writes 'buffer' to a new sector and points the fat entry 'fat_index' of a file at it,
+    'bad_sector' (where the file was) is recorded as a bad entry and never freed
+     */
+    fn move_to_new_sector(
+        &mut self,
+        directory: &Directory,
+        fat_index: u16,
+        bad_sector: u16,
+        buffer: &[u8; SECTOR_SIZE],
+    ) -> Result<(), FileSystemError> {
+        let mut fat = self.get_current_fat(directory)?;
+        let sector = loop {
+            let sector = self.allocate_zeroed_sector(&mut fat)?;
+            match self.disk_manager.write(buffer.as_ptr(), sector as u64, 1) {
+                Ok(()) => break sector,
+                Err(e) if e.is_media_error() => fat.add_entry(FATEntry::new_bad(sector)?)?,
+                Err(e) => {
+                    self.allocator.free(sector);
+                    self.allocator.save(&*self.disk_manager)?;
+                    return Err(e);
+                }
+            }
+        };
+        fat.entries[fat_index as usize] = FATEntry::new_used(sector)?;
+        // without a free entry the sector is only left out of circulation, it isn't freed either way
+        let _ = fat.add_entry(FATEntry::new_bad(bad_sector)?);
+        fat.save(&*self.disk_manager, Some(directory.fat_sector))
+    }
+
+    /*
+    this function allocates a new sector and zeroes it, sectors that fail with a media error are
+    recorded as bad entries in 'fat' and skipped
+     */
+    fn allocate_zeroed_sector(&mut self, fat: &mut FAT) -> Result<u16, FileSystemError> {
+        let zero = [0u8; SECTOR_SIZE];
+        loop {
+            let sector = self.allocator.get_free_sector();
+            self.allocator.save(&*self.disk_manager)?;
+            match self.disk_manager.write(zero.as_ptr(), sector as u64, 1) {
+                Ok(()) => return Ok(sector),
+                Err(e) if e.is_media_error() => {
+                    eprintln!("sector {} is bad, skipping it", sector);
+                    fat.add_entry(FATEntry::new_bad(sector)?)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
     }
     pub fn get_sector(&self, entry_index: usize) -> Result<u16, FileSystemError> {
         let entry = self.get_entry(entry_index)?;
@@ -279,13 +382,13 @@ impl FAtApi {
             return Err(DirectoryNotFound);
         }
         Ok((
-            Directory::load(&self.disk_manager, Some(entry.first_cluster))?,
+            Directory::load(&*self.disk_manager, Some(entry.first_cluster))?,
             entry.first_cluster,
         ))
     }
 
-    pub fn search_directory(&self, name: &str) -> Result<bool, FileSystemError> {
-        let dir = self.get_current_directory()?.0.get_entry(name);
+    pub fn search_directory(&self, dir: &str, name: &str) -> Result<bool, FileSystemError> {
+        let dir = self.open_directory(dir)?.0.get_entry(name);
         match dir {
             Ok(dir) => {
                 if dir.entry_type == DIR_ENTRY_TYPE {
@@ -299,15 +402,15 @@ impl FAtApi {
         }
     }
 
-    fn get_current_directory(&self) -> Result<(Directory, u16), FileSystemError> {
-        let parts: Vec<String> = WORKING_DIR
-            .lock()
+    // the directory table at the absolute 'path' and its sector
+    fn open_directory(&self, path: &str) -> Result<(Directory, u16), FileSystemError> {
+        let parts: Vec<String> = path
             .split('/')
             .map(String::from)
             .filter(|s| !s.is_empty())
             .collect();
 
-        let mut last_dir = (Directory::load(&self.disk_manager, None)?, FIRST_DIRECTORY);
+        let mut last_dir = (Directory::load(&*self.disk_manager, None)?, FIRST_DIRECTORY);
         for dir_name in parts {
             last_dir = self.get_directory_table_by_name(&last_dir.0, dir_name.as_str())?;
         }
@@ -315,11 +418,10 @@ impl FAtApi {
         Ok(last_dir)
     }
     fn get_current_fat(&self, directory: &Directory) -> Result<FAT, FileSystemError> {
-        FAT::load(&self.disk_manager, Some(directory.fat_sector))
+        FAT::load(&*self.disk_manager, Some(directory.fat_sector))
     }
-    fn get_parent_sector(&self) -> Result<u16, FileSystemError> {
-        let mut parts: Vec<String> = WORKING_DIR
-            .lock()
+    fn get_parent_sector(&self, path: &str) -> Result<u16, FileSystemError> {
+        let mut parts: Vec<String> = path
             .split('/')
             .map(String::from)
             .filter(|s| !s.is_empty())
@@ -329,7 +431,7 @@ impl FAtApi {
             return Ok(FIRST_DIRECTORY);
         }
         let current = parts.pop().unwrap(); // remove the current dir
-        let mut last_dir = (Directory::load(&self.disk_manager, None)?, FIRST_DIRECTORY).0;
+        let mut last_dir = (Directory::load(&*self.disk_manager, None)?, FIRST_DIRECTORY).0;
         for dir_name in parts {
             last_dir = self
                 .get_directory_table_by_name(&last_dir, dir_name.as_str())?
@@ -337,39 +439,56 @@ impl FAtApi {
         }
         Ok(last_dir.get_entry(current.as_str())?.first_cluster)
     }
-    pub fn add_file(&mut self, name: &str) -> Result<(), FileSystemError> {
-        let mut dir = self.get_current_directory()?;
+    pub fn add_file(&mut self, dir: &str, name: &str) -> Result<(), FileSystemError> {
+        let mut dir = self.open_directory(dir)?;
         match dir.0.get_entry(name) {
             Err(_) => {
                 let mut fat = self.get_current_fat(&dir.0)?;
 
+                let sector = self.allocate_zeroed_sector(&mut fat)?;
                 let index = fat.first_free_entry()?;
 
-                let zero = [0u8; SECTOR_SIZE];
-                let sector = self.allocator.get_free_sector();
-                self.allocator.save(&self.disk_manager)?;
-                self.disk_manager.write(zero.as_ptr(), sector as u64, 1)?;
-
                 fat.add_entry(FATEntry::new_used(sector)?)?;
 
                 dir.0
                     .add_entry(DirEntry::new(name, index as u16, FILE_ENTRY_TYPE))?;
 
-                fat.save(&self.disk_manager, Some(dir.0.fat_sector))?;
-                dir.0.save(&self.disk_manager, Some(dir.1))?;
+                fat.save(&*self.disk_manager, Some(dir.0.fat_sector))?;
+                dir.0.save(&*self.disk_manager, Some(dir.1))?;
                 Ok(())
             }
             Ok(_) => Err(FileAlreadyExists),
         }
     }
 
+    /*
+    this function adds the FIFO 'name' to the directory 'dir'. the entry has no sectors, its data only
+    passes through the pipe the kernel keeps for it while the system runs
+     */
+    pub fn add_fifo(&mut self, dir: &str, name: &str) -> Result<(), FileSystemError> {
+        let mut dir = self.open_directory(dir)?;
+        if dir.0.get_entry(name).is_ok() {
+            return Err(FileAlreadyExists);
+        }
+        dir.0.add_entry(DirEntry::new(name, 0, FIFO_ENTRY_TYPE))?;
+        dir.0.save(&*self.disk_manager, Some(dir.1))
+    }
+
+    pub fn is_fifo(&self, dir: &str, name: &str) -> Result<bool, FileSystemError> {
+        match self.open_directory(dir)?.0.get_entry(name) {
+            Ok(entry) => Ok(entry.entry_type == FIFO_ENTRY_TYPE),
+            Err(FileSystemError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     // this function creates new dir and making a sub dirs of '.' and '..'
-    pub fn new_dir(&mut self, name: &str) -> Result<(), FileSystemError> {
-        if self.get_current_directory()?.0.get_entry(name).is_ok() {
+    pub fn new_dir(&mut self, path: &str, name: &str) -> Result<(), FileSystemError> {
+        if self.open_directory(path)?.0.get_entry(name).is_ok() {
             return Err(DirAlreadyExists);
         }
         let fat_sector = self.allocator.get_free_sectors(9);
-        self.allocator.save(&self.disk_manager)?;
+        self.allocator.save(&*self.disk_manager)?;
         let mut fat = FAT::new();
 
         let dir_sector = fat_sector + 1;
@@ -378,22 +497,22 @@ impl FAtApi {
         dir.add_entry(DirEntry::new(".", dir_sector, DIR_ENTRY_TYPE))?;
         dir.add_entry(DirEntry::new(
             "..",
-            self.get_parent_sector()?,
+            self.get_parent_sector(path)?,
             DIR_ENTRY_TYPE,
         ))?;
 
-        fat.save(&self.disk_manager, Some(fat_sector))?;
+        fat.save(&*self.disk_manager, Some(fat_sector))?;
 
-        dir.save(&self.disk_manager, Some(dir_sector))?;
-        let mut parent = self.get_current_directory()?;
+        dir.save(&*self.disk_manager, Some(dir_sector))?;
+        let mut parent = self.open_directory(path)?;
         parent
             .0
             .add_entry(DirEntry::new(name, dir_sector, DIR_ENTRY_TYPE))?;
-        parent.0.save(&self.disk_manager, Some(parent.1))
+        parent.0.save(&*self.disk_manager, Some(parent.1))
     }
 
-    pub fn list_dir(&self) {
-        self.get_current_directory().unwrap().0.print();
+    pub fn list_dir(&self, dir: &str) {
+        self.open_directory(dir).unwrap().0.print();
     }
 
     pub fn index_by_name(&self, name: &str) -> Result<u16, FileSystemError> {
@@ -406,15 +525,22 @@ impl FAtApi {
         directory: &mut (Directory, u16),
     ) -> Result<(), FileSystemError> {
         let mut entry = directory.0.get_entry(name)?;
+        if entry.entry_type == FIFO_ENTRY_TYPE {
+            directory.0.remove_entry(name);
+            return directory.0.save(&*self.disk_manager, Some(directory.1));
+        }
         if entry.entry_type == FILE_ENTRY_TYPE {
             let fat_index = entry.first_cluster;
             let mut fat = self.get_current_fat(&directory.0)?;
             let fat_entry = fat.entries[fat_index as usize];
-            self.allocator.free(fat_entry.get_sector()?);
-            fat.remove_entry(fat_index)?;
-            fat.save(&self.disk_manager, Some(directory.0.fat_sector))?;
+            // a bad entry is kept so its sector stays out of circulation
+            if !fat_entry.is_bad() {
+                self.allocator.free(fat_entry.get_sector()?);
+                fat.remove_entry(fat_index)?;
+            }
+            fat.save(&*self.disk_manager, Some(directory.0.fat_sector))?;
             directory.0.remove_entry(name);
-            directory.0.save(&self.disk_manager, Some(directory.1))?;
+            directory.0.save(&*self.disk_manager, Some(directory.1))?;
             Ok(())
         } else {
             Err(FileSystemError::NotAFile)
@@ -450,18 +576,18 @@ impl FAtApi {
             }
         }
         fat.entries[0] = FATEntry::new_free();
-        fat.save(&self.disk_manager, Some(dir.0.fat_sector))?;
+        fat.save(&*self.disk_manager, Some(dir.0.fat_sector))?;
         self.allocator.free(dir.0.fat_sector);
         dir.0.magic = 0;
-        dir.0.save(&self.disk_manager, Some(dir.1))?;
+        dir.0.save(&*self.disk_manager, Some(dir.1))?;
         directory.0.entries[entry_index] = DirEntry::empty();
-        directory.0.save(&self.disk_manager, Some(directory.1))?;
+        directory.0.save(&*self.disk_manager, Some(directory.1))?;
         self.allocator.free_directory(dir.1);
 
         Ok(())
     }
-    pub fn remove_entry(&mut self, name: &str) -> Result<(), FileSystemError> {
-        let mut curr_dir = self.get_current_directory()?;
+    pub fn remove_entry(&mut self, dir: &str, name: &str) -> Result<(), FileSystemError> {
+        let mut curr_dir = self.open_directory(dir)?;
         return match self.remove_file_by_name(name, &mut curr_dir) {
             Ok(_) => Ok(()),
             Err(FileSystemError::NotAFile) => self.remove_dir_by_name(name, &mut curr_dir),
@@ -472,6 +598,7 @@ impl FAtApi {
 
 const DIR_ENTRY_TYPE: u8 = 0x10;
 const FILE_ENTRY_TYPE: u8 = 0x05;
+const FIFO_ENTRY_TYPE: u8 = 0x06;
 #[derive(Debug, Clone, Copy)]
 #[repr(C)] // Ensures the struct layout is C-compatible (for binary data)
 pub struct DirEntry {
@@ -533,8 +660,8 @@ impl Directory {
     fn get_entries(&self) -> &[DirEntry] {
         &self.entries
     }
-    pub fn load_or_create_dir(disk_manager: &Disk) -> Directory {
-        match Directory::load(&disk_manager, None) {
+    pub fn load_or_create_dir(disk_manager: &dyn BlockDevice) -> Directory {
+        match Directory::load(disk_manager, None) {
             Ok(dir) => {
                 println!("Directory loaded successfully and is valid.");
                 dir
@@ -563,6 +690,10 @@ impl Directory {
     fn print(&self) {
         for i in 0..self.entries.len() {
             if !self.entries[i].is_empty() {
+                if self.entries[i].entry_type == FIFO_ENTRY_TYPE {
+                    println!("{}: fifo", self.entries[i].to_string());
+                    continue;
+                }
                 if self.entries[i].entry_type == DIR_ENTRY_TYPE {
                     change_writer_color(Self::DIR_COLOR);
                 }
@@ -576,7 +707,7 @@ impl Directory {
         }
     }
 
-    fn save(&self, disk_manager: &Disk, sector: Option<u16>) -> Result<(), FileSystemError> {
+    fn save(&self, disk_manager: &dyn BlockDevice, sector: Option<u16>) -> Result<(), FileSystemError> {
         let bytes = unsafe {
             core::slice::from_raw_parts(
                 self as *const Directory as *const u8,
@@ -591,7 +722,7 @@ impl Directory {
         }
     }
 
-    fn load(disk_manager: &Disk, sector: Option<u16>) -> Result<Directory, FileSystemError> {
+    fn load(disk_manager: &dyn BlockDevice, sector: Option<u16>) -> Result<Directory, FileSystemError> {
         let mut buffer = [0u8; core::mem::size_of::<Directory>()];
         if sector.is_none() {
             disk_manager.read(buffer.as_mut_ptr(), FIRST_DIRECTORY as u64, 8)?;
@@ -664,7 +795,7 @@ impl SectorAllocator {
             self.freed_sectors.push(sector + offset);
         }
     }
-    fn save(&self, disk: &Disk) -> Result<(), FileSystemError> {
+    fn save(&self, disk: &dyn BlockDevice) -> Result<(), FileSystemError> {
         let buff = self.to_bitmap();
         disk.write(buff.as_ptr(), FIRST_USABLE_SECTOR as u64 - 2, 1)
     }
@@ -712,13 +843,13 @@ impl SectorAllocator {
         }
         Ok(allocator)
     }
-    fn load(disk: &Disk) -> Result<Self, FileSystemError> {
+    fn load(disk: &dyn BlockDevice) -> Result<Self, FileSystemError> {
         let mut tmp: [u8; 512] = [0u8; SECTOR_SIZE];
         disk.read(tmp.as_mut_ptr(), FIRST_USABLE_SECTOR as u64 - 2, 1)?;
         Self::from_bitmap(tmp)
     }
 
-    fn load_or_create(disk: &Disk) -> Self {
+    fn load_or_create(disk: &dyn BlockDevice) -> Self {
         match Self::load(disk) {
             Ok(allocator) => {
                 println!("sector allocator found and is valid!");
@@ -0,0 +1,155 @@
+//FILE TABLE
+//The file descriptors of a process. Descriptors point to shared open files, so a child started by a
+//process reads at the same offsets as its parent. 0, 1 and 2 start out on the terminal
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::ext2::{Ext2, Inode};
+use crate::file_system::mount;
+use crate::file_system::pipe::{self, PipeReader, PipeWriter};
+use crate::multitasking::process;
+use crate::terminal::input::buffer::BUFFER;
+use crate::{eprint, print};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+// descriptors a process can have open at once
+pub const MAX_FILES: usize = 64;
+
+pub enum OpenFile {
+    // the terminal, 'error' writes in the error color
+    Console { error: bool },
+    // a file of a mounted ext2 file system, read only. every read only reads the blocks it needs
+    Mounted { fs: Arc<Ext2>, inode: Inode, offset: Mutex<u64> },
+    // the ends of a pipe or FIFO, the pipe sees an end closed when the last descriptor of it is closed
+    PipeRead(PipeReader),
+    PipeWrite(PipeWriter),
+}
+
+impl OpenFile {
+    /*
+    this function opens the absolute 'path': an end of the FIFO there, or else a file of a mounted
+    file system, which can only be read. opening a FIFO doesn't wait for the other side
+     */
+    pub fn open(path: &str, write: bool) -> Result<Self, FileSystemError> {
+        if write {
+            return pipe::open_writer(path).map(OpenFile::PipeWrite).ok_or(FileSystemError::AccessDenied);
+        }
+        if let Some(reader) = pipe::open_reader(path) {
+            return Ok(OpenFile::PipeRead(reader));
+        }
+        let (fs, path) = mount::resolve(path).ok_or(FileSystemError::FileNotFound)?;
+        let inode = fs.open_file(&path)?;
+        Ok(OpenFile::Mounted { fs, inode, offset: Mutex::new(0) })
+    }
+
+    // both ends of a new pipe
+    pub fn pipe() -> (Self, Self) {
+        let (reader, writer) = pipe::pipe();
+        (OpenFile::PipeRead(reader), OpenFile::PipeWrite(writer))
+    }
+
+    // up to 'len' bytes from the current offset, the console reads one line including its newline
+    // unless a signal interrupts it
+    pub fn read(&self, len: usize) -> Result<Vec<u8>, FileSystemError> {
+        match self {
+            OpenFile::Console { .. } => {
+                let pid = process::current_pid();
+                let mut line: String = BUFFER
+                    .lock()
+                    .get_input_until(|| process::signal_pending(pid))
+                    .ok_or(FileSystemError::Interrupted)?;
+                line.push('\n');
+                let mut bytes = line.into_bytes();
+                bytes.truncate(len);
+                Ok(bytes)
+            }
+            OpenFile::Mounted { fs, inode, offset } => {
+                // held during the disk access, so descriptors sharing the file never read the same bytes
+                let mut offset = offset.lock();
+                let data = fs.read_at(inode, *offset, len)?;
+                *offset += data.len() as u64;
+                Ok(data)
+            }
+            OpenFile::PipeRead(reader) => reader.read(len),
+            OpenFile::PipeWrite(_) => Err(FileSystemError::AccessDenied),
+        }
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<usize, FileSystemError> {
+        match self {
+            OpenFile::Console { error } => {
+                let text = String::from_utf8_lossy(data);
+                if *error {
+                    eprint!("{}", text);
+                } else {
+                    print!("{}", text);
+                }
+                Ok(data.len())
+            }
+            OpenFile::PipeWrite(writer) => writer.write(data),
+            OpenFile::Mounted { .. } | OpenFile::PipeRead(_) => Err(FileSystemError::AccessDenied),
+        }
+    }
+}
+
+// cloning the table shares the open files, like the descriptors a child inherits
+#[derive(Clone)]
+pub struct FileTable {
+    files: Vec<Option<Arc<OpenFile>>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        FileTable { files: Vec::new() }
+    }
+
+    // stdin, stdout and stderr on the terminal
+    pub fn with_console() -> Self {
+        let input = Arc::new(OpenFile::Console { error: false });
+        FileTable {
+            files: vec![Some(input.clone()), Some(input), Some(Arc::new(OpenFile::Console { error: true }))],
+        }
+    }
+
+    pub fn get(&self, fd: usize) -> Option<Arc<OpenFile>> {
+        self.files.get(fd).cloned().flatten()
+    }
+
+    // the lowest free descriptor now refers to 'file', None when MAX_FILES are open
+    pub fn insert(&mut self, file: Arc<OpenFile>) -> Option<usize> {
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                Some(fd)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(Some(file));
+                Some(self.files.len() - 1)
+            }
+            None => None,
+        }
+    }
+
+    // 'fd' now refers to 'file' and whatever it referred to before is closed, false if fd is too large
+    pub fn set(&mut self, fd: usize, file: Arc<OpenFile>) -> bool {
+        if fd >= MAX_FILES {
+            return false;
+        }
+        if fd >= self.files.len() {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd] = Some(file);
+        true
+    }
+
+    // false when 'fd' wasn't open
+    pub fn close(&mut self, fd: usize) -> bool {
+        self.files.get_mut(fd).and_then(Option::take).is_some()
+    }
+
+    pub fn open_count(&self) -> usize {
+        self.files.iter().filter(|file| file.is_some()).count()
+    }
+}
@@ -1,3 +1,11 @@
+pub mod ahci;
+pub mod block_device;
 pub mod disk_driver;
 pub mod fat16;
 pub mod errors;
+pub mod ext2;
+pub mod file_table;
+pub mod mount;
+pub mod partition;
+pub mod pipe;
+pub mod virtio_blk;
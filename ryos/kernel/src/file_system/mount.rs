@@ -0,0 +1,112 @@
+//MOUNT TABLE
+//Extra file systems mounted next to the main ryos volume, each one under its own absolute path
+use crate::file_system::block_device::DEVICES;
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::ext2::Ext2;
+use crate::file_system::partition::PartitionKind;
+use crate::println;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub struct MountPoint {
+    // absolute path without a trailing '/', for example "/data"
+    pub path: String,
+    pub device: String,
+    pub fs: Arc<Ext2>,
+}
+
+pub static MOUNTS: Mutex<Vec<MountPoint>> = Mutex::new(Vec::new());
+
+// where the programs partition of the data disk is mounted at boot
+pub const PROGRAMS_PATH: &str = "/bin";
+
+// 'path' without empty parts and a trailing '/', the root is ""
+pub fn normalize(path: &str) -> String {
+    let mut normalized = String::new();
+    for part in path.split('/').filter(|s| !s.is_empty()) {
+        normalized.push('/');
+        normalized.push_str(part);
+    }
+    normalized
+}
+
+/*
+this function mounts the ext2 file system on the block device named 'device' (as listed by lsblk)
+at the absolute 'path'
+ */
+pub fn mount(device: &str, path: &str) -> Result<(), FileSystemError> {
+    let path = normalize(path);
+    if path.is_empty() {
+        return Err(FileSystemError::AccessDenied); // the root belongs to the ryos volume
+    }
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(FileSystemError::DirAlreadyExists);
+    }
+    let block_device = DEVICES
+        .lock()
+        .iter()
+        .find(|entry| entry.name == device)
+        .map(|entry| entry.device.clone())
+        .ok_or(FileSystemError::DiskNotAvailable)?;
+    let fs = Ext2::mount(block_device)?;
+    mounts.push(MountPoint {
+        path,
+        device: String::from(device),
+        fs: Arc::new(fs),
+    });
+    Ok(())
+}
+
+pub fn unmount(path: &str) -> Result<(), FileSystemError> {
+    let path = normalize(path);
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|mount| mount.path == path)
+        .ok_or(FileSystemError::DirectoryNotFound)?;
+    mounts.remove(index);
+    Ok(())
+}
+
+// find the mounted file system holding the absolute 'path' and the path inside it
+pub fn resolve(path: &str) -> Option<(Arc<Ext2>, String)> {
+    let path = normalize(path);
+    MOUNTS
+        .lock()
+        .iter()
+        .filter(|mount| path == mount.path || path.starts_with(&(mount.path.clone() + "/")))
+        .max_by_key(|mount| mount.path.len())
+        .map(|mount| (mount.fs.clone(), String::from(&path[mount.path.len()..])))
+}
+
+// names of the mount points directly inside the absolute directory 'path'
+pub fn mount_points_in(path: &str) -> Vec<String> {
+    let parent = normalize(path);
+    MOUNTS
+        .lock()
+        .iter()
+        .filter_map(|mount| {
+            let name = mount.path.strip_prefix(&(parent.clone() + "/"))?;
+            (!name.contains('/')).then(|| String::from(name))
+        })
+        .collect()
+}
+
+// mount the first linux partition with an ext2 file system at PROGRAMS_PATH, the runner installs the programs there
+pub fn mount_programs() {
+    let partitions: Vec<String> = DEVICES
+        .lock()
+        .iter()
+        .filter(|entry| matches!(entry.partition, Some((PartitionKind::Linux, _))))
+        .map(|entry| entry.name.clone())
+        .collect();
+    for name in partitions {
+        if mount(&name, PROGRAMS_PATH).is_ok() {
+            println!("mounted the programs on {} at {}", name, PROGRAMS_PATH);
+            return;
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//PARTITION TABLES
+//Parsing of MBR and GPT partition tables, every partition is exposed as its own block device
+use crate::file_system::block_device::BlockDevice;
+use crate::file_system::disk_driver::SECTOR_SIZE;
+use crate::file_system::errors::FileSystemError;
+use crate::eprintln;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const MBR_SIGNATURE: u16 = 0xAA55;
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_ENTRY_COUNT: usize = 4;
+
+// mbr partition type ids
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_LINUX_SWAP: u8 = 0x82;
+const MBR_TYPE_LINUX: u8 = 0x83;
+const MBR_TYPE_RYOS: u8 = 0x7F; // the "alternative os development" id
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_LBA: u64 = 1;
+const GPT_MAX_ENTRIES: u32 = 128;
+
+// gpt type guids, in the mixed endian on disk layout
+const GPT_TYPE_UNUSED: [u8; 16] = [0; 16];
+// 0fc63daf-8483-4772-8e79-3d69d8477de4
+const GPT_TYPE_LINUX: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+];
+// 0657fd6d-a4ab-43c4-84e5-0933c84b4f4f
+const GPT_TYPE_LINUX_SWAP: [u8; 16] = [
+    0x6D, 0xFD, 0x57, 0x06, 0xAB, 0xA4, 0xC4, 0x43, 0x84, 0xE5, 0x09, 0x33, 0xC8, 0x4B, 0x4F, 0x4F,
+];
+// 52594f53-0000-4d00-8000-00000000a710 ("RYOS")
+const GPT_TYPE_RYOS: [u8; 16] = [
+    0x53, 0x4F, 0x59, 0x52, 0x00, 0x00, 0x00, 0x4D, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0xA7, 0x10,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    Ryos,
+    Linux,
+    Swap,
+    Other,
+}
+
+impl PartitionKind {
+    fn from_mbr_type(id: u8) -> Self {
+        match id {
+            MBR_TYPE_RYOS => PartitionKind::Ryos,
+            MBR_TYPE_LINUX => PartitionKind::Linux,
+            MBR_TYPE_LINUX_SWAP => PartitionKind::Swap,
+            _ => PartitionKind::Other,
+        }
+    }
+
+    fn from_gpt_type(guid: &[u8; 16]) -> Self {
+        match *guid {
+            GPT_TYPE_RYOS => PartitionKind::Ryos,
+            GPT_TYPE_LINUX => PartitionKind::Linux,
+            GPT_TYPE_LINUX_SWAP => PartitionKind::Swap,
+            _ => PartitionKind::Other,
+        }
+    }
+}
+
+// a window of 'length' sectors starting at 'start' on the parent device
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    pub number: usize,
+    pub kind: PartitionKind,
+    pub start: u64,
+    pub length: u64,
+}
+
+impl Partition {
+    pub fn new(
+        device: Arc<dyn BlockDevice>,
+        number: usize,
+        kind: PartitionKind,
+        start: u64,
+        length: u64,
+    ) -> Self {
+        Partition {
+            device,
+            number,
+            kind,
+            start,
+            length,
+        }
+    }
+
+    // make sure the whole transfer stays inside the partition
+    fn check_bounds(&self, lba: u64, sectors: u16) -> Result<u64, FileSystemError> {
+        match lba.checked_add(sectors as u64) {
+            Some(end) if end <= self.length => Ok(self.start + lba),
+            _ => Err(FileSystemError::IndexOutOfBounds),
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn read(&self, target: *mut u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        let lba = self.check_bounds(lba, sectors)?;
+        self.device.read(target, lba, sectors)
+    }
+
+    fn write(&self, source: *const u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        let lba = self.check_bounds(lba, sectors)?;
+        self.device.write(source, lba, sectors)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.length
+    }
+}
+
+/*
+this function reads the partition table of 'device' and returns its partitions,
+a disk without a valid mbr has no partitions
+ */
+pub fn scan(device: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, FileSystemError> {
+    let mut mbr = [0u8; SECTOR_SIZE];
+    device.read(mbr.as_mut_ptr(), 0, 1)?;
+
+    if read_u16(&mbr, 510) != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let mut partitions = Vec::new();
+    for i in 0..MBR_ENTRY_COUNT {
+        let entry = &mbr[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let type_id = entry[4];
+        let start = read_u32(entry, 8) as u64;
+        let length = read_u32(entry, 12) as u64;
+
+        if type_id == MBR_TYPE_GPT_PROTECTIVE {
+            return scan_gpt(device);
+        }
+        if type_id == MBR_TYPE_EMPTY || length == 0 {
+            continue;
+        }
+        if start + length > device.sector_count() {
+            eprintln!("partition {} is larger than the disk, ignoring it", i + 1);
+            continue;
+        }
+        partitions.push(Partition::new(
+            device.clone(),
+            i + 1,
+            PartitionKind::from_mbr_type(type_id),
+            start,
+            length,
+        ));
+    }
+    Ok(partitions)
+}
+
+fn scan_gpt(device: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>, FileSystemError> {
+    let mut header = [0u8; SECTOR_SIZE];
+    device.read(header.as_mut_ptr(), GPT_HEADER_LBA, 1)?;
+
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err(FileSystemError::InvalidPartitionTable);
+    }
+    let header_size = read_u32(&header, 12) as usize;
+    if header_size < 92 || header_size > SECTOR_SIZE {
+        return Err(FileSystemError::InvalidPartitionTable);
+    }
+    // the crc is computed with its own field zeroed
+    let expected_crc = read_u32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != expected_crc {
+        return Err(FileSystemError::InvalidPartitionTable);
+    }
+
+    let entries_lba = read_u64(&header, 72);
+    let entry_count = read_u32(&header, 80).min(GPT_MAX_ENTRIES);
+    let entry_size = read_u32(&header, 84) as usize;
+    if entry_size < 128 || entry_size > SECTOR_SIZE || SECTOR_SIZE % entry_size != 0 {
+        return Err(FileSystemError::InvalidPartitionTable);
+    }
+
+    let entries_per_sector = SECTOR_SIZE / entry_size;
+    let mut partitions = Vec::new();
+    let mut sector = [0u8; SECTOR_SIZE];
+    for i in 0..entry_count as usize {
+        if i % entries_per_sector == 0 {
+            device.read(
+                sector.as_mut_ptr(),
+                entries_lba + (i / entries_per_sector) as u64,
+                1,
+            )?;
+        }
+        let entry = &sector[(i % entries_per_sector) * entry_size..][..entry_size];
+        let mut type_guid = [0u8; 16];
+        type_guid.copy_from_slice(&entry[0..16]);
+        if type_guid == GPT_TYPE_UNUSED {
+            continue;
+        }
+
+        let first = read_u64(entry, 32);
+        let last = read_u64(entry, 40);
+        if last < first || last >= device.sector_count() {
+            eprintln!("gpt entry {} is outside the disk, ignoring it", i + 1);
+            continue;
+        }
+        partitions.push(Partition::new(
+            device.clone(),
+            i + 1,
+            PartitionKind::from_gpt_type(&type_guid),
+            first,
+            last - first + 1,
+        ));
+    }
+    Ok(partitions)
+}
+
+pub fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+pub fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buffer[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buffer[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+// crc32 (ieee 802.3), as used by the gpt header
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,238 @@
+//PIPES
+//A pipe is a bounded ring buffer in the kernel with a read end and a write end. Reads wait for data
+//and return nothing (end of file) after the last writer closed its end, writes wait for room and fail
+//with BrokenPipe after the last reader closed its end. Waiting tasks park on a wait list of the pipe.
+//Named pipes (FIFOs) are entries of the ryos volume, every open of one is a new end of the same pipe,
+//which the kernel creates when the FIFO is first opened
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::fat16::with_volume;
+use crate::file_system::mount;
+use crate::multitasking::wait_list::WaitList;
+use crate::multitasking::{process, round_robin};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+// bytes a pipe holds before writers have to wait
+pub const PIPE_SIZE: usize = 4096;
+
+struct Pipe {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    // ends closed so far, an end only gives up on the other side when one of those closed after it was opened
+    closed_readers: u64,
+    closed_writers: u64,
+    // readers waiting for data or the end of the file, writers waiting for room or the last reader
+    waiting_readers: WaitList,
+    waiting_writers: WaitList,
+}
+
+type SharedPipe = Arc<Mutex<Pipe>>;
+
+// pipes are used by tasks that get preempted, the timer must not switch away while one is locked
+fn with_pipe<R>(pipe: &SharedPipe, f: impl FnOnce(&mut Pipe) -> R) -> R {
+    without_interrupts(|| f(&mut pipe.lock()))
+}
+
+fn new_pipe() -> SharedPipe {
+    Arc::new(Mutex::new(Pipe {
+        buffer: VecDeque::with_capacity(PIPE_SIZE),
+        readers: 0,
+        writers: 0,
+        closed_readers: 0,
+        closed_writers: 0,
+        waiting_readers: WaitList::new(),
+        waiting_writers: WaitList::new(),
+    }))
+}
+
+pub struct PipeReader {
+    pipe: SharedPipe,
+    closed_writers: u64,
+}
+
+pub struct PipeWriter {
+    pipe: SharedPipe,
+    closed_readers: u64,
+}
+
+impl PipeReader {
+    fn new(pipe: SharedPipe) -> Self {
+        let closed_writers = with_pipe(&pipe, |pipe| {
+            pipe.readers += 1;
+            pipe.closed_writers
+        });
+        PipeReader { pipe, closed_writers }
+    }
+
+    /*
+    this function waits until the pipe has data and takes up to 'len' bytes. an empty result is the end
+    of the file: no writer is left and one closed since this end was opened, so a reader of a FIFO
+    waits for its first writer. a signal for the running process ends the wait
+     */
+    pub fn read(&self, len: usize) -> Result<Vec<u8>, FileSystemError> {
+        let pid = process::current_pid();
+        loop {
+            let data = with_pipe(&self.pipe, |pipe| {
+                if !pipe.buffer.is_empty() {
+                    let count = len.min(pipe.buffer.len());
+                    pipe.waiting_writers.wake_all();
+                    return Some(pipe.buffer.drain(..count).collect());
+                }
+                if pipe.writers == 0 && pipe.closed_writers != self.closed_writers {
+                    return Some(Vec::new());
+                }
+                pipe.waiting_readers.park();
+                None
+            });
+            if let Some(data) = data {
+                return Ok(data);
+            }
+            if interrupted(pid) {
+                return Err(FileSystemError::Interrupted);
+            }
+            without_interrupts(round_robin::schedule);
+        }
+    }
+}
+
+impl PipeWriter {
+    fn new(pipe: SharedPipe) -> Self {
+        let closed_readers = with_pipe(&pipe, |pipe| {
+            pipe.writers += 1;
+            pipe.closed_readers
+        });
+        PipeWriter { pipe, closed_readers }
+    }
+
+    // waits for room and writes as much of 'data' as fits, the count can be less than data.len()
+    pub fn write(&self, data: &[u8]) -> Result<usize, FileSystemError> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let pid = process::current_pid();
+        loop {
+            let written = with_pipe(&self.pipe, |pipe| {
+                if pipe.readers == 0 && pipe.closed_readers != self.closed_readers {
+                    return Err(FileSystemError::BrokenPipe);
+                }
+                // without a reader yet the data waits, a FIFO may be opened for writing first
+                let count = data.len().min(PIPE_SIZE - pipe.buffer.len());
+                if count == 0 {
+                    pipe.waiting_writers.park();
+                    return Ok(0);
+                }
+                pipe.buffer.extend(&data[..count]);
+                pipe.waiting_readers.wake_all();
+                Ok(count)
+            })?;
+            if written != 0 {
+                return Ok(written);
+            }
+            if interrupted(pid) {
+                return Err(FileSystemError::Interrupted);
+            }
+            without_interrupts(round_robin::schedule);
+        }
+    }
+}
+
+// checked after parking, a signal sent before the park is seen here and one sent after it unparks the task
+fn interrupted(pid: usize) -> bool {
+    if process::signal_pending(pid) {
+        round_robin::unpark(pid);
+        return true;
+    }
+    false
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        with_pipe(&self.pipe, |pipe| {
+            pipe.readers -= 1;
+            pipe.closed_readers += 1;
+            pipe.waiting_writers.wake_all();
+        });
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        with_pipe(&self.pipe, |pipe| {
+            pipe.writers -= 1;
+            pipe.closed_writers += 1;
+            pipe.waiting_readers.wake_all();
+        });
+    }
+}
+
+// a new anonymous pipe, both ends are open
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = new_pipe();
+    (PipeReader::new(pipe.clone()), PipeWriter::new(pipe))
+}
+
+// the pipes of the FIFOs opened since boot by their normalized absolute path
+static FIFOS: Mutex<BTreeMap<String, SharedPipe>> = Mutex::new(BTreeMap::new());
+
+fn with_fifos<R>(f: impl FnOnce(&mut BTreeMap<String, SharedPipe>) -> R) -> R {
+    without_interrupts(|| f(&mut FIFOS.lock()))
+}
+
+// the directory and the name of the absolute 'path' on the ryos volume, the root has no name
+fn split(path: &str) -> Result<(String, String), FileSystemError> {
+    let path = mount::normalize(path);
+    let (dir, name) = path.rsplit_once('/').ok_or(FileSystemError::FileAlreadyExists)?;
+    Ok((format!("{}/", dir), String::from(name)))
+}
+
+/*
+this function creates a FIFO at the absolute 'path', the directory it is in has to exist and nothing
+may have its name. the mounted file systems are read only
+ */
+pub fn mkfifo(path: &str) -> Result<(), FileSystemError> {
+    if mount::resolve(path).is_some() {
+        return Err(FileSystemError::AccessDenied);
+    }
+    let (dir, name) = split(path)?;
+    with_volume(|fs| fs.add_fifo(&dir, &name))?;
+    // left over from a FIFO at the same path that was removed with its directory
+    forget_fifo(path);
+    Ok(())
+}
+
+// called when the FIFO at 'path' was removed, the open ends keep working and a new FIFO gets a new pipe
+pub fn forget_fifo(path: &str) {
+    let path = mount::normalize(path);
+    with_fifos(|fifos| fifos.remove(&path));
+}
+
+pub fn is_fifo(path: &str) -> bool {
+    if mount::resolve(path).is_some() {
+        return false;
+    }
+    split(path).and_then(|(dir, name)| with_volume(|fs| fs.is_fifo(&dir, &name))).unwrap_or(false)
+}
+
+// the pipe of the FIFO at 'path', None if there is no FIFO there
+fn fifo_pipe(path: &str) -> Option<SharedPipe> {
+    if !is_fifo(path) {
+        return None;
+    }
+    let path = mount::normalize(path);
+    Some(with_fifos(|fifos| fifos.entry(path).or_insert_with(new_pipe).clone()))
+}
+
+// a new read end of the FIFO at 'path'
+pub fn open_reader(path: &str) -> Option<PipeReader> {
+    fifo_pipe(path).map(PipeReader::new)
+}
+
+pub fn open_writer(path: &str) -> Option<PipeWriter> {
+    fifo_pipe(path).map(PipeWriter::new)
+}
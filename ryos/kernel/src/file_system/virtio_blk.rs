@@ -0,0 +1,380 @@
+//VIRTIO BLOCK DRIVER
+//Driver for legacy (transitional) virtio-blk pci devices using a single virtqueue
+use crate::file_system::block_device::BlockDevice;
+use crate::file_system::disk_driver::SECTOR_SIZE;
+use crate::file_system::errors::FileSystemError;
+use crate::interrupts::interrupts::register_irq_handler;
+use crate::memory::dma::DmaRegion;
+use crate::pci::bus::{self, Bar};
+use crate::eprintln;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const VIRTIO_VENDOR_ID: u16 = 0x1af4;
+const VIRTIO_BLK_LEGACY_DEVICE_ID: u16 = 0x1001;
+
+// legacy register offsets inside the i/o bar
+const DEVICE_FEATURES: u16 = 0x00;
+const GUEST_FEATURES: u16 = 0x04;
+const QUEUE_ADDRESS: u16 = 0x08;
+const QUEUE_SIZE: u16 = 0x0c;
+const QUEUE_SELECT: u16 = 0x0e;
+const QUEUE_NOTIFY: u16 = 0x10;
+const DEVICE_STATUS: u16 = 0x12;
+const ISR_STATUS: u16 = 0x13;
+const CONFIG_CAPACITY: u16 = 0x14;
+
+// device status bits
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 128;
+
+// feature bits
+const VIRTIO_BLK_F_RO: u32 = 1 << 5;
+
+// descriptor flags
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+// request types and statuses
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+const QUEUE_ALIGN: usize = 4096;
+// data goes through a bounce buffer of this many pages, bigger transfers are split
+const BOUNCE_PAGES: usize = 16;
+const BOUNCE_SECTORS: usize = BOUNCE_PAGES * 4096 / SECTOR_SIZE;
+// the request header lives at the start of the request page, the status byte after it
+const STATUS_OFFSET: usize = 16;
+// polls of the used ring (or interrupts waited for) before a request is considered lost
+const TIMEOUT_POLLS: u32 = 10_000_000;
+const TIMEOUT_HALTS: u32 = 1_000;
+
+// isr ports of all devices, read by the interrupt handler to acknowledge the interrupt
+static ISR_PORTS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct RequestHeader {
+    request_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+struct VirtQueue {
+    ring: DmaRegion,
+    size: u16,
+    avail_offset: usize,
+    used_offset: usize,
+    avail_idx: u16,
+    last_used_idx: u16,
+    request: DmaRegion,
+    bounce: DmaRegion,
+}
+
+impl VirtQueue {
+    fn new(size: u16) -> Option<Self> {
+        let (avail_offset, used_offset, total) = Self::layout(size as usize);
+        Some(VirtQueue {
+            ring: DmaRegion::allocate(total / 4096)?,
+            size,
+            avail_offset,
+            used_offset,
+            avail_idx: 0,
+            last_used_idx: 0,
+            request: DmaRegion::allocate(1)?,
+            bounce: DmaRegion::allocate(BOUNCE_PAGES)?,
+        })
+    }
+
+    // offsets of the available and used rings and the total size of a legacy queue
+    fn layout(size: usize) -> (usize, usize, usize) {
+        let avail_offset = 16 * size;
+        let used_offset = align_up(avail_offset + 6 + 2 * size, QUEUE_ALIGN);
+        let total = used_offset + align_up(6 + 8 * size, QUEUE_ALIGN);
+        (avail_offset, used_offset, total)
+    }
+
+    fn set_descriptor(&self, index: u16, addr: u64, len: u32, flags: u16, next: u16) {
+        let descriptor = Descriptor {
+            addr,
+            len,
+            flags,
+            next,
+        };
+        unsafe {
+            write_volatile(self.ring.ptr::<Descriptor>(index as usize * 16), descriptor);
+        }
+    }
+
+    // put descriptor chain 'head' in the available ring
+    fn push_available(&mut self, head: u16) {
+        let slot = self.avail_offset + 4 + 2 * (self.avail_idx % self.size) as usize;
+        unsafe {
+            write_volatile(self.ring.ptr::<u16>(slot), head);
+        }
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        // the device must see the ring entry before the new index
+        fence(Ordering::SeqCst);
+        unsafe {
+            write_volatile(self.ring.ptr::<u16>(self.avail_offset + 2), self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    // forget every request, the device was reset and starts with empty rings
+    fn restart(&mut self) {
+        unsafe { core::ptr::write_bytes(self.ring.ptr::<u8>(0), 0, self.ring.size()) };
+        self.avail_idx = 0;
+        self.last_used_idx = 0;
+    }
+
+    fn used_idx(&self) -> u16 {
+        unsafe { read_volatile(self.ring.ptr::<u16>(self.used_offset + 2)) }
+    }
+}
+
+// the legacy register block in the device's i/o bar
+struct Registers {
+    io_base: u16,
+}
+
+impl Registers {
+    fn write8(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.io_base + register).write(value) }
+    }
+    fn read16(&self, register: u16) -> u16 {
+        unsafe { Port::<u16>::new(self.io_base + register).read() }
+    }
+    fn write16(&self, register: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.io_base + register).write(value) }
+    }
+    fn read32(&self, register: u16) -> u32 {
+        unsafe { Port::<u32>::new(self.io_base + register).read() }
+    }
+    fn write32(&self, register: u16, value: u32) {
+        unsafe { Port::<u32>::new(self.io_base + register).write(value) }
+    }
+}
+
+pub struct VirtioBlk {
+    registers: Registers,
+    queue: Mutex<VirtQueue>,
+    sectors: u64,
+    read_only: bool,
+    // set when the device couldn't be brought back after a lost request, every request fails then
+    failed: AtomicBool,
+}
+
+impl VirtioBlk {
+    // reset the device and go through the legacy initialization sequence
+    fn init(io_base: u16) -> Result<Self, FileSystemError> {
+        let registers = Registers { io_base };
+        // we only care about the read only bit
+        let features = Self::negotiate(&registers);
+
+        registers.write16(QUEUE_SELECT, 0);
+        let size = registers.read16(QUEUE_SIZE);
+        if size == 0 {
+            registers.write8(DEVICE_STATUS, STATUS_FAILED);
+            return Err(FileSystemError::DiskNotAvailable);
+        }
+        let Some(queue) = VirtQueue::new(size) else {
+            registers.write8(DEVICE_STATUS, STATUS_FAILED);
+            return Err(FileSystemError::OutOfSpace);
+        };
+        let low = registers.read32(CONFIG_CAPACITY) as u64;
+        let high = registers.read32(CONFIG_CAPACITY + 4) as u64;
+
+        Self::start(&registers, &queue);
+        Ok(VirtioBlk {
+            registers,
+            queue: Mutex::new(queue),
+            sectors: high << 32 | low,
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    // reset the device and acknowledge it without accepting any optional feature, returns the features it offers
+    fn negotiate(registers: &Registers) -> u32 {
+        registers.write8(DEVICE_STATUS, 0);
+        registers.write8(DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+        registers.write8(DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let features = registers.read32(DEVICE_FEATURES);
+        registers.write32(GUEST_FEATURES, 0);
+        features
+    }
+
+    // hand 'queue' to the device as queue 0 and let it run
+    fn start(registers: &Registers, queue: &VirtQueue) {
+        registers.write16(QUEUE_SELECT, 0);
+        registers.write32(QUEUE_ADDRESS, (queue.ring.phys(0) / QUEUE_ALIGN as u64) as u32);
+        registers.write8(
+            DEVICE_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+    }
+
+    /*
+    this function resets the device after a request timed out. the device still owns the descriptors
+    and the buffers of that request, a late completion would otherwise be taken for the next request.
+    after the reset the device stops using the queue, which starts over empty
+     */
+    fn reset(&self, queue: &mut VirtQueue) {
+        Self::negotiate(&self.registers);
+        self.registers.write16(QUEUE_SELECT, 0);
+        if self.registers.read16(QUEUE_SIZE) != queue.size {
+            eprintln!("virtio-blk didn't come back after a reset, giving up on it");
+            self.registers.write8(DEVICE_STATUS, STATUS_FAILED);
+            self.failed.store(true, Ordering::Relaxed);
+            return;
+        }
+        queue.restart();
+        Self::start(&self.registers, queue);
+    }
+
+    // send one request of at most BOUNCE_SECTORS sectors and wait for its completion
+    fn request(&self, write: bool, lba: u64, sectors: usize, data: *mut u8) -> Result<(), FileSystemError> {
+        let mut queue = self.queue.lock();
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(FileSystemError::DiskNotAvailable);
+        }
+        let length = sectors * SECTOR_SIZE;
+
+        let header = RequestHeader {
+            request_type: if write { VIRTIO_BLK_T_OUT } else { VIRTIO_BLK_T_IN },
+            reserved: 0,
+            sector: lba,
+        };
+        unsafe {
+            write_volatile(queue.request.ptr::<RequestHeader>(0), header);
+            write_volatile(queue.request.ptr::<u8>(STATUS_OFFSET), 0xff);
+            if write {
+                core::ptr::copy_nonoverlapping(data, queue.bounce.ptr::<u8>(0), length);
+            }
+        }
+
+        let data_flags = if write { DESC_F_NEXT } else { DESC_F_NEXT | DESC_F_WRITE };
+        queue.set_descriptor(0, queue.request.phys(0), 16, DESC_F_NEXT, 1);
+        queue.set_descriptor(1, queue.bounce.phys(0), length as u32, data_flags, 2);
+        queue.set_descriptor(2, queue.request.phys(STATUS_OFFSET), 1, DESC_F_WRITE, 0);
+        queue.push_available(0);
+        self.registers.write16(QUEUE_NOTIFY, 0);
+
+        let expected = queue.last_used_idx.wrapping_add(1);
+        // the completion interrupt wakes us up, unless we were called with interrupts off
+        let halt = x86_64::instructions::interrupts::are_enabled();
+        let limit = if halt { TIMEOUT_HALTS } else { TIMEOUT_POLLS };
+        let mut polls = 0;
+        while queue.used_idx() != expected {
+            polls += 1;
+            if polls > limit {
+                self.reset(&mut queue);
+                return Err(FileSystemError::DiskTimeout);
+            }
+            if halt {
+                x86_64::instructions::hlt();
+            } else {
+                core::hint::spin_loop();
+            }
+        }
+        queue.last_used_idx = expected;
+        fence(Ordering::SeqCst);
+
+        match unsafe { read_volatile(queue.request.ptr::<u8>(STATUS_OFFSET)) } {
+            VIRTIO_BLK_S_OK => {}
+            VIRTIO_BLK_S_UNSUPP => return Err(FileSystemError::CommandAborted),
+            _ => return Err(FileSystemError::DriveFault),
+        }
+        if !write {
+            unsafe {
+                core::ptr::copy_nonoverlapping(queue.bounce.ptr::<u8>(0), data, length);
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer(&self, write: bool, data: *mut u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        if lba + sectors as u64 > self.sectors {
+            return Err(FileSystemError::IndexOutOfBounds);
+        }
+        let mut done = 0;
+        while done < sectors as usize {
+            let count = (sectors as usize - done).min(BOUNCE_SECTORS);
+            let chunk = unsafe { data.add(done * SECTOR_SIZE) };
+            self.request(write, lba + done as u64, count, chunk)?;
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn read(&self, target: *mut u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        self.transfer(false, target, lba, sectors)
+    }
+
+    fn write(&self, source: *const u8, lba: u64, sectors: u16) -> Result<(), FileSystemError> {
+        if self.read_only {
+            return Err(FileSystemError::AccessDenied);
+        }
+        self.transfer(true, source as *mut u8, lba, sectors)
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+// acknowledge the interrupt of every device, the waiting request checks the used ring itself
+fn virtio_interrupt() {
+    for &port in ISR_PORTS.lock().iter() {
+        unsafe {
+            Port::<u8>::new(port).read();
+        }
+    }
+}
+
+// find and initialize all virtio-blk devices on the pci bus
+pub fn probe() -> Vec<VirtioBlk> {
+    let mut disks = Vec::new();
+    for pci_device in bus::find(VIRTIO_VENDOR_ID, VIRTIO_BLK_LEGACY_DEVICE_ID) {
+        let Bar::Io(io_base) = pci_device.bar(0) else {
+            eprintln!("virtio-blk without an i/o bar, skipping it");
+            continue;
+        };
+        pci_device.enable();
+        match VirtioBlk::init(io_base) {
+            Ok(disk) => {
+                x86_64::instructions::interrupts::without_interrupts(|| {
+                    ISR_PORTS.lock().push(io_base + ISR_STATUS);
+                });
+                let line = pci_device.interrupt_line();
+                if (2..16).contains(&line) {
+                    register_irq_handler(line, virtio_interrupt);
+                }
+                disks.push(disk);
+            }
+            Err(e) => eprintln!("virtio-blk init failed {:?}", e),
+        }
+    }
+    disks
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
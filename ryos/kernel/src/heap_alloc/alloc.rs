@@ -1,14 +1,28 @@
-const HEAP_START: usize = 0x_4444_4444_0000;
-const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+// mapped at boot, set with RYOS_HEAP_INITIAL_SIZE in KiB when the kernel is built
+const HEAP_DEFAULT_INITIAL_SIZE: usize = 100 * 1024; // 100 KiB, grows on demand
+const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+const HEAP_GROW_STEP: usize = 64 * 1024; // map at least this much at a time
+const PAGE_SIZE: usize = 4096;
 use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, Size4KiB};
 use crate::VirtAddr;
+use crate::memory::paging::{FRAME_ALLOCATOR, MAPPER};
+use crate::{eprintln, hlt_loop};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::structures::paging::PageTableFlags as Flags;
 
-pub fn init_heap(frame_allocator: &mut impl FrameAllocator<Size4KiB>, mapper: &mut OffsetPageTable
+// map the pages of [start, start + size) to fresh frames
+fn map_heap_pages(
+    start: usize,
+    size: usize,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    mapper: &mut OffsetPageTable,
 ) -> Result<(), MapToError<Size4KiB>> {
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + (HEAP_SIZE as usize).try_into().unwrap() - 1;
+        let heap_start = VirtAddr::new(start as u64);
+        let heap_end = heap_start + (size as u64) - 1;
         let heap_start_page: Page<Size4KiB> = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
@@ -20,24 +34,169 @@ pub fn init_heap(frame_allocator: &mut impl FrameAllocator<Size4KiB>, mapper: &m
             .ok_or(MapToError::FrameAllocationFailed)?;
         let flags = Flags::PRESENT | Flags::WRITABLE;
         unsafe {
-            let map_to_result = unsafe {
-                // FIXME: this is not safe, we do it only for testing
-                mapper.map_to(page, frame, flags, frame_allocator)
-            };
-            map_to_result.expect("map_to failed").flush();
-        };
+            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+        }
     }
+    Ok(())
+}
+
+// the initial heap size the kernel was built with, whole pages between one page and the maximum size
+pub fn initial_size() -> usize {
+    option_env!("RYOS_HEAP_INITIAL_SIZE")
+        .and_then(|kib| kib.parse::<usize>().ok())
+        .map_or(HEAP_DEFAULT_INITIAL_SIZE, |kib| kib.saturating_mul(1024))
+        .clamp(PAGE_SIZE, HEAP_MAX_SIZE)
+        .div_ceil(PAGE_SIZE)
+        * PAGE_SIZE
+}
+
+pub fn init_heap(initial_size: usize, frame_allocator: &mut impl FrameAllocator<Size4KiB>, mapper: &mut OffsetPageTable
+) -> Result<(), MapToError<Size4KiB>> {
+    map_heap_pages(HEAP_START, initial_size, frame_allocator, mapper)?;
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.heap.heap.lock().init(HEAP_START, initial_size);
     }
 
-
     Ok(())
 }
 
-use linked_list_allocator::LockedHeap;
+use crate::heap_alloc::slab::{slab_layout, SlabCache};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+// bytes handed out to callers right now and the most there ever were
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    // mapped heap memory
+    pub size: usize,
+    // taken from the linked list heap, including whole slab pages
+    pub used: usize,
+    pub free: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub max_size: usize,
+}
+
+pub fn stats() -> HeapStats {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.heap.heap.lock();
+        HeapStats {
+            size: heap.size(),
+            used: heap.used(),
+            free: heap.free(),
+            in_use: IN_USE.load(Ordering::Relaxed),
+            peak: PEAK.load(Ordering::Relaxed),
+            max_size: HEAP_MAX_SIZE,
+        }
+    })
+}
+
+// linked list heap that maps more pages after its end when an allocation doesn't fit,
+// it serves the large objects and the pages of the slab cache
+pub struct GrowableHeap {
+    heap: Mutex<Heap>,
+}
+
+impl GrowableHeap {
+    /*
+    this function maps enough pages for 'layout' at the top of the heap and hands them to it,
+    false when the heap is at its maximum size or memory paging isn't set up yet
+     */
+    fn grow(heap: &mut Heap, layout: Layout) -> bool {
+        let needed = layout.size() + layout.align();
+        let by = needed.max(HEAP_GROW_STEP).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        if heap.size() + by > HEAP_MAX_SIZE {
+            return false;
+        }
+
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (Some(mapper), Some(frame_allocator)) = (mapper.as_mut(), frame_allocator.as_mut()) else {
+            return false;
+        };
+        if map_heap_pages(heap.top(), by, frame_allocator, mapper).is_err() {
+            return false;
+        }
+        unsafe {
+            heap.extend(by);
+        }
+        true
+    }
+
+    pub fn allocate(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        loop {
+            if let Ok(allocation) = heap.allocate_first_fit(layout) {
+                return allocation.as_ptr();
+            }
+            if !Self::grow(&mut heap, layout) {
+                return null_mut();
+            }
+        }
+    }
+
+    // unsafe because 'ptr' must come from allocate with the same layout
+    pub unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        unsafe {
+            self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout);
+        }
+    }
+}
+
+// small objects come from the slab cache, everything else from the linked list heap
+pub struct KernelAllocator {
+    pub slabs: SlabCache,
+    pub heap: GrowableHeap,
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // an interrupt handler allocating while we hold a lock would deadlock
+        let ptr = x86_64::instructions::interrupts::without_interrupts(|| match SlabCache::class_of(&layout) {
+            Some(class) => self.slabs.allocate(class, || self.heap.allocate(slab_layout())),
+            None => self.heap.allocate(layout),
+        });
+        if !ptr.is_null() {
+            let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(in_use, Ordering::Relaxed);
+        }
+        ptr
+    }
 
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+            match SlabCache::class_of(&layout) {
+                Some(class) => self.slabs.deallocate(class, ptr),
+                None => self.heap.deallocate(ptr, layout),
+            }
+        })
+    }
+}
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static ALLOCATOR: KernelAllocator = KernelAllocator {
+    slabs: SlabCache::new(),
+    heap: GrowableHeap {
+        heap: Mutex::new(Heap::empty()),
+    },
+};
+
+// called when an allocation fails even after trying to grow the heap
+#[alloc_error_handler]
+fn out_of_memory(layout: Layout) -> ! {
+    let heap = stats();
+    eprintln!(
+        "out of memory: allocating {} bytes (align {}) failed, heap uses {} of {} bytes (max {})",
+        layout.size(),
+        layout.align(),
+        heap.used,
+        heap.size,
+        heap.max_size
+    );
+    hlt_loop();
+}
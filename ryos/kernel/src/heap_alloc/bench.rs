@@ -0,0 +1,62 @@
+//HEAP BENCHMARK
+//Runs the same alloc/free churn through the slab allocator and through the plain linked list heap
+use crate::heap_alloc::alloc::ALLOCATOR;
+use crate::println;
+use core::alloc::{GlobalAlloc, Layout};
+use core::arch::x86_64::_rdtsc;
+use core::ptr::null_mut;
+use x86_64::instructions::interrupts::without_interrupts;
+
+const LIVE_OBJECTS: usize = 64;
+const OPERATIONS: usize = 20_000;
+const MAX_OBJECT_SIZE: usize = 512;
+
+/*
+this function replaces a pseudo random live object with a new one of a random size OPERATIONS times
+and returns the average cycles of one free + alloc pair
+ */
+fn churn(allocate: impl Fn(Layout) -> *mut u8, deallocate: impl Fn(*mut u8, Layout)) -> u64 {
+    let mut live = [(null_mut::<u8>(), Layout::new::<u8>()); LIVE_OBJECTS];
+    let mut random = 0x2545_F491_4F6C_DD1Du64;
+    let mut next = || {
+        // xorshift, the same sequence for every allocator
+        random ^= random << 13;
+        random ^= random >> 7;
+        random ^= random << 17;
+        random as usize
+    };
+
+    let start = unsafe { _rdtsc() };
+    for _ in 0..OPERATIONS {
+        let slot = next() % LIVE_OBJECTS;
+        let (ptr, layout) = live[slot];
+        if !ptr.is_null() {
+            deallocate(ptr, layout);
+        }
+        let layout = Layout::from_size_align(next() % MAX_OBJECT_SIZE + 1, 8).unwrap();
+        live[slot] = (allocate(layout), layout);
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+
+    for (ptr, layout) in live {
+        if !ptr.is_null() {
+            deallocate(ptr, layout);
+        }
+    }
+    cycles / OPERATIONS as u64
+}
+
+pub fn run() {
+    let slab = churn(
+        |layout| unsafe { ALLOCATOR.alloc(layout) },
+        |ptr, layout| unsafe { ALLOCATOR.dealloc(ptr, layout) },
+    );
+    // same interrupt handling as the global allocator so only the allocation strategy differs
+    let linked_list = churn(
+        |layout| without_interrupts(|| ALLOCATOR.heap.allocate(layout)),
+        |ptr, layout| without_interrupts(|| unsafe { ALLOCATOR.heap.deallocate(ptr, layout) }),
+    );
+    println!("{} alloc/free pairs of 1-{} bytes:", OPERATIONS, MAX_OBJECT_SIZE);
+    println!("slab allocator: {} cycles per pair", slab);
+    println!("linked list allocator: {} cycles per pair", linked_list);
+}
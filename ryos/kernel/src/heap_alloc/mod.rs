@@ -1 +1,3 @@
-pub mod alloc;
\ No newline at end of file
+pub mod alloc;
+pub mod bench;
+pub mod slab;
@@ -0,0 +1,93 @@
+//SLAB ALLOCATOR
+//Small objects are served from per size class free lists, each list is refilled a page at a time
+use core::alloc::Layout;
+use core::ptr::null_mut;
+use spin::Mutex;
+
+// power of two object sizes, anything bigger goes to the linked list heap
+pub const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const SLAB_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlabStats {
+    // pages taken from the heap for slabs, they are never given back
+    pub pages: usize,
+    // objects currently handed out, per size class
+    pub allocated: [usize; SIZE_CLASSES.len()],
+}
+
+struct Lists {
+    // address of the first free object of every class, 0 when the list is empty.
+    // every free object stores the address of the next one in its first 8 bytes
+    free: [usize; SIZE_CLASSES.len()],
+    stats: SlabStats,
+}
+
+pub struct SlabCache {
+    lists: Mutex<Lists>,
+}
+
+impl SlabCache {
+    pub const fn new() -> Self {
+        SlabCache {
+            lists: Mutex::new(Lists {
+                free: [0; SIZE_CLASSES.len()],
+                stats: SlabStats {
+                    pages: 0,
+                    allocated: [0; SIZE_CLASSES.len()],
+                },
+            }),
+        }
+    }
+
+    // index of the smallest class that fits 'layout', None for large objects
+    pub fn class_of(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|&class| size <= class)
+    }
+
+    /*
+    this function pops an object of size class 'class', 'new_page' is called for a fresh page when
+    the class has no free objects left
+     */
+    pub fn allocate(&self, class: usize, new_page: impl FnOnce() -> *mut u8) -> *mut u8 {
+        let mut lists = self.lists.lock();
+        if lists.free[class] == 0 {
+            let page = new_page();
+            if page.is_null() {
+                return null_mut();
+            }
+            lists.stats.pages += 1;
+            // carve the page and chain the objects, the last one ends the list
+            let size = SIZE_CLASSES[class];
+            let count = SLAB_SIZE / size;
+            for i in 0..count {
+                let object = page as usize + i * size;
+                let next = if i + 1 < count { object + size } else { 0 };
+                unsafe { (object as *mut usize).write(next) };
+            }
+            lists.free[class] = page as usize;
+        }
+
+        let object = lists.free[class];
+        lists.free[class] = unsafe { (object as *const usize).read() };
+        lists.stats.allocated[class] += 1;
+        object as *mut u8
+    }
+
+    // put 'ptr' back on the list of its size class, unsafe because it must come from allocate with 'class'
+    pub unsafe fn deallocate(&self, class: usize, ptr: *mut u8) {
+        let mut lists = self.lists.lock();
+        unsafe { (ptr as *mut usize).write(lists.free[class]) };
+        lists.free[class] = ptr as usize;
+        lists.stats.allocated[class] -= 1;
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.lists.lock().stats
+    }
+}
+
+pub fn slab_layout() -> Layout {
+    Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+}
@@ -1,35 +1,52 @@
-
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use lazy_static::lazy_static;
 use x86_64::registers::segmentation::SS;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+// page faults get their own stack so an overflowing task stack can still be reported
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE as u64;
-            stack_end
-        };
-        tss
+// privilege_stack_table[0] is rewritten on every task switch, so the tss can't live behind a shared reference
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+fn init_tss() {
+    let tss = unsafe { &mut *(&raw mut TSS) };
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        let stack_end = stack_start + STACK_SIZE as u64;
+        stack_end
     };
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        stack_start + STACK_SIZE as u64
+    };
+}
+
+// the stack the cpu switches to when an interrupt or exception arrives while user code runs
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { (&raw mut TSS.privilege_stack_table[0]).write_volatile(top) };
 }
 
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
 
 lazy_static! {
+    // syscall and sysret find the segments by their position: kernel data right after kernel code,
+    // user data right before user code
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
         let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        (gdt, Selectors { code_selector, tss_selector, data_selector })
+        let tss_selector = gdt.append(unsafe { Descriptor::tss_segment_unchecked(&raw const TSS) });
+        let user_data_selector = gdt.append(Descriptor::user_data_segment());
+        let user_code_selector = gdt.append(Descriptor::user_code_segment());
+        (gdt, Selectors { code_selector, tss_selector, data_selector, user_code_selector, user_data_selector })
     };
 }
 
@@ -37,6 +54,7 @@ pub fn init() {
     use x86_64::instructions::tables::load_tss;
     use x86_64::instructions::segmentation::{CS, Segment};
 
+    init_tss();
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
@@ -45,8 +63,27 @@ pub fn init() {
     }
 }
 
+pub fn kernel_code_selector() -> SegmentSelector {
+    GDT.1.code_selector
+}
+
+pub fn kernel_data_selector() -> SegmentSelector {
+    GDT.1.data_selector
+}
+
+// the user selectors already carry privilege level 3
+pub fn user_code_selector() -> SegmentSelector {
+    GDT.1.user_code_selector
+}
+
+pub fn user_data_selector() -> SegmentSelector {
+    GDT.1.user_data_selector
+}
+
 struct Selectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
-    data_selector: SegmentSelector
+    data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
 }
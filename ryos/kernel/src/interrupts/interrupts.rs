@@ -1,10 +1,19 @@
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use lazy_static::lazy_static;
 use crate::{println, eprintln, terminal::input::buffer::BUFFER, print};
 use crate::interrupts::gdt;
-use crate::multitasking::round_robin::{schedule, TaskManager, TASK_MANAGER};
+use crate::memory::stack;
+use crate::memory::vmm::{VmError, USER_SPACE_END};
+use crate::syscall;
+use crate::syscall::user_memory;
+use x86_64::{PrivilegeLevel, VirtAddr};
+use crate::multitasking::{process, signal};
+use crate::multitasking::round_robin::{self, schedule, CurrentTask, TaskManager, TASK_MANAGER};
 use pic8259::ChainedPics;
 use spin;
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::rflags::RFlags;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -26,19 +35,102 @@ impl InterruptIndex {
 
 }
 
+// irq lines 0 and 1 belong to the timer and keyboard, the rest can be claimed by drivers
+static IRQ_HANDLERS: spin::Mutex<[Option<fn()>; 16]> = spin::Mutex::new([None; 16]);
+
+macro_rules! irq_handler {
+    ($name:ident, $line:expr) => {
+        extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+            dispatch_irq($line);
+        }
+    };
+}
+
+irq_handler!(irq2_handler, 2);
+irq_handler!(irq3_handler, 3);
+irq_handler!(irq4_handler, 4);
+irq_handler!(irq5_handler, 5);
+irq_handler!(irq6_handler, 6);
+irq_handler!(irq7_handler, 7);
+irq_handler!(irq8_handler, 8);
+irq_handler!(irq9_handler, 9);
+irq_handler!(irq10_handler, 10);
+irq_handler!(irq11_handler, 11);
+irq_handler!(irq12_handler, 12);
+irq_handler!(irq13_handler, 13);
+irq_handler!(irq14_handler, 14);
+irq_handler!(irq15_handler, 15);
+
+const IRQ_LINE_HANDLERS: [(u8, extern "x86-interrupt" fn(InterruptStackFrame)); 14] = [
+    (2, irq2_handler),
+    (3, irq3_handler),
+    (4, irq4_handler),
+    (5, irq5_handler),
+    (6, irq6_handler),
+    (7, irq7_handler),
+    (8, irq8_handler),
+    (9, irq9_handler),
+    (10, irq10_handler),
+    (11, irq11_handler),
+    (12, irq12_handler),
+    (13, irq13_handler),
+    (14, irq14_handler),
+    (15, irq15_handler),
+];
+
+fn dispatch_irq(line: u8) {
+    let handler = IRQ_HANDLERS.lock()[line as usize];
+    if let Some(handler) = handler {
+        handler();
+    }
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + line);
+    }
+}
+
+// call 'handler' whenever irq 'line' fires and unmask the line on the pics
+pub fn register_irq_handler(line: u8, handler: fn()) {
+    assert!((2..16).contains(&line), "irq line {} can't be claimed", line);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        IRQ_HANDLERS.lock()[line as usize] = Some(handler);
+        let mut pics = PICS.lock();
+        unsafe {
+            let [mut primary, mut secondary] = pics.read_masks();
+            if line < 8 {
+                primary &= !(1 << line);
+            } else {
+                // the secondary pic is chained through line 2 of the primary
+                primary &= !(1 << 2);
+                secondary &= !(1 << (line - 8));
+            }
+            pics.write_masks(primary, secondary);
+        }
+    });
+}
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt[InterruptIndex::Timer.as_u8()]
             .set_handler_fn(timer_interrupt_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
         idt[InterruptIndex::Keyboard.as_u8()]
             .set_handler_fn(keyboard_interrupt_handler);
+        for (line, handler) in IRQ_LINE_HANDLERS {
+            idt[PIC_1_OFFSET + line].set_handler_fn(handler);
+        }
 
         unsafe {
+            idt[syscall::entry::INT80_VECTOR]
+                .set_handler_addr(VirtAddr::new(syscall::entry::int80_entry as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3);
+            idt.divide_error.set_handler_fn(divide_error_handler);
+            idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+            idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
             idt.double_fault.set_handler_fn(double_fault_handler)
               .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt.page_fault.set_handler_fn(page_fault_handler)
+              .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
         }
 
         idt
@@ -55,15 +147,28 @@ extern "x86-interrupt" fn breakpoint_handler(
     eprintln!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+// the pit isn't programmed, so it keeps its default rate of 1193182 / 65536 interrupts per second
+const PIT_FREQUENCY: u64 = 1193182;
+const PIT_DIVISOR: u64 = 65536;
+
+// timer interrupts since the interrupts were enabled
+pub static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn uptime_ms() -> u64 {
+    TICKS.load(Ordering::Relaxed) * PIT_DIVISOR * 1000 / PIT_FREQUENCY
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(
-    _stack_frame: InterruptStackFrame)
+    mut stack_frame: InterruptStackFrame)
 {
-
+    TICKS.fetch_add(1, Ordering::Relaxed);
 
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+    // a program killed while it computes in user mode doesn't make a system call to notice it
+    signal::terminate_interrupted(&mut stack_frame);
     schedule();
 
 }
@@ -78,7 +183,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     lazy_static! {
         static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
             Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1,
-                HandleControl::Ignore)
+                HandleControl::MapLettersToUnicode)
             );
     }
 
@@ -90,6 +195,9 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
         if let Some(key) = keyboard.process_keyevent(key_event) {
             match key {
+                DecodedKey::Unicode(signal::INTERRUPT_KEY) => signal::interrupt_foreground(),
+                // the other ctrl combinations aren't used
+                DecodedKey::Unicode(character) if character.is_control() && !matches!(character, '\n' | '\t' | '\x08' | '\x7f') => {},
                 DecodedKey::Unicode(character) => x86_64::instructions::interrupts::without_interrupts(|| {BUFFER.lock().add_char(character);}),
                 DecodedKey::RawKey(key) => {
                     if key == pc_keyboard::KeyCode::ArrowUp {
@@ -106,16 +214,184 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     }
 }
 
+/*
+this function kills the running task for an exception raised by its user code, false when the
+exception came from the kernel
+ */
+fn kill_user_task(stack_frame: &mut InterruptStackFrame, exception: &str) -> bool {
+    if stack_frame.code_segment.rpl() != PrivilegeLevel::Ring3 {
+        return false;
+    }
+    let Some(CurrentTask { id, stack_top: Some(stack_top), .. }) = round_robin::current_task() else {
+        return false;
+    };
+    eprintln!("task {} killed: {} at {:?}", id, exception, stack_frame.instruction_pointer);
+    round_robin::kill_current(stack_frame, stack_top);
+    true
+}
+
+extern "x86-interrupt" fn divide_error_handler(mut stack_frame: InterruptStackFrame) {
+    if !kill_user_task(&mut stack_frame, "divide error") {
+        panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+    }
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(mut stack_frame: InterruptStackFrame) {
+    if !kill_user_task(&mut stack_frame, "invalid opcode") {
+        panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+    }
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(mut stack_frame: InterruptStackFrame, error_code: u64) {
+    if !kill_user_task(&mut stack_frame, "general protection fault") {
+        panic!("EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}", error_code, stack_frame);
+    }
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64) -> !
 {
     panic!("EXCEPTION: DOUBLE FAULT ");
 }
+/*
+this function hands faults on user memory to the faulting task, which maps lazily backed pages and
+is killed on invalid accesses. that is only done for user code and for the kernel's copy to and from
+user memory, any other kernel fault on user memory is a bug and fatal. other faults kill the task,
+only faults of the main kernel task are fatal
+ */
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame, error_code: x86_64::structures::idt::PageFaultErrorCode
+    mut stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode
 )
 {
+    use x86_64::registers::control::Cr2;
 
-     eprintln!("error code {:?}", error_code);
+    let address = VirtAddr::new_truncate(Cr2::read_raw());
+    let task = round_robin::current_task();
+    if address.as_u64() < USER_SPACE_END {
+        let user_access = error_code.contains(PageFaultErrorCode::USER_MODE)
+            || user_memory::faulted_in_copy(stack_frame.instruction_pointer);
+        if !user_access {
+            eprintln!("error code {:?} accessing {:?}", error_code, address);
+            panic!("EXCEPTION: PAGE FAULT ON USER MEMORY IN THE KERNEL {:?}", stack_frame);
+        }
+        if let Some(task) = task.as_ref().filter(|task| task.address_space.is_some()) {
+            if retry_in_task(&mut stack_frame, task, address, error_code) {
+                return;
+            }
+        }
+    }
+
+    let overflow = stack::guard_owner(address);
+    if let Some(CurrentTask { id, stack_top: Some(stack_top), .. }) = task {
+        if overflow == Some(id) {
+            eprintln!("stack overflow in task {}", id);
+        } else {
+            eprintln!("task {} killed: page fault at {:?} ({:?})", id, address, error_code);
+        }
+        round_robin::kill_current(&mut stack_frame, stack_top);
+        return;
+    }
+
+    if let Some(owner) = overflow {
+        eprintln!("stack overflow in task {}", owner);
+    }
+     eprintln!("error code {:?} accessing {:?}", error_code, address);
     panic!("EXCEPTION: PAGE FAULT {:?}", stack_frame);
+}
+
+/*
+this function makes the faulting task handle the fault itself. the handler runs on the page fault
+stack with interrupts disabled, but bringing a page in may wait for the address space lock, evict
+pages and read the disk. the address, the error code and a frame back to the faulting instruction go
+on the task's own stack and the handler returns to page_fault_entry there. false when the task
+can't wait because it ran with interrupts disabled
+ */
+fn retry_in_task(
+    stack_frame: &mut InterruptStackFrame,
+    task: &CurrentTask,
+    address: VirtAddr,
+    error_code: PageFaultErrorCode,
+) -> bool {
+    let top = match (stack_frame.code_segment.rpl(), task.stack_top) {
+        (PrivilegeLevel::Ring3, Some(top)) => top.as_u64(),
+        // the kernel is built without a red zone, everything below its stack pointer is free
+        (PrivilegeLevel::Ring0, _) if stack_frame.cpu_flags.contains(RFlags::INTERRUPT_FLAG) => {
+            stack_frame.stack_pointer.as_u64()
+        }
+        _ => return false,
+    };
+    let retry = [
+        address.as_u64(),
+        error_code.bits(),
+        stack_frame.instruction_pointer.as_u64(),
+        stack_frame.code_segment.0 as u64,
+        stack_frame.cpu_flags.bits(),
+        stack_frame.stack_pointer.as_u64(),
+        stack_frame.stack_segment.0 as u64,
+    ];
+    // aligned like the cpu aligns an interrupt frame
+    let base = (top & !0xF) - size_of_val(&retry) as u64;
+    unsafe {
+        (base as *mut [u64; 7]).write(retry);
+        stack_frame.as_mut().update(|frame| {
+            frame.instruction_pointer = VirtAddr::new(page_fault_entry as *const () as u64);
+            frame.stack_pointer = VirtAddr::new(base);
+            frame.code_segment = gdt::kernel_code_selector();
+            frame.stack_segment = gdt::kernel_data_selector();
+            // interrupts stay disabled until handle_task_fault and the rust code needs the direction flag cleared
+            frame.cpu_flags = RFlags::empty();
+        });
+    }
+    true
+}
+
+// saves the registers the rust code may change, handles the fault and returns to the faulting instruction
+#[naked]
+unsafe extern "C" fn page_fault_entry() {
+    naked_asm!(
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        // the address and the error code retry_in_task put below the frame
+        "mov rdi, [rsp + 72]",
+        "mov rsi, [rsp + 80]",
+        "call {handle}",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        "add rsp, 16",
+        "iretq",
+        handle = sym handle_task_fault,
+    );
+}
+
+/*
+this function runs in the faulting task with interrupts enabled, so it can wait for the address
+space when another task holds it. the task ends when the fault can't be handled
+ */
+extern "C" fn handle_task_fault(address: u64, error_code: u64) {
+    x86_64::instructions::interrupts::enable();
+    let address = VirtAddr::new_truncate(address);
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    let result = match round_robin::current_task().and_then(|task| task.address_space) {
+        Some(space) => space.lock().handle_fault(address, error_code),
+        None => Err(VmError::NotMapped),
+    };
+    if let Err(e) = result {
+        eprintln!("task {} killed: page fault at {:?} ({:?}): {:?}", process::current_pid(), address, error_code, e);
+        round_robin::exit_killed_task();
+    }
+    x86_64::instructions::interrupts::disable();
 }
\ No newline at end of file
@@ -0,0 +1,120 @@
+//CAPABILITIES
+//A process can only use a port through a handle in its capability table, and the rights of the handle
+//decide what it may do: send calls to the port, receive them or pass the handle on in a message.
+//Handles come from creating a port, looking up a registered name (send only), a message or fork
+use crate::ipc::port::Port;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+// the rights of a capability
+pub const SEND: u64 = 1;
+pub const RECEIVE: u64 = 2;
+// the capability may be attached to a message
+pub const GRANT: u64 = 4;
+pub const ALL_RIGHTS: u64 = SEND | RECEIVE | GRANT;
+
+// handles a process can have at once
+pub const MAX_CAPABILITIES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CapabilityError {
+    // no capability with that handle
+    BadHandle,
+    // the capability lacks a right the operation needs
+    NotPermitted,
+}
+
+pub struct Capability {
+    port: Arc<Port>,
+    rights: u64,
+}
+
+impl Capability {
+    // the port counts its receive rights, when the last one is gone it is closed
+    pub fn new(port: Arc<Port>, rights: u64) -> Self {
+        if rights & RECEIVE != 0 {
+            port.add_receiver();
+        }
+        Capability { port, rights }
+    }
+
+    pub fn port(&self) -> &Arc<Port> {
+        &self.port
+    }
+
+    pub fn check(&self, rights: u64) -> Result<(), CapabilityError> {
+        match self.rights & rights == rights {
+            true => Ok(()),
+            false => Err(CapabilityError::NotPermitted),
+        }
+    }
+
+    // a capability for the same port with only some of the rights of this one
+    pub fn restrict(&self, rights: u64) -> Result<Capability, CapabilityError> {
+        self.check(rights)?;
+        Ok(Capability::new(self.port.clone(), rights))
+    }
+}
+
+impl Clone for Capability {
+    fn clone(&self) -> Self {
+        Capability::new(self.port.clone(), self.rights)
+    }
+}
+
+impl Drop for Capability {
+    fn drop(&mut self) {
+        if self.rights & RECEIVE != 0 {
+            self.port.remove_receiver();
+        }
+    }
+}
+
+// cloning the table clones every capability, like the table a forked child gets
+#[derive(Clone)]
+pub struct CapabilityTable {
+    capabilities: Vec<Option<Capability>>,
+}
+
+impl CapabilityTable {
+    pub fn new() -> Self {
+        CapabilityTable { capabilities: Vec::new() }
+    }
+
+    pub fn get(&self, handle: u64) -> Result<&Capability, CapabilityError> {
+        self.capabilities
+            .get(handle as usize)
+            .and_then(Option::as_ref)
+            .ok_or(CapabilityError::BadHandle)
+    }
+
+    /*
+    this function gives 'capability' the lowest free handle. when MAX_CAPABILITIES are in use it is
+    given back, so the caller drops it outside of the process table lock
+     */
+    pub fn insert(&mut self, capability: Capability) -> Result<u64, Capability> {
+        match self.capabilities.iter().position(Option::is_none) {
+            Some(handle) => {
+                self.capabilities[handle] = Some(capability);
+                Ok(handle as u64)
+            }
+            None if self.capabilities.len() < MAX_CAPABILITIES => {
+                self.capabilities.push(Some(capability));
+                Ok(self.capabilities.len() as u64 - 1)
+            }
+            None => Err(capability),
+        }
+    }
+
+    // the caller drops the capability, which may close its port
+    pub fn remove(&mut self, handle: u64) -> Result<Capability, CapabilityError> {
+        self.capabilities
+            .get_mut(handle as usize)
+            .and_then(Option::take)
+            .ok_or(CapabilityError::BadHandle)
+    }
+
+    pub fn count(&self) -> usize {
+        self.capabilities.iter().filter(|capability| capability.is_some()).count()
+    }
+}
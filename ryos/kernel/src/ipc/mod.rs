@@ -0,0 +1,5 @@
+//IPC
+//Message passing between processes: ports that take synchronous calls, with pages and capabilities
+//attached, and the capability tables that decide which ports a process may use
+pub mod capability;
+pub mod port;
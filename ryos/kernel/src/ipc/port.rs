@@ -0,0 +1,278 @@
+//PORTS
+//A port is a queue of calls. A client makes a call through a capability with the send right and waits
+//until the server that received it replies, the server takes the calls from the queue through a
+//capability with the receive right. A call can carry pages of the client's memory, which are mapped
+//into the server, and a capability. Servers register their ports under a name so clients can find them
+use crate::ipc::capability::Capability;
+use crate::memory::vmm::SharedPages;
+use crate::multitasking::{process, round_robin};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+// bytes of a call or a reply
+pub const MAX_MESSAGE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpcError {
+    // every receive right of the port is gone, or the server exited before it replied
+    Closed,
+    TooBig,
+    // a signal arrived while the process waited
+    Interrupted,
+    // the token doesn't belong to a call that waits for a reply
+    NoCall,
+    // the call was received by another process
+    NotPermitted,
+    NameExists,
+    NameNotFound,
+}
+
+pub struct Port {
+    id: u64,
+    state: Mutex<PortState>,
+}
+
+struct PortState {
+    queue: VecDeque<Message>,
+    receivers: usize,
+}
+
+// a call as the server receives it
+pub struct Message {
+    // identifies the call in the reply
+    pub token: u64,
+    pub sender: usize,
+    pub data: Vec<u8>,
+    pub pages: Option<SharedPages>,
+    pub capability: Option<Capability>,
+}
+
+// a call between send and reply, only the caller removes it
+struct Call {
+    receiver: Option<usize>,
+    reply: Option<Result<Vec<u8>, IpcError>>,
+}
+
+// ids of ports and tokens of calls
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static CALLS: Mutex<BTreeMap<u64, Call>> = Mutex::new(BTreeMap::new());
+static NAMES: Mutex<BTreeMap<String, Arc<Port>>> = Mutex::new(BTreeMap::new());
+
+// the ipc locks are used by tasks that get preempted, the timer must not switch away while one is held
+fn with_calls<R>(f: impl FnOnce(&mut BTreeMap<u64, Call>) -> R) -> R {
+    without_interrupts(|| f(&mut CALLS.lock()))
+}
+
+fn with_names<R>(f: impl FnOnce(&mut BTreeMap<String, Arc<Port>>) -> R) -> R {
+    without_interrupts(|| f(&mut NAMES.lock()))
+}
+
+impl Port {
+    pub fn new() -> Arc<Port> {
+        Arc::new(Port {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            state: Mutex::new(PortState {
+                queue: VecDeque::new(),
+                receivers: 0,
+            }),
+        })
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut PortState) -> R) -> R {
+        without_interrupts(|| f(&mut self.state.lock()))
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.with_state(|state| state.receivers != 0)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.with_state(|state| state.queue.len())
+    }
+
+    pub(super) fn add_receiver(&self) {
+        self.with_state(|state| state.receivers += 1);
+    }
+
+    // the last receive right closes the port, the queued calls fail
+    pub(super) fn remove_receiver(&self) {
+        let dropped = self.with_state(|state| {
+            state.receivers -= 1;
+            match state.receivers {
+                0 => core::mem::take(&mut state.queue),
+                _ => VecDeque::new(),
+            }
+        });
+        // messages hold pages and capabilities, they are released without the port locked
+        drop(dropped);
+    }
+
+    // take the call 'token' out of the queue if nobody received it yet
+    fn withdraw(&self, token: u64) -> Option<Message> {
+        self.with_state(|state| {
+            let index = state.queue.iter().position(|message| message.token == token)?;
+            state.queue.remove(index)
+        })
+    }
+}
+
+/*
+this function queues a call with 'data' and the attachments on 'port' and waits for the reply. it
+fails when the port closes or the server exits first, and a signal for the caller ends the wait
+ */
+pub fn call(
+    port: &Arc<Port>,
+    data: Vec<u8>,
+    pages: Option<SharedPages>,
+    capability: Option<Capability>,
+) -> Result<Vec<u8>, IpcError> {
+    if data.len() > MAX_MESSAGE {
+        return Err(IpcError::TooBig);
+    }
+    let caller = process::current_pid();
+    let token = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    with_calls(|calls| calls.insert(token, Call { receiver: None, reply: None }));
+    let message = Message { token, sender: caller, data, pages, capability };
+    let refused = port.with_state(|state| match state.receivers {
+        0 => Some(message),
+        _ => {
+            state.queue.push_back(message);
+            None
+        }
+    });
+    if refused.is_some() {
+        with_calls(|calls| calls.remove(&token));
+        return Err(IpcError::Closed);
+    }
+
+    loop {
+        let (reply, receiver) = with_calls(|calls| {
+            let call = calls.get_mut(&token).expect("call vanished before its reply");
+            (call.reply.take(), call.receiver)
+        });
+        if let Some(reply) = reply {
+            with_calls(|calls| calls.remove(&token));
+            return reply;
+        }
+        let gone = match receiver {
+            Some(pid) => !process::is_running(pid),
+            None => !port.is_open(),
+        };
+        let error = match gone {
+            true => Some(IpcError::Closed),
+            false => process::signal_pending(caller).then_some(IpcError::Interrupted),
+        };
+        if let Some(error) = error {
+            drop(port.withdraw(token));
+            with_calls(|calls| calls.remove(&token));
+            return Err(error);
+        }
+        without_interrupts(round_robin::schedule);
+    }
+}
+
+/*
+this function waits for the next call on 'port' and takes it out of the queue, the running process
+has to reply to it. a call larger than 'max_len' stays queued and TooBig is returned
+ */
+pub fn receive(port: &Arc<Port>, max_len: usize) -> Result<Message, IpcError> {
+    let receiver = process::current_pid();
+    loop {
+        let message = port.with_state(|state| match state.queue.front() {
+            Some(message) if message.data.len() > max_len => Err(IpcError::TooBig),
+            _ => Ok(state.queue.pop_front()),
+        })?;
+        if let Some(message) = message {
+            // the caller may have given up since it queued the call
+            let claimed = with_calls(|calls| match calls.get_mut(&message.token) {
+                Some(call) => {
+                    call.receiver = Some(receiver);
+                    true
+                }
+                None => false,
+            });
+            if claimed {
+                return Ok(message);
+            }
+            continue;
+        }
+        if process::signal_pending(receiver) {
+            return Err(IpcError::Interrupted);
+        }
+        without_interrupts(round_robin::schedule);
+    }
+}
+
+// answer the call 'token' the running process received, the caller continues with 'result'
+fn complete(token: u64, result: Result<Vec<u8>, IpcError>) -> Result<(), IpcError> {
+    let pid = process::current_pid();
+    with_calls(|calls| {
+        let call = calls.get_mut(&token).ok_or(IpcError::NoCall)?;
+        if call.receiver != Some(pid) {
+            return Err(IpcError::NotPermitted);
+        }
+        if call.reply.is_some() {
+            return Err(IpcError::NoCall);
+        }
+        call.reply = Some(result);
+        Ok(())
+    })
+}
+
+pub fn reply(token: u64, data: Vec<u8>) -> Result<(), IpcError> {
+    if data.len() > MAX_MESSAGE {
+        return Err(IpcError::TooBig);
+    }
+    complete(token, Ok(data))
+}
+
+// the call 'token' fails with 'error', for calls the kernel couldn't hand to the server
+pub fn abort(token: u64, error: IpcError) -> Result<(), IpcError> {
+    complete(token, Err(error))
+}
+
+// clients find 'port' under 'name' until it closes
+pub fn register(name: &str, port: &Arc<Port>) -> Result<(), IpcError> {
+    with_names(|names| {
+        if names.get(name).is_some_and(|registered| registered.is_open()) {
+            return Err(IpcError::NameExists);
+        }
+        names.insert(String::from(name), port.clone());
+        Ok(())
+    })
+}
+
+pub fn lookup(name: &str) -> Result<Arc<Port>, IpcError> {
+    with_names(|names| names.get(name).filter(|port| port.is_open()).cloned()).ok_or(IpcError::NameNotFound)
+}
+
+// what the ports command shows about a registered port
+pub struct PortInfo {
+    pub name: String,
+    pub id: u64,
+    pub queued: usize,
+}
+
+// the registered ports that are still open
+pub fn list() -> Vec<PortInfo> {
+    with_names(|names| {
+        names.retain(|_, port| port.is_open());
+        names
+            .iter()
+            .map(|(name, port)| PortInfo {
+                name: name.clone(),
+                id: port.id(),
+                queued: port.queued(),
+            })
+            .collect()
+    })
+}
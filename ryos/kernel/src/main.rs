@@ -2,6 +2,7 @@
 #![no_main]
 #![feature(abi_x86_interrupt)]
 #![feature(naked_functions)]
+#![feature(alloc_error_handler)]
 extern crate alloc;
 
 use crate::file_system::fat16::FAtApi;
@@ -16,6 +17,8 @@ use x86_64::VirtAddr;
 static BOOT_CONFIG: bootloader_api::BootloaderConfig = {
     let mut config = bootloader_api::BootloaderConfig::new_default();
     config.mappings.physical_memory = Some(bootloader_api::config::Mapping::new_default());
+    // keep everything the bootloader places itself in the upper half, the lower half is for address spaces
+    config.mappings.dynamic_range_start = Some(0xffff_8000_0000_0000);
     config.kernel_stack_size = 130 * 1024;
     config
 };
@@ -23,18 +26,24 @@ bootloader_api::entry_point!(kernel_main, config = &BOOT_CONFIG);
 mod file_system;
 mod heap_alloc;
 mod interrupts;
+mod ipc;
 mod memory;
 mod multitasking;
+mod pci;
+mod selftest;
+mod syscall;
 mod terminal;
 
 extern "C" fn testa() {
     for _ in 0..50 {
+        multitasking::signal::exit_if_terminated();
         print!("aaaaaaa");
     }
 }
 
 extern "C" fn testb() {
     for _ in 0..50 {
+        multitasking::signal::exit_if_terminated();
         print!("bbbbbbb");
     }
 }
@@ -42,8 +51,9 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     init(boot_info);
     println!("\n\nreal main");
 
-    let fat = FAtApi::new();
-    let mut terminal = terminal::interface::Terminal::new(fat);
+    file_system::fat16::set_volume(FAtApi::new());
+    file_system::mount::mount_programs();
+    let mut terminal = terminal::interface::Terminal::new();
     loop {
         terminal.run();
         x86_64::instructions::hlt();
@@ -55,10 +65,14 @@ fn panic(_info: &PanicInfo) -> ! {
     eprintln!("{}", _info);
     hlt_loop();
 }
-fn test_multitasking() {
-    multitasking::round_robin::add_task(testb);
-    multitasking::round_robin::add_task(testa);
+// returns the pids of the tasks that could be started, the shell waits for them
+fn test_multitasking() -> alloc::vec::Vec<usize> {
+    [testb as extern "C" fn(), testa]
+        .into_iter()
+        .filter_map(multitasking::round_robin::add_task)
+        .collect()
 }
+
 fn init(boot_info: &'static mut BootInfo) {
     let frame_buffer_optional = &mut boot_info.framebuffer;
 
@@ -79,6 +93,7 @@ fn init(boot_info: &'static mut BootInfo) {
 fn init_interrupts() {
     interrupts::gdt::init();
     interrupts::interrupts::init_idt();
+    syscall::entry::init();
     unsafe { interrupts::interrupts::PICS.lock().initialize() }
     x86_64::instructions::interrupts::enable();
 }
@@ -87,10 +102,18 @@ fn init_memory(boot_info: &'static mut BootInfo) {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.clone().take().unwrap());
     let mut mapper = unsafe { memory::paging::init(phys_mem_offset) };
     let mut frame_allocator =
-        unsafe { memory::paging::BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+        unsafe { memory::frame_allocator::BitmapFrameAllocator::init(&boot_info.memory_regions) };
 
-    heap_alloc::alloc::init_heap(&mut frame_allocator, &mut mapper)
+    heap_alloc::alloc::init_heap(heap_alloc::alloc::initial_size(), &mut frame_allocator, &mut mapper)
         .expect("error initializing heap");
+    let stats = frame_allocator.stats();
+    println!(
+        "physical memory: {} KiB used, {} KiB free",
+        stats.used() * 4,
+        stats.free * 4
+    );
+    memory::paging::store(mapper, frame_allocator);
+    memory::vmm::init();
 }
 
 pub fn hlt_loop() -> ! {
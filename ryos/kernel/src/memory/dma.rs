@@ -0,0 +1,65 @@
+//DMA MEMORY
+//Physically contiguous memory that devices can read and write directly
+use crate::memory::paging::{phys_to_virt, FRAME_ALLOCATOR};
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{PhysAddr, VirtAddr};
+
+const PAGE_SIZE: usize = 4096;
+// devices that only take 32 bit addresses need their memory in the first 4 GiB
+const LIMIT_32BIT: u64 = 1 << 32;
+
+pub struct DmaRegion {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    size: usize,
+}
+
+impl DmaRegion {
+    // allocate 'pages' contiguous zeroed pages, None when there is no such run of free frames
+    pub fn allocate(pages: usize) -> Option<Self> {
+        Self::allocate_below(pages, u64::MAX)
+    }
+
+    // like allocate, for devices that can only reach physical addresses below 4 GiB
+    pub fn allocate_32bit(pages: usize) -> Option<Self> {
+        Self::allocate_below(pages, LIMIT_32BIT)
+    }
+
+    fn allocate_below(pages: usize, limit: u64) -> Option<Self> {
+        let frame = FRAME_ALLOCATOR.lock().as_mut()?.allocate_contiguous_below(pages, limit)?;
+        let phys = frame.start_address();
+        let region = DmaRegion {
+            phys,
+            virt: phys_to_virt(phys),
+            size: pages * PAGE_SIZE,
+        };
+        unsafe {
+            core::ptr::write_bytes(region.virt.as_mut_ptr::<u8>(), 0, region.size);
+        }
+        Some(region)
+    }
+
+    // physical address of 'offset' inside the region, this is what the device gets
+    pub fn phys(&self, offset: usize) -> u64 {
+        self.phys.as_u64() + offset as u64
+    }
+
+    pub fn ptr<T>(&self, offset: usize) -> *mut T {
+        debug_assert!(offset + core::mem::size_of::<T>() <= self.size);
+        (self.virt + offset as u64).as_mut_ptr()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+            unsafe {
+                allocator.deallocate_contiguous(PhysFrame::containing_address(self.phys), self.size / PAGE_SIZE);
+            }
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//FRAME ALLOCATOR
+//Bitmap of every physical frame up to the end of usable memory, one bit per frame (1 = used),
+//next to it a share count per frame for frames mapped by several address spaces (copy on write)
+use crate::memory::paging::phys_to_virt;
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+pub const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    // frames the bootloader reported as usable
+    pub total: usize,
+    pub free: usize,
+    // reserved frames, by the bootloader (kernel image, page tables, boot info) and by the firmware
+    pub bootloader: usize,
+    pub firmware: usize,
+    // frames with more than one owner
+    pub shared: usize,
+}
+
+impl FrameStats {
+    pub fn used(&self) -> usize {
+        self.total - self.free
+    }
+}
+
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    // owners of every used frame besides the first one, a shared frame is only freed by its last owner
+    shares: &'static mut [u16],
+    frames: usize,
+    total: usize,
+    free: usize,
+    bootloader: usize,
+    firmware: usize,
+    shared: usize,
+    // word to start the next single frame search from
+    next_word: usize,
+}
+
+impl BitmapFrameAllocator {
+    /*
+    this function builds the bitmap from the memory map, the bitmap and the share counts are stored
+    in the first usable region big enough for them and accessed through the physical memory mapping.
+    unsafe because every frame marked as usable must really be unused
+     */
+    pub unsafe fn init(memory_map: &'static MemoryRegions) -> Self {
+        let usable = || memory_map.iter().filter(|r| r.kind == MemoryRegionKind::Usable);
+        let end = usable().map(|r| r.end).max().unwrap_or(0);
+        let frames = (end / FRAME_SIZE) as usize;
+        let words = frames.div_ceil(BITS_PER_WORD);
+        let bitmap_bytes = (words * 8) as u64;
+        let shares_bytes = (frames * 2) as u64;
+        let reserved_bytes = bitmap_bytes + shares_bytes;
+
+        let region = usable()
+            .find(|r| first_frame(r.start) * FRAME_SIZE + reserved_bytes <= r.end)
+            .expect("no usable memory region can hold the frame bitmap");
+        let bitmap_start = PhysAddr::new(first_frame(region.start) * FRAME_SIZE);
+        let bitmap =
+            unsafe { core::slice::from_raw_parts_mut(phys_to_virt(bitmap_start).as_mut_ptr::<u64>(), words) };
+        let shares_start = phys_to_virt(bitmap_start) + bitmap_bytes;
+        let shares = unsafe { core::slice::from_raw_parts_mut(shares_start.as_mut_ptr::<u16>(), frames) };
+        shares.fill(0);
+
+        // everything starts used, then the usable regions are released
+        bitmap.fill(u64::MAX);
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            shares,
+            frames,
+            total: 0,
+            free: 0,
+            bootloader: 0,
+            firmware: 0,
+            shared: 0,
+            next_word: 0,
+        };
+        for region in memory_map.iter() {
+            let frames = ((region.end - region.start) / FRAME_SIZE) as usize;
+            match region.kind {
+                MemoryRegionKind::Usable => {}
+                MemoryRegionKind::Bootloader => allocator.bootloader += frames,
+                _ => allocator.firmware += frames,
+            }
+        }
+        for region in usable() {
+            for frame in first_frame(region.start)..region.end / FRAME_SIZE {
+                allocator.set_free(frame as usize);
+                allocator.total += 1;
+            }
+        }
+
+        // and the frames holding the bitmap and the share counts are taken again
+        let bitmap_first = (bitmap_start.as_u64() / FRAME_SIZE) as usize;
+        for frame in bitmap_first..bitmap_first + reserved_bytes.div_ceil(FRAME_SIZE) as usize {
+            allocator.set_used(frame);
+        }
+        allocator
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize) {
+        debug_assert!(!self.is_used(frame));
+        self.bitmap[frame / BITS_PER_WORD] |= 1 << (frame % BITS_PER_WORD);
+        self.free -= 1;
+    }
+
+    fn set_free(&mut self, frame: usize) {
+        debug_assert!(self.is_used(frame));
+        self.bitmap[frame / BITS_PER_WORD] &= !(1 << (frame % BITS_PER_WORD));
+        self.free += 1;
+    }
+
+    fn to_frame(index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+    }
+
+    fn to_index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / FRAME_SIZE) as usize
+    }
+
+    // allocate 'count' physically contiguous frames and return the first one
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        self.allocate_contiguous_below(count, u64::MAX)
+    }
+
+    // like allocate_contiguous, but every frame ends at or below the physical address 'limit'
+    pub fn allocate_contiguous_below(&mut self, count: usize, limit: u64) -> Option<PhysFrame> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+        let frames = self.frames.min((limit / FRAME_SIZE) as usize);
+        let mut run_start = 0;
+        let mut run_length = 0;
+        for frame in 0..frames {
+            if self.is_used(frame) {
+                run_length = 0;
+                continue;
+            }
+            if run_length == 0 {
+                run_start = frame;
+            }
+            run_length += 1;
+            if run_length == count {
+                for taken in run_start..run_start + count {
+                    self.set_used(taken);
+                }
+                return Some(Self::to_frame(run_start));
+            }
+        }
+        None
+    }
+
+    /*
+    this function gives back 'count' frames starting at 'first', as returned by allocate_contiguous.
+    shared frames only lose one owner and stay allocated. unsafe because the frames must not be in use
+    anymore
+     */
+    pub unsafe fn deallocate_contiguous(&mut self, first: PhysFrame, count: usize) {
+        let first = Self::to_index(first);
+        for frame in first..first + count {
+            match self.shares[frame] {
+                0 => self.set_free(frame),
+                1 => {
+                    self.shares[frame] = 0;
+                    self.shared -= 1;
+                }
+                _ => self.shares[frame] -= 1,
+            }
+        }
+        self.next_word = self.next_word.min(first / BITS_PER_WORD);
+    }
+
+    // add an owner to the used frame 'frame', false when it already has the maximum number of owners
+    pub fn share(&mut self, frame: PhysFrame) -> bool {
+        let index = Self::to_index(frame);
+        debug_assert!(self.is_used(index));
+        match self.shares[index] {
+            u16::MAX => false,
+            0 => {
+                self.shares[index] = 1;
+                self.shared += 1;
+                true
+            }
+            _ => {
+                self.shares[index] += 1;
+                true
+            }
+        }
+    }
+
+    pub fn is_shared(&self, frame: PhysFrame) -> bool {
+        self.shares[Self::to_index(frame)] != 0
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            free: self.free,
+            bootloader: self.bootloader,
+            firmware: self.firmware,
+            shared: self.shared,
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let words = self.bitmap.len();
+        for offset in 0..words {
+            let word = (self.next_word + offset) % words;
+            if self.bitmap[word] == u64::MAX {
+                continue;
+            }
+            let frame = word * BITS_PER_WORD + self.bitmap[word].trailing_ones() as usize;
+            if frame >= self.frames {
+                continue;
+            }
+            self.set_used(frame);
+            self.next_word = word;
+            return Some(Self::to_frame(frame));
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        unsafe { self.deallocate_contiguous(frame, 1) }
+    }
+}
+
+// first whole frame at or after 'address', frame 0 stays used so a physical address of 0 is never handed out
+fn first_frame(address: u64) -> u64 {
+    address.div_ceil(FRAME_SIZE).max(1)
+}
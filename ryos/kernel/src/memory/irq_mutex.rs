@@ -0,0 +1,53 @@
+//IRQ MUTEX
+//A spin lock that keeps interrupts disabled while it is held. The paging locks are taken by the heap,
+//which runs with interrupts disabled, so a holder must never be interrupted and descheduled
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+pub struct IrqMutex<T> {
+    inner: Mutex<T>,
+}
+
+// interrupts are enabled again when the guard is dropped if they were enabled before the lock
+pub struct IrqMutexGuard<'a, T> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    enable: bool,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        IrqMutex { inner: Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let enable = interrupts::are_enabled();
+        interrupts::disable();
+        IrqMutexGuard { guard: ManuallyDrop::new(self.inner.lock()), enable }
+    }
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // the lock is released before interrupts come back
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.enable {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//MEMORY MAPPED FILES
+//A file seen as the list of its sectors, the pages of a mapping are read from them on their first
+//access and written back to them when they are dirty
+use crate::file_system::block_device::BlockDevice;
+use crate::file_system::disk_driver::SECTOR_SIZE;
+use crate::file_system::errors::FileSystemError;
+use crate::memory::frame_allocator::FRAME_SIZE;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+const SECTORS_PER_PAGE: u64 = FRAME_SIZE / SECTOR_SIZE as u64;
+
+pub struct MappedFile {
+    device: Arc<dyn BlockDevice>,
+    // sector holding every 512 bytes of the file, None for the holes of sparse files
+    sectors: Vec<Option<u64>>,
+    size: u64,
+    writable: bool,
+}
+
+impl MappedFile {
+    pub fn new(device: Arc<dyn BlockDevice>, sectors: Vec<Option<u64>>, size: u64, writable: bool) -> Self {
+        MappedFile {
+            device,
+            sectors,
+            size,
+            writable,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    // sectors of the page at 'offset' with their position in the page, past the end of the file there are none
+    fn page_sectors(&self, offset: u64) -> impl Iterator<Item = (usize, u64)> + '_ {
+        let first = offset / SECTOR_SIZE as u64;
+        (0..SECTORS_PER_PAGE).filter_map(move |i| {
+            let sector = (*self.sectors.get((first + i) as usize)?)?;
+            Some((i as usize * SECTOR_SIZE, sector))
+        })
+    }
+
+    /*
+    this function fills the page at 'target' with the file content at 'offset', the caller zeroes it
+    before so holes and the part after the end of the file read as zeros
+     */
+    pub fn read_page(&self, offset: u64, target: *mut u8) -> Result<(), FileSystemError> {
+        for (position, sector) in self.page_sectors(offset) {
+            self.device.read(target.wrapping_add(position), sector, 1)?;
+        }
+        Ok(())
+    }
+
+    // write the page at 'source' back to the file at 'offset', holes and bytes after the file's last sector are dropped
+    pub fn write_page(&self, offset: u64, source: *const u8) -> Result<(), FileSystemError> {
+        if !self.writable {
+            return Err(FileSystemError::AccessDenied);
+        }
+        for (position, sector) in self.page_sectors(offset) {
+            self.device.write(source.wrapping_add(position), sector, 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MappedFile {{ size: {}, writable: {} }}", self.size, self.writable)
+    }
+}
@@ -0,0 +1,46 @@
+//MMIO MAPPINGS
+//Maps device registers into a dedicated uncached virtual window
+use crate::memory::paging::{FRAME_ALLOCATOR, MAPPER};
+use spin::Mutex;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags as Flags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+pub const MMIO_START: u64 = 0x_6666_0000_0000;
+const MMIO_SIZE: u64 = 1024 * 1024 * 1024; // 1 GiB of device windows
+
+// next free virtual address in the mmio window
+static NEXT_MMIO: Mutex<u64> = Mutex::new(MMIO_START);
+
+/*
+this function maps 'size' bytes of device memory starting at 'phys' and returns the virtual address of 'phys',
+the mapping is never removed
+ */
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Option<VirtAddr> {
+    let first_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(phys);
+    let last_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(phys + (size - 1));
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+    let pages = frames.count() as u64;
+
+    let start = {
+        let mut next = NEXT_MMIO.lock();
+        if *next + pages * 4096 > MMIO_START + MMIO_SIZE {
+            return None;
+        }
+        let start = *next;
+        *next += pages * 4096;
+        start
+    };
+
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut()?;
+    let frame_allocator = frame_allocator.as_mut()?;
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE | Flags::WRITE_THROUGH;
+    for (index, frame) in frames.enumerate() {
+        let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(start + index as u64 * 4096));
+        unsafe {
+            mapper.map_to(page, frame, flags, frame_allocator).ok()?.flush();
+        }
+    }
+    Some(VirtAddr::new(start) + (phys.as_u64() - first_frame.start_address().as_u64()))
+}
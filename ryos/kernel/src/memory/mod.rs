@@ -1 +1,9 @@
-pub mod paging;
\ No newline at end of file
+pub mod dma;
+pub mod frame_allocator;
+pub mod irq_mutex;
+pub mod mmap;
+pub mod mmio;
+pub mod paging;
+pub mod stack;
+pub mod swap;
+pub mod vmm;
\ No newline at end of file
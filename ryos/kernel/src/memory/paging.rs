@@ -1,5 +1,16 @@
 use x86_64::{structures::paging::PageTable, PhysAddr, VirtAddr};
+use crate::memory::frame_allocator::BitmapFrameAllocator;
 use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PhysFrame, Size4KiB};
+use crate::memory::irq_mutex::IrqMutex;
+use conquer_once::spin::OnceCell;
+
+// the bootloader maps the whole physical memory starting at this virtual address
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+// kept after the heap is set up so drivers can map memory and allocate frames for dma.
+// always taken in this order, the heap takes both when it grows
+pub static MAPPER: IrqMutex<Option<OffsetPageTable<'static>>> = IrqMutex::new(None);
+pub static FRAME_ALLOCATOR: IrqMutex<Option<BitmapFrameAllocator>> = IrqMutex::new(None);
 
 
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
@@ -17,57 +28,71 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
 }
 
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.init_once(|| physical_memory_offset);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+// hand the page table and frame allocator over to the rest of the kernel
+pub fn store(mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+}
 
+// virtual address through which the kernel can access a physical address
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("paging not initialized")
+        + addr.as_u64()
+}
 
-pub struct EmptyFrameAllocator;
 
-unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        None
-    }
-}
-use bootloader_api::info::MemoryRegions;
-use bootloader_api::info::MemoryRegionKind;
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryRegions,
-    next: usize,
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageTableStats {
+    // page table frames, the level 4 table included
+    pub tables: usize,
+    pub pages_4k: usize,
+    pub pages_2m: usize,
+    pub pages_1g: usize,
 }
 
-impl BootInfoFrameAllocator {
-    /// Create a FrameAllocator from the passed memory map.
-    ///
-    /// This function is unsafe because the caller must guarantee that the passed
-    /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_map: &'static MemoryRegions) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
+/*
+this function walks the active page tables and counts the tables and the mapped pages of every size
+ */
+pub fn page_table_stats() -> PageTableStats {
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::PageTableFlags;
+
+    fn walk(table: &PageTable, level: u8, stats: &mut PageTableStats) {
+        stats.tables += 1;
+        for entry in table.iter().filter(|e| e.flags().contains(PageTableFlags::PRESENT)) {
+            let huge = entry.flags().contains(PageTableFlags::HUGE_PAGE);
+            match level {
+                1 => stats.pages_4k += 1,
+                2 if huge => stats.pages_2m += 1,
+                3 if huge => stats.pages_1g += 1,
+                _ => {
+                    let next = phys_to_virt(entry.addr()).as_ptr::<PageTable>();
+                    walk(unsafe { &*next }, level - 1, stats);
+                }
+            }
         }
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions
-            .filter(|r| r.kind ==  MemoryRegionKind::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions
-            .map(|r| r.start..r.end);
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
+    // nobody may change the tables while we walk them
+    let _mapper = MAPPER.lock();
+    let mut stats = PageTableStats::default();
+    let level_4_table = phys_to_virt(Cr3::read().0.start_address()).as_ptr::<PageTable>();
+    walk(unsafe { &*level_4_table }, 4, &mut stats);
+    stats
 }
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+
+pub struct EmptyFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        None
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,137 @@
+//TASK STACKS
+//Every task stack lives in its own slot of a dedicated region, only the top of the slot is mapped so
+//the unmapped rest of the slot works as a guard area that catches stack overflows
+use crate::memory::frame_allocator::FRAME_SIZE;
+use crate::memory::paging::{FRAME_ALLOCATOR, MAPPER};
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+pub const STACKS_START: u64 = 0x_5555_0000_0000;
+const SLOT_SIZE: u64 = 1024 * 1024;
+const SLOT_COUNT: usize = 1024;
+// at least one page of every slot stays unmapped
+pub const MAX_STACK_SIZE: usize = (SLOT_SIZE - FRAME_SIZE) as usize;
+pub const DEFAULT_STACK_SIZE: usize = 16 * 1024;
+
+// id of the task owning every slot, None for free slots
+static SLOTS: Mutex<Vec<Option<usize>>> = Mutex::new(Vec::new());
+// stacks of tasks that ended, they are unmapped by the next allocation and not by the scheduler
+// because it may run while the page table locks are held
+static RETIRED: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+pub struct TaskStack {
+    slot: usize,
+    pages: usize,
+}
+
+fn slot_top(slot: usize) -> VirtAddr {
+    VirtAddr::new(STACKS_START + (slot as u64 + 1) * SLOT_SIZE)
+}
+
+fn stack_pages(slot: usize, pages: usize) -> impl Iterator<Item = Page<Size4KiB>> {
+    let top = slot_top(slot);
+    (1..=pages as u64).map(move |i| Page::containing_address(top - i * FRAME_SIZE))
+}
+
+impl TaskStack {
+    /*
+    this function maps a stack of at least 'size' bytes for the task 'owner', None when the size is
+    above MAX_STACK_SIZE or there is no free slot or memory
+     */
+    pub fn allocate(size: usize, owner: usize) -> Option<TaskStack> {
+        if size == 0 || size > MAX_STACK_SIZE {
+            return None;
+        }
+        reclaim_retired();
+
+        let slot = without_interrupts(|| {
+            let mut slots = SLOTS.lock();
+            if slots.is_empty() {
+                slots.resize(SLOT_COUNT, None);
+            }
+            let slot = slots.iter().position(|s| s.is_none())?;
+            slots[slot] = Some(owner);
+            Some(slot)
+        })?;
+        let pages = size.div_ceil(FRAME_SIZE as usize);
+
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let mapper = mapper.as_mut()?;
+        let frame_allocator = frame_allocator.as_mut()?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        for (mapped, page) in stack_pages(slot, pages).enumerate() {
+            let frame = frame_allocator.allocate_frame();
+            let result = frame.map(|frame| unsafe { mapper.map_to(page, frame, flags, frame_allocator) });
+            match result {
+                Some(Ok(flush)) => flush.flush(),
+                _ => {
+                    if let Some(frame) = frame {
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                    }
+                    unmap_locked(mapper, frame_allocator, slot, mapped);
+                    without_interrupts(|| SLOTS.lock()[slot] = None);
+                    return None;
+                }
+            }
+        }
+        Some(TaskStack { slot, pages })
+    }
+
+    // first address above the stack, the initial stack pointer
+    pub fn top(&self) -> VirtAddr {
+        slot_top(self.slot)
+    }
+}
+
+impl Drop for TaskStack {
+    fn drop(&mut self) {
+        without_interrupts(|| RETIRED.lock().push((self.slot, self.pages)));
+    }
+}
+
+// unmap 'pages' pages from the top of 'slot', the caller holds the page table locks
+fn unmap_locked(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+    slot: usize,
+    pages: usize,
+) {
+    for page in stack_pages(slot, pages) {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+fn unmap(slot: usize, pages: usize) {
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    if let (Some(mapper), Some(frame_allocator)) = (mapper.as_mut(), frame_allocator.as_mut()) {
+        unmap_locked(mapper, frame_allocator, slot, pages);
+    }
+}
+
+fn reclaim_retired() {
+    let retired = without_interrupts(|| core::mem::take(&mut *RETIRED.lock()));
+    for (slot, pages) in retired {
+        unmap(slot, pages);
+        without_interrupts(|| SLOTS.lock()[slot] = None);
+    }
+}
+
+/*
+this function returns the task whose guard area contains 'addr', it is called from the page fault
+handler so it never waits for the slot lock
+ */
+pub fn guard_owner(addr: VirtAddr) -> Option<usize> {
+    let offset = addr.as_u64().checked_sub(STACKS_START)?;
+    let slot = (offset / SLOT_SIZE) as usize;
+    // the mapped part of a slot never faults, so any fault inside an owned slot hit its guard area
+    let slots = SLOTS.try_lock()?;
+    *slots.get(slot)?
+}
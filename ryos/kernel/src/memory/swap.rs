@@ -0,0 +1,168 @@
+//SWAP
+//Pages of user memory evicted by the address spaces are kept in page sized slots of a swap partition.
+//The swap lock is only taken with interrupts disabled and never held during the disk access, the
+//slot being written or read belongs to the caller until it is done
+use crate::file_system::block_device::{BlockDevice, DEVICES};
+use crate::file_system::disk_driver::SECTOR_SIZE;
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::partition::PartitionKind;
+use crate::memory::frame_allocator::FRAME_SIZE;
+use crate::memory::paging::phys_to_virt;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::PhysFrame;
+
+const SECTORS_PER_SLOT: u64 = FRAME_SIZE / SECTOR_SIZE as u64;
+// the first page of a swap partition holds the linux swap header, it is never used for pages
+const FIRST_SLOT: u64 = 1;
+
+#[derive(Debug)]
+pub enum SwapError {
+    NoSuchDevice,
+    // only partitions of the linux swap type are used
+    NotASwapPartition,
+    AlreadyOn,
+    NotOn,
+    // swapoff couldn't bring every page back into memory
+    InUse,
+    Io(FileSystemError),
+}
+
+#[derive(Debug, Clone)]
+pub struct SwapStats {
+    pub device: String,
+    pub slots: usize,
+    pub used: usize,
+}
+
+struct SwapSpace {
+    name: String,
+    device: Arc<dyn BlockDevice>,
+    // address spaces holding every slot, 0 for free slots. forked spaces share slots
+    owners: Vec<u16>,
+    // the free slots, write_out takes the last one
+    free: Vec<u32>,
+    used: usize,
+}
+
+static SWAP: Mutex<Option<SwapSpace>> = Mutex::new(None);
+
+// start swapping to the swap partition registered as 'name'
+pub fn swapon(name: &str) -> Result<(), SwapError> {
+    let device = {
+        let devices = DEVICES.lock();
+        let entry = devices.iter().find(|d| d.name == name).ok_or(SwapError::NoSuchDevice)?;
+        if !matches!(entry.partition, Some((PartitionKind::Swap, _))) {
+            return Err(SwapError::NotASwapPartition);
+        }
+        entry.device.clone()
+    };
+    let slots = (device.sector_count() / SECTORS_PER_SLOT).min(u32::MAX as u64) as usize;
+    let free = (FIRST_SLOT as u32..slots as u32).rev().collect();
+    without_interrupts(|| {
+        let mut swap = SWAP.lock();
+        if swap.is_some() {
+            return Err(SwapError::AlreadyOn);
+        }
+        *swap = Some(SwapSpace {
+            name: String::from(name),
+            device,
+            owners: vec![0; slots],
+            free,
+            used: 0,
+        });
+        Ok(())
+    })
+}
+
+// stop swapping, every swapped page must have been brought back before
+pub fn swapoff() -> Result<(), SwapError> {
+    without_interrupts(|| {
+        let mut swap = SWAP.lock();
+        match swap.as_ref() {
+            None => Err(SwapError::NotOn),
+            Some(space) if space.used != 0 => Err(SwapError::InUse),
+            Some(_) => {
+                *swap = None;
+                Ok(())
+            }
+        }
+    })
+}
+
+pub fn stats() -> Option<SwapStats> {
+    without_interrupts(|| {
+        SWAP.lock().as_ref().map(|space| SwapStats {
+            device: space.name.clone(),
+            slots: space.owners.len().saturating_sub(FIRST_SLOT as usize),
+            used: space.used,
+        })
+    })
+}
+
+/*
+this function writes 'frame' to a free slot and returns its number, None when swap is off, full or
+the write failed. the slot is taken before the write, so swapoff waits for it
+ */
+pub fn write_out(frame: PhysFrame) -> Option<u64> {
+    let (device, slot) = without_interrupts(|| {
+        let mut swap = SWAP.lock();
+        let space = swap.as_mut()?;
+        let slot = space.free.pop()? as u64;
+        space.owners[slot as usize] = 1;
+        space.used += 1;
+        Some((space.device.clone(), slot))
+    })?;
+    let source = phys_to_virt(frame.start_address()).as_ptr::<u8>();
+    if device.write(source, slot * SECTORS_PER_SLOT, SECTORS_PER_SLOT as u16).is_err() {
+        release(slot);
+        return None;
+    }
+    Some(slot)
+}
+
+// read the page in 'slot' into 'frame', the slot stays taken
+pub fn read_in(slot: u64, frame: PhysFrame) -> Result<(), SwapError> {
+    let device = without_interrupts(|| SWAP.lock().as_ref().map(|space| space.device.clone()));
+    let device = device.ok_or(SwapError::NotOn)?;
+    let target = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+    device
+        .read(target, slot * SECTORS_PER_SLOT, SECTORS_PER_SLOT as u16)
+        .map_err(SwapError::Io)
+}
+
+// add an owner to 'slot', false when it already has the maximum number of owners
+pub fn share(slot: u64) -> bool {
+    without_interrupts(|| {
+        let mut swap = SWAP.lock();
+        let Some(owners) = swap.as_mut().and_then(|space| space.owners.get_mut(slot as usize)) else {
+            return false;
+        };
+        if *owners == u16::MAX {
+            return false;
+        }
+        *owners += 1;
+        true
+    })
+}
+
+// drop one owner of 'slot', the slot is free again after its last owner released it
+pub fn release(slot: u64) {
+    without_interrupts(|| {
+        let mut swap = SWAP.lock();
+        let Some(space) = swap.as_mut() else {
+            return;
+        };
+        if let Some(owners) = space.owners.get_mut(slot as usize) {
+            if *owners == 1 {
+                space.used -= 1;
+                space.free.push(slot as u32);
+            }
+            *owners = owners.saturating_sub(1);
+        }
+    })
+}
@@ -0,0 +1,1029 @@
+//VIRTUAL MEMORY MANAGER
+//Address spaces with their own level 4 table. The lower 64 TiB belong to the address space,
+//every other level 4 entry is copied from the kernel table so all spaces share the kernel mappings
+use crate::heap_alloc::alloc::HEAP_START;
+use crate::memory::frame_allocator::{BitmapFrameAllocator, FRAME_SIZE};
+use crate::memory::mmap::MappedFile;
+use crate::memory::mmio::MMIO_START;
+use crate::memory::stack::STACKS_START;
+use crate::memory::swap;
+use crate::memory::paging::{phys_to_virt, FRAME_ALLOCATOR, MAPPER};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::page::PageRangeInclusive;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+// level 4 entries below this index hold the memory of the address space
+pub const USER_L4_ENTRIES: usize = 128;
+pub const USER_SPACE_END: u64 = (USER_L4_ENTRIES as u64) << 39;
+// nothing is ever mapped at the first page so null pointers fault
+pub const USER_SPACE_START: u64 = FRAME_SIZE;
+
+// kernel regions at fixed addresses in the shared half, their level 3 tables are created at boot so
+// later mappings in them show up in every address space
+const KERNEL_REGIONS: [u64; 3] = [HEAP_START as u64, MMIO_START, STACKS_START];
+
+// marks pages that are read only because their frame is shared after a fork, the first write copies them
+const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+// marks not present entries of pages that are in swap, the address bits hold the swap slot
+const SWAPPED: PageTableFlags = PageTableFlags::BIT_10;
+
+static KERNEL_PML4: OnceCell<PhysFrame> = OnceCell::uninit();
+
+// the address spaces of tasks with their level 4 table, their pages are evicted to swap in clock order
+static SWAPPABLE: Mutex<Vec<(PhysFrame, Weak<Mutex<AddressSpace>>)>> = Mutex::new(Vec::new());
+// index in SWAPPABLE of the address space the next eviction starts with
+static CLOCK_SPACE: AtomicUsize = AtomicUsize::new(0);
+// address spaces of removed tasks, the scheduler can't free them while it holds the task manager
+static RETIRED: Mutex<Vec<Arc<Mutex<AddressSpace>>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VmError {
+    OutOfMemory,
+    InvalidRange,
+    Overlap,
+    NotMapped,
+    // the access isn't allowed by the area flags
+    AccessViolation,
+    // reading or writing back a mapped file failed
+    Io,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AreaKind {
+    Anonymous,
+    Stack,
+    Heap,
+    File,
+    // frames granted by another address space, both see the same memory
+    Shared,
+}
+
+/*
+frames pinned for a grant, each one with a share of its own that map_shared hands to the address
+space it maps them into. dropping it unmapped gives the shares back
+ */
+pub struct SharedPages {
+    frames: Vec<PhysFrame>,
+    writable: bool,
+}
+
+impl SharedPages {
+    // bytes the pages cover
+    pub fn size(&self) -> u64 {
+        self.frames.len() as u64 * FRAME_SIZE
+    }
+}
+
+impl Drop for SharedPages {
+    fn drop(&mut self) {
+        if self.frames.is_empty() {
+            return;
+        }
+        if let Some(frame_allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+            for frame in self.frames.drain(..) {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    }
+}
+
+// where the pages of a file mapping come from
+#[derive(Debug, Clone)]
+pub struct FileBacking {
+    pub file: Arc<MappedFile>,
+    // file offset of the first page of the area
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct VmArea {
+    pub start: VirtAddr,
+    // exclusive
+    pub end: VirtAddr,
+    pub flags: PageTableFlags,
+    pub kind: AreaKind,
+    // pages of lazy areas get a frame on their first access
+    pub lazy: bool,
+    pub file: Option<FileBacking>,
+}
+
+impl VmArea {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    fn pages(&self) -> PageRangeInclusive {
+        page_range(self.start, self.end)
+    }
+
+    // file offset of the page at 'addr'
+    fn file_offset(&self, addr: VirtAddr) -> Option<(&MappedFile, u64)> {
+        let backing = self.file.as_ref()?;
+        Some((&backing.file, backing.offset + (addr - self.start)))
+    }
+
+    // the backing of the part of this area that starts at 'addr'
+    fn file_from(&self, addr: VirtAddr) -> Option<FileBacking> {
+        let backing = self.file.as_ref()?;
+        Some(FileBacking {
+            file: backing.file.clone(),
+            offset: backing.offset + (addr - self.start),
+        })
+    }
+}
+
+fn page_range(start: VirtAddr, end: VirtAddr) -> PageRangeInclusive {
+    Page::range_inclusive(Page::containing_address(start), Page::containing_address(end - 1u64))
+}
+
+// remember the boot page table as the kernel address space and prepare the shared kernel regions
+pub fn init() {
+    let (pml4, _) = Cr3::read();
+    KERNEL_PML4.init_once(|| pml4);
+    // the kernel has to fault on read only pages as well, or its writes would skip copy on write
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+
+    let _mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("frame allocator not initialized");
+    let kernel_table = unsafe { &mut *phys_to_virt(pml4.start_address()).as_mut_ptr::<PageTable>() };
+    for region in KERNEL_REGIONS {
+        let entry = &mut kernel_table[Page::<Size4KiB>::containing_address(VirtAddr::new(region)).p4_index()];
+        if entry.is_unused() {
+            let table = allocate_zeroed_frame(frame_allocator).expect("no frame for a kernel page table");
+            entry.set_frame(table, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+    }
+}
+
+pub fn kernel_pml4() -> PhysFrame {
+    *KERNEL_PML4.get().expect("vmm not initialized")
+}
+
+// load the level 4 table 'pml4', None for the kernel address space
+pub fn switch_to(pml4: Option<PhysFrame>) {
+    let pml4 = pml4.unwrap_or_else(kernel_pml4);
+    let (current, flags) = Cr3::read();
+    if current != pml4 {
+        unsafe { Cr3::write(pml4, flags) };
+    }
+}
+
+fn allocate_zeroed_frame(frame_allocator: &mut BitmapFrameAllocator) -> Option<PhysFrame> {
+    let frame = frame_allocator.allocate_frame()?;
+    unsafe {
+        core::ptr::write_bytes(phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(), 0, FRAME_SIZE as usize);
+    }
+    Some(frame)
+}
+
+fn table_at(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() }
+}
+
+// the level 1 entry of 'page' under 'pml4', None when a table on the way is missing
+fn entry_of(pml4: PhysFrame, page: Page) -> Option<&'static mut PageTableEntry> {
+    let mut table = table_at(pml4);
+    for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+        table = table_at(table[index].frame().ok()?);
+    }
+    Some(&mut table[page.p1_index()])
+}
+
+// like entry_of, but missing tables are created with 'table_flags'
+fn create_entry(
+    pml4: PhysFrame,
+    page: Page,
+    table_flags: PageTableFlags,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Option<&'static mut PageTableEntry> {
+    let mut table = table_at(pml4);
+    for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+        if table[index].is_unused() {
+            table[index].set_frame(allocate_zeroed_frame(frame_allocator)?, table_flags);
+        }
+        table = table_at(table[index].frame().ok()?);
+    }
+    Some(&mut table[page.p1_index()])
+}
+
+// the swap slot holding 'page', None when it isn't swapped out
+fn swapped_slot(pml4: PhysFrame, page: Page) -> Option<u64> {
+    let entry = entry_of(pml4, page)?;
+    let flags = entry.flags();
+    (!flags.contains(PageTableFlags::PRESENT) && flags.contains(SWAPPED)).then(|| entry.addr().as_u64() / FRAME_SIZE)
+}
+
+// let the pages of 'space' be evicted to swap, called for the address space of every task
+pub fn make_swappable(space: &Arc<Mutex<AddressSpace>>) {
+    let pml4 = space.lock().pml4;
+    without_interrupts(|| {
+        let mut spaces = SWAPPABLE.lock();
+        spaces.retain(|(_, space)| space.strong_count() > 0);
+        spaces.push((pml4, Arc::downgrade(space)));
+    });
+}
+
+// called by the scheduler for the address space of a task it removes, reclaim_retired frees it later
+pub fn retire(space: Arc<Mutex<AddressSpace>>) {
+    without_interrupts(|| RETIRED.lock().push(space));
+}
+
+// drop the retired address spaces, only called in task context since the last one tears its space down
+pub fn reclaim_retired() {
+    let retired = without_interrupts(|| core::mem::take(&mut *RETIRED.lock()));
+    drop(retired);
+}
+
+fn swappable_spaces() -> Vec<(PhysFrame, Arc<Mutex<AddressSpace>>)> {
+    without_interrupts(|| {
+        SWAPPABLE
+            .lock()
+            .iter()
+            .filter_map(|(pml4, space)| Some((*pml4, space.upgrade()?)))
+            .collect()
+    })
+}
+
+/*
+this function moves one page of user memory to swap, going round the swappable address spaces like
+a clock. 'current' is locked by the caller, so it is used directly instead of through its lock
+ */
+fn evict_any(current: &mut AddressSpace) -> bool {
+    let spaces = swappable_spaces();
+    let start = CLOCK_SPACE.load(Ordering::Relaxed);
+    for i in 0..spaces.len() {
+        let index = (start + i) % spaces.len();
+        let (pml4, space) = &spaces[index];
+        let evicted = match *pml4 == current.pml4 {
+            true => current.evict_page(),
+            false => space.try_lock().is_some_and(|mut space| space.evict_page()),
+        };
+        if evicted {
+            CLOCK_SPACE.store(index, Ordering::Relaxed);
+            return true;
+        }
+    }
+    false
+}
+
+// bring every page of the swappable address spaces back from swap, needed before swapoff
+pub fn swap_in_all() -> Result<(), VmError> {
+    for (_, space) in swappable_spaces() {
+        space.lock().swap_in_everything()?;
+    }
+    Ok(())
+}
+
+// page table mapper for the tables under 'pml4', through the physical memory mapping
+fn mapper_of(pml4: PhysFrame) -> OffsetPageTable<'static> {
+    let table = unsafe { &mut *phys_to_virt(pml4.start_address()).as_mut_ptr::<PageTable>() };
+    unsafe { OffsetPageTable::new(table, phys_to_virt(PhysAddr::new(0))) }
+}
+
+pub struct AddressSpace {
+    pml4: PhysFrame,
+    areas: Vec<VmArea>,
+    // position of the eviction clock hand among the swappable pages
+    clock: u64,
+}
+
+impl AddressSpace {
+    // an empty address space that only holds the kernel mappings
+    pub fn new() -> Result<Self, VmError> {
+        reclaim_retired();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().ok_or(VmError::OutOfMemory)?;
+        let pml4 = allocate_zeroed_frame(frame_allocator).ok_or(VmError::OutOfMemory)?;
+
+        let kernel_table = unsafe { &*phys_to_virt(kernel_pml4().start_address()).as_ptr::<PageTable>() };
+        let table = unsafe { &mut *phys_to_virt(pml4.start_address()).as_mut_ptr::<PageTable>() };
+        for index in USER_L4_ENTRIES..512 {
+            table[index] = kernel_table[index].clone();
+        }
+        Ok(AddressSpace {
+            pml4,
+            areas: Vec::new(),
+            clock: 0,
+        })
+    }
+
+    pub fn pml4(&self) -> PhysFrame {
+        self.pml4
+    }
+
+    pub fn areas(&self) -> &[VmArea] {
+        &self.areas
+    }
+
+    pub fn find_area(&self, addr: VirtAddr) -> Option<&VmArea> {
+        self.areas.iter().find(|area| area.contains(addr))
+    }
+
+    // whether every byte of the 'len' bytes at 'start' lies in areas that have all of 'flags'
+    pub fn allows(&self, start: VirtAddr, len: u64, flags: PageTableFlags) -> bool {
+        let Some(end) = start.as_u64().checked_add(len) else {
+            return false;
+        };
+        if start.as_u64() < USER_SPACE_START || end > USER_SPACE_END {
+            return false;
+        }
+        let mut addr = start;
+        while addr.as_u64() < end {
+            match self.find_area(addr) {
+                Some(area) if area.flags.contains(flags) => addr = area.end,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    // the lowest free range of 'size' bytes at or above 'from'
+    pub fn find_free(&self, from: VirtAddr, size: u64) -> Option<VirtAddr> {
+        let mut areas: Vec<&VmArea> = self.areas.iter().collect();
+        areas.sort_by_key(|area| area.start);
+        let mut start = from.align_up(FRAME_SIZE).as_u64().max(USER_SPACE_START);
+        for area in areas {
+            if area.end.as_u64() <= start {
+                continue;
+            }
+            if start.checked_add(size)? <= area.start.as_u64() {
+                break;
+            }
+            start = area.end.as_u64();
+        }
+        (start.checked_add(size)? <= USER_SPACE_END).then(|| VirtAddr::new(start))
+    }
+
+    pub fn activate(&self) {
+        switch_to(Some(self.pml4));
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        mapper_of(self.pml4)
+    }
+
+    fn check_range(&self, start: VirtAddr, size: u64) -> Result<VirtAddr, VmError> {
+        let end = start.as_u64().checked_add(size).ok_or(VmError::InvalidRange)?;
+        if size == 0
+            || !start.is_aligned(FRAME_SIZE)
+            || size % FRAME_SIZE != 0
+            || start.as_u64() < USER_SPACE_START
+            || end > USER_SPACE_END
+        {
+            return Err(VmError::InvalidRange);
+        }
+        Ok(VirtAddr::new(end))
+    }
+
+    /*
+    this function adds an area of 'size' bytes at 'start' and backs it with zeroed frames,
+    'flags' are used for every page (PRESENT is always added)
+     */
+    pub fn map(&mut self, start: VirtAddr, size: u64, flags: PageTableFlags, kind: AreaKind) -> Result<(), VmError> {
+        self.add_area(start, size, flags, kind, false, None)
+    }
+
+    // like map, but the frames are only allocated when the pages are first touched
+    pub fn reserve(&mut self, start: VirtAddr, size: u64, flags: PageTableFlags, kind: AreaKind) -> Result<(), VmError> {
+        self.add_area(start, size, flags, kind, true, None)
+    }
+
+    /*
+    this function maps 'size' bytes of 'file' starting at the page aligned 'offset' to 'start', the
+    pages are read from the disk on their first access. writable mappings need a writable file,
+    their dirty pages are written back by sync and unmap
+     */
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+        file: Arc<MappedFile>,
+        offset: u64,
+    ) -> Result<(), VmError> {
+        if offset % FRAME_SIZE != 0 {
+            return Err(VmError::InvalidRange);
+        }
+        if flags.contains(PageTableFlags::WRITABLE) && !file.writable() {
+            return Err(VmError::AccessViolation);
+        }
+        let backing = FileBacking { file, offset };
+        self.add_area(start, size, flags, AreaKind::File, true, Some(backing))
+    }
+
+    fn add_area(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+        kind: AreaKind,
+        lazy: bool,
+        file: Option<FileBacking>,
+    ) -> Result<(), VmError> {
+        let end = self.check_range(start, size)?;
+        if self.areas.iter().any(|area| area.start < end && start < area.end) {
+            return Err(VmError::Overlap);
+        }
+        let area = VmArea {
+            start,
+            end,
+            flags: flags | PageTableFlags::PRESENT,
+            kind,
+            lazy,
+            file,
+        };
+        if lazy {
+            self.areas.push(area);
+            return Ok(());
+        }
+
+        let mut mapped = 0;
+        let result = self.populate(&area, &mut mapped);
+        if result.is_err() {
+            // give back what was mapped before running out of memory
+            self.unmap_pages(page_range(start, start + mapped * FRAME_SIZE));
+            return result;
+        }
+        self.areas.push(area);
+        Ok(())
+    }
+
+    fn populate(&mut self, area: &VmArea, mapped: &mut u64) -> Result<(), VmError> {
+        for page in area.pages() {
+            self.map_new_page(page, area.flags)?;
+            *mapped += 1;
+        }
+        Ok(())
+    }
+
+    // back 'page' with a zeroed frame, outside of the page fault handler
+    fn map_new_page(&mut self, page: Page, flags: PageTableFlags) -> Result<PhysFrame, VmError> {
+        let frame = self.user_frame()?;
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().ok_or(VmError::OutOfMemory)?;
+        match unsafe { self.mapper().map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(frame)
+            }
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                Err(VmError::OutOfMemory)
+            }
+        }
+    }
+
+    /*
+    this function copies 'data' to 'addr' through the physical memory mapping, so it works on read only
+    pages and while another address space is active. missing pages of anonymous lazy areas get their
+    frame now. meant for filling an address space before its program runs, shared copy on write frames
+    are written as they are
+     */
+    pub fn write_bytes(&mut self, addr: VirtAddr, data: &[u8]) -> Result<(), VmError> {
+        let mut written = 0;
+        while written < data.len() {
+            let target = addr + written as u64;
+            let area = self.find_area(target).ok_or(VmError::NotMapped)?;
+            let (flags, anonymous) = (area.flags, area.lazy && area.file.is_none());
+            let page = Page::containing_address(target);
+            let frame = match self.mapper().translate(target) {
+                TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), .. } => frame,
+                TranslateResult::NotMapped if anonymous && swapped_slot(self.pml4, page).is_none() => {
+                    self.map_new_page(page, flags)?
+                }
+                _ => return Err(VmError::NotMapped),
+            };
+            let offset = target - page.start_address();
+            let count = ((FRAME_SIZE - offset) as usize).min(data.len() - written);
+            let destination = phys_to_virt(frame.start_address() + offset).as_mut_ptr::<u8>();
+            unsafe { core::ptr::copy_nonoverlapping(data[written..].as_ptr(), destination, count) };
+            written += count;
+        }
+        Ok(())
+    }
+
+    /*
+    this function allocates a zeroed frame for user memory, when memory is full pages of the swappable
+    address spaces are evicted until one is free. it only runs in task context with interrupts enabled,
+    page faults are handled by the faulting task
+     */
+    fn user_frame(&mut self) -> Result<PhysFrame, VmError> {
+        loop {
+            let frame = allocate_zeroed_frame(FRAME_ALLOCATOR.lock().as_mut().ok_or(VmError::OutOfMemory)?);
+            if let Some(frame) = frame {
+                return Ok(frame);
+            }
+            if !evict_any(self) {
+                return Err(VmError::OutOfMemory);
+            }
+        }
+    }
+
+    // the 'index'th page of the areas that can be swapped, file mappings are never swapped
+    fn swappable_page(&self, mut index: u64) -> Option<Page> {
+        for area in self.areas.iter().filter(|area| area.file.is_none()) {
+            let pages = (area.end - area.start) / FRAME_SIZE;
+            if index < pages {
+                return Some(Page::containing_address(area.start + index * FRAME_SIZE));
+            }
+            index -= pages;
+        }
+        None
+    }
+
+    /*
+    this function moves the first page the clock hand finds that wasn't accessed since the hand last
+    passed it to swap, false when there is none or swap is off or full
+     */
+    fn evict_page(&mut self) -> bool {
+        let total: u64 = self
+            .areas
+            .iter()
+            .filter(|area| area.file.is_none())
+            .map(|area| (area.end - area.start) / FRAME_SIZE)
+            .sum();
+        // two rounds, the first one may only clear accessed bits
+        for _ in 0..2 * total {
+            self.clock = (self.clock + 1) % total;
+            let Some(page) = self.swappable_page(self.clock) else {
+                continue;
+            };
+            let Some(entry) = entry_of(self.pml4, page) else {
+                continue;
+            };
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            if flags.contains(PageTableFlags::ACCESSED) {
+                entry.set_flags(flags - PageTableFlags::ACCESSED);
+                tlb::flush(page.start_address());
+                continue;
+            }
+            // frames shared after a fork stay in memory
+            let frame = PhysFrame::containing_address(entry.addr());
+            let shared = FRAME_ALLOCATOR.lock().as_ref().is_none_or(|allocator| allocator.is_shared(frame));
+            if shared {
+                continue;
+            }
+            // not present while it is written, the disk access runs with interrupts enabled and the
+            // owner may run meanwhile. its fault on the page waits for the address space lock
+            entry.set_flags(flags - PageTableFlags::PRESENT);
+            tlb::flush(page.start_address());
+            let Some(slot) = swap::write_out(frame) else {
+                entry.set_flags(flags);
+                return false;
+            };
+            entry.set_addr(PhysAddr::new(slot * FRAME_SIZE), SWAPPED);
+            tlb::flush(page.start_address());
+            if let Some(frame_allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+            return true;
+        }
+        false
+    }
+
+    // read 'page' back from swap 'slot' into a new frame
+    fn swap_in(&mut self, page: Page, slot: u64, flags: PageTableFlags) -> Result<(), VmError> {
+        let frame = self.user_frame()?;
+        if swap::read_in(slot, frame).is_err() {
+            if let Some(frame_allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+            return Err(VmError::Io);
+        }
+        swap::release(slot);
+        let entry = entry_of(self.pml4, page).ok_or(VmError::NotMapped)?;
+        entry.set_frame(frame, flags);
+        tlb::flush(page.start_address());
+        Ok(())
+    }
+
+    fn swap_in_everything(&mut self) -> Result<(), VmError> {
+        let areas: Vec<(PageRangeInclusive, PageTableFlags)> =
+            self.areas.iter().map(|area| (area.pages(), area.flags)).collect();
+        for (pages, flags) in areas {
+            for page in pages {
+                if let Some(slot) = swapped_slot(self.pml4, page) {
+                    self.swap_in(page, slot, flags)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    this function handles a page fault at 'addr' inside this address space: writes to copy on write
+    pages get their own frame, swapped out pages are read back and pages of lazy areas get a zeroed
+    one, as long as the area allows the access. the page fault handler lets the faulting task call it
+    with interrupts enabled
+     */
+    pub fn handle_fault(&mut self, addr: VirtAddr, error: PageFaultErrorCode) -> Result<(), VmError> {
+        let area = self.find_area(addr).ok_or(VmError::NotMapped)?;
+        let flags = area.flags;
+        let allowed = (!error.contains(PageFaultErrorCode::CAUSED_BY_WRITE) || flags.contains(PageTableFlags::WRITABLE))
+            && (!error.contains(PageFaultErrorCode::INSTRUCTION_FETCH) || !flags.contains(PageTableFlags::NO_EXECUTE))
+            && (!error.contains(PageFaultErrorCode::USER_MODE) || flags.contains(PageTableFlags::USER_ACCESSIBLE));
+        if !allowed {
+            return Err(VmError::AccessViolation);
+        }
+        if error.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            return match error.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+                true => self.copy_on_write(addr, flags),
+                false => Err(VmError::AccessViolation),
+            };
+        }
+        let page = Page::containing_address(addr);
+        if let Some(slot) = swapped_slot(self.pml4, page) {
+            return self.swap_in(page, slot, flags);
+        }
+        if !area.lazy {
+            return Err(VmError::AccessViolation);
+        }
+        let file = area.file_from(page.start_address());
+
+        let frame = self.user_frame()?;
+        // the allocator isn't locked during the disk access, the driver may need it
+        let read = match &file {
+            Some(backing) => backing.file.read_page(backing.offset, phys_to_virt(frame.start_address()).as_mut_ptr()),
+            None => Ok(()),
+        };
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().ok_or(VmError::OutOfMemory)?;
+        if read.is_err() {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            return Err(VmError::Io);
+        }
+        match unsafe { self.mapper().map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                Err(VmError::OutOfMemory)
+            }
+        }
+    }
+
+    // give the copy on write page at 'addr' a frame of its own, 'flags' are the flags of its area
+    fn copy_on_write(&mut self, addr: VirtAddr, flags: PageTableFlags) -> Result<(), VmError> {
+        let page = Page::containing_address(addr);
+        let mut mapper = self.mapper();
+        let frame = match mapper.translate(addr) {
+            TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags: current, .. }
+                if current.contains(COPY_ON_WRITE) => frame,
+            _ => return Err(VmError::AccessViolation),
+        };
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().ok_or(VmError::OutOfMemory)?;
+
+        // every other owner already copied it or is gone, so it can simply be written again
+        if !frame_allocator.is_shared(frame) {
+            unsafe { mapper.update_flags(page, flags) }.map_err(|_| VmError::NotMapped)?.flush();
+            return Ok(());
+        }
+
+        let copy = frame_allocator.allocate_frame().ok_or(VmError::OutOfMemory)?;
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+                phys_to_virt(copy.start_address()).as_mut_ptr::<u8>(),
+                FRAME_SIZE as usize,
+            );
+        }
+        let (_, flush) = mapper.unmap(page).map_err(|_| VmError::NotMapped)?;
+        flush.flush();
+        // only drops this address space's share of the frame
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        match unsafe { mapper.map_to(page, copy, flags, frame_allocator) } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(copy) };
+                Err(VmError::OutOfMemory)
+            }
+        }
+    }
+
+    // unmap every mapped page in 'pages' and free its frame or swap slot
+    fn unmap_pages(&mut self, pages: PageRangeInclusive) {
+        if pages.is_empty() {
+            return;
+        }
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let Some(frame_allocator) = frame_allocator.as_mut() else {
+            return;
+        };
+        let pml4 = self.pml4;
+        let mut mapper = self.mapper();
+        for page in pages {
+            if let Some(slot) = swapped_slot(pml4, page) {
+                swap::release(slot);
+                if let Some(entry) = entry_of(pml4, page) {
+                    entry.set_unused();
+                }
+                continue;
+            }
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    }
+
+    /*
+    this function writes the dirty pages of the file mappings in [start, end) back to their files,
+    every page is tried even after a failure
+     */
+    fn write_back(&mut self, start: VirtAddr, end: VirtAddr) -> Result<(), VmError> {
+        let mut mapper = mapper_of(self.pml4);
+        let mut result = Ok(());
+        for area in self.areas.iter().filter(|area| area.file.is_some()) {
+            let (first, last) = (area.start.max(start), area.end.min(end));
+            if first >= last {
+                continue;
+            }
+            for page in page_range(first, last) {
+                let (frame, flags) = match mapper.translate(page.start_address()) {
+                    TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags, .. } => (frame, flags),
+                    _ => continue,
+                };
+                if !flags.contains(PageTableFlags::DIRTY) {
+                    continue;
+                }
+                let Some((file, offset)) = area.file_offset(page.start_address()) else {
+                    continue;
+                };
+                if file.write_page(offset, phys_to_virt(frame.start_address()).as_ptr()).is_err() {
+                    result = Err(VmError::Io);
+                    continue;
+                }
+                if let Ok(flush) = unsafe { mapper.update_flags(page, flags - PageTableFlags::DIRTY) } {
+                    flush.flush();
+                }
+            }
+        }
+        result
+    }
+
+    // write every change to the mapped files back, done in task context when a program ends since the
+    // teardown of an address space may run where disk access isn't possible
+    pub fn write_back_all(&mut self) -> Result<(), VmError> {
+        self.write_back(VirtAddr::new(USER_SPACE_START), VirtAddr::new(USER_SPACE_END))
+    }
+
+    // write the changes to the mapped files in [start, start + size) back to the disk
+    pub fn sync(&mut self, start: VirtAddr, size: u64) -> Result<(), VmError> {
+        let end = self.check_range(start, size)?;
+        self.write_back(start, end)
+    }
+
+    // remove [start, start + size), areas that only partly overlap it are split
+    pub fn unmap(&mut self, start: VirtAddr, size: u64) -> Result<(), VmError> {
+        let end = self.check_range(start, size)?;
+        if !self.areas.iter().any(|area| area.start < end && start < area.end) {
+            return Err(VmError::NotMapped);
+        }
+        // the pages are unmapped even if writing them back failed
+        let written = self.write_back(start, end);
+        let mut kept = Vec::new();
+        for area in core::mem::take(&mut self.areas) {
+            if area.end <= start || end <= area.start {
+                kept.push(area);
+                continue;
+            }
+            if area.start < start {
+                kept.push(VmArea { end: start, ..area.clone() });
+            }
+            if end < area.end {
+                kept.push(VmArea {
+                    start: end,
+                    file: area.file_from(end),
+                    ..area.clone()
+                });
+            }
+        }
+        self.areas = kept;
+        self.unmap_pages(page_range(start, end));
+        written
+    }
+
+    /*
+    this function pins the frames of the anonymous memory in [start, start + size) so another address
+    space can map them with map_shared. every page is made present and private first: lazy pages get
+    their frame, swapped pages are read back and copy on write pages are copied, so both sides see the
+    same memory afterwards. 'writable' grants need writable areas
+     */
+    pub fn share_pages(&mut self, start: VirtAddr, size: u64, writable: bool) -> Result<SharedPages, VmError> {
+        let end = self.check_range(start, size)?;
+        let mut needed = PageTableFlags::USER_ACCESSIBLE;
+        if writable {
+            needed |= PageTableFlags::WRITABLE;
+        }
+        if !self.allows(start, size, needed) {
+            return Err(VmError::AccessViolation);
+        }
+        // the frames are pushed with the frame allocator locked, the heap may need it to grow
+        let mut shared = SharedPages { frames: Vec::with_capacity(page_range(start, end).count()), writable };
+        for page in page_range(start, end) {
+            let area = self.find_area(page.start_address()).ok_or(VmError::NotMapped)?;
+            // file pages are written back to their file, they can't be granted
+            if area.file.is_some() {
+                return Err(VmError::AccessViolation);
+            }
+            let flags = area.flags;
+            if let Some(slot) = swapped_slot(self.pml4, page) {
+                self.swap_in(page, slot, flags)?;
+            }
+            let frame = match self.mapper().translate(page.start_address()) {
+                TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags: current, .. } => {
+                    if current.contains(COPY_ON_WRITE) {
+                        self.copy_on_write(page.start_address(), flags)?;
+                        self.mapper().translate_page(page).map_err(|_| VmError::NotMapped)?
+                    } else {
+                        frame
+                    }
+                }
+                TranslateResult::NotMapped => self.map_new_page(page, flags)?,
+                _ => return Err(VmError::NotMapped),
+            };
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator.as_mut().ok_or(VmError::OutOfMemory)?;
+            if !frame_allocator.share(frame) {
+                return Err(VmError::OutOfMemory);
+            }
+            shared.frames.push(frame);
+        }
+        Ok(shared)
+    }
+
+    /*
+    this function maps the frames of 'pages' at 'start' as a new shared area, 'flags' are used for
+    every page. the address space owns the shares afterwards and gives them back when it unmaps them
+     */
+    pub fn map_shared(&mut self, start: VirtAddr, mut pages: SharedPages, flags: PageTableFlags) -> Result<(), VmError> {
+        let end = self.check_range(start, pages.size())?;
+        if self.areas.iter().any(|area| area.start < end && start < area.end) {
+            return Err(VmError::Overlap);
+        }
+        let mut flags = flags | PageTableFlags::PRESENT;
+        if !pages.writable {
+            flags -= PageTableFlags::WRITABLE;
+        }
+        // the area is pushed with the frame allocator locked, the heap may need it to grow
+        self.areas.reserve(1);
+        let mut mapper = self.mapper();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().ok_or(VmError::OutOfMemory)?;
+        let mut mapped = 0;
+        for (page, frame) in page_range(start, end).zip(pages.frames.iter()) {
+            match unsafe { mapper.map_to(page, *frame, flags, frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => break,
+            }
+            mapped += 1;
+        }
+        if mapped < pages.frames.len() {
+            // the frames mapped so far are unmapped again, the rest is given back when 'pages' is dropped
+            for (page, frame) in page_range(start, end).zip(pages.frames.drain(..mapped)) {
+                if let Ok((_, flush)) = mapper.unmap(page) {
+                    flush.flush();
+                }
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+            return Err(VmError::OutOfMemory);
+        }
+        pages.frames.clear();
+        self.areas.push(VmArea {
+            start,
+            end,
+            flags,
+            kind: AreaKind::Shared,
+            lazy: false,
+            file: None,
+        });
+        Ok(())
+    }
+
+    /*
+    this function creates a copy of this address space that shares every mapped frame with it instead
+    of copying it. writable pages become read only copy on write pages in both spaces and are copied
+    by the page fault handler on the first write
+     */
+    pub fn fork(&mut self) -> Result<AddressSpace, VmError> {
+        let mut child = AddressSpace::new()?;
+        // copied before the frame allocator is locked, since the heap takes it when it grows. when the
+        // fork fails the child releases the pages shared so far and finds nothing in the rest
+        child.areas = self.areas.clone();
+        let result = match FRAME_ALLOCATOR.lock().as_mut() {
+            Some(frame_allocator) => self.share_with(&mut child, frame_allocator),
+            None => Err(VmError::OutOfMemory),
+        };
+        // on errors the child is dropped here, after the frame allocator was unlocked
+        result.map(|_| child)
+    }
+
+    fn share_with(&mut self, child: &mut AddressSpace, frame_allocator: &mut BitmapFrameAllocator) -> Result<(), VmError> {
+        let mut parent = mapper_of(self.pml4);
+        let mut mapper = mapper_of(child.pml4);
+        for area in &self.areas {
+            // the tables stay writable, the page entries decide
+            let table_flags = PageTableFlags::PRESENT
+                | PageTableFlags::WRITABLE
+                | (area.flags & PageTableFlags::USER_ACCESSIBLE);
+            for page in area.pages() {
+                // swapped out pages share their slot instead
+                if let Some(slot) = swapped_slot(self.pml4, page) {
+                    if !swap::share(slot) {
+                        return Err(VmError::OutOfMemory);
+                    }
+                    let Some(entry) = create_entry(child.pml4, page, table_flags, frame_allocator) else {
+                        swap::release(slot);
+                        return Err(VmError::OutOfMemory);
+                    };
+                    entry.set_addr(PhysAddr::new(slot * FRAME_SIZE), SWAPPED);
+                    continue;
+                }
+                let Ok(frame) = parent.translate_page(page) else {
+                    continue;
+                };
+                let mut flags = area.flags;
+                if flags.contains(PageTableFlags::WRITABLE) {
+                    flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+                    unsafe { parent.update_flags(page, flags) }.map_err(|_| VmError::NotMapped)?.flush();
+                }
+                if !frame_allocator.share(frame) {
+                    return Err(VmError::OutOfMemory);
+                }
+                match unsafe { mapper.map_to_with_table_flags(page, frame, flags, table_flags, frame_allocator) } {
+                    Ok(flush) => flush.ignore(),
+                    Err(_) => {
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                        return Err(VmError::OutOfMemory);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // free the page tables of the user half, the mapped frames have to be freed already
+    fn free_tables(&mut self, frame_allocator: &mut BitmapFrameAllocator) {
+        fn free(table: &mut PageTable, level: u8, frame_allocator: &mut BitmapFrameAllocator) {
+            for entry in table.iter_mut().filter(|e| !e.is_unused()) {
+                let frame = PhysFrame::containing_address(entry.addr());
+                if level > 2 {
+                    free(table_at(frame), level - 1, frame_allocator);
+                }
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                entry.set_unused();
+            }
+        }
+        let pml4 = table_at(self.pml4);
+        for index in 0..USER_L4_ENTRIES {
+            if !pml4[index].is_unused() {
+                let frame = PhysFrame::containing_address(pml4[index].addr());
+                free(table_at(frame), 3, frame_allocator);
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                pml4[index].set_unused();
+            }
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // never free the table the cpu is using
+        if Cr3::read().0 == self.pml4 {
+            switch_to(None);
+        }
+        // mapped files got their changes from write_back_all when the program ended
+        for area in core::mem::take(&mut self.areas) {
+            self.unmap_pages(area.pages());
+        }
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        if let Some(frame_allocator) = frame_allocator.as_mut() {
+            self.free_tables(frame_allocator);
+            unsafe { frame_allocator.deallocate_frame(self.pml4) };
+        }
+    }
+}
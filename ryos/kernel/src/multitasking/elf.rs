@@ -0,0 +1,342 @@
+//ELF LOADER
+//Static ELF64 executables for x86_64 are loaded into a new address space: every PT_LOAD segment is
+//mapped with the permissions of its flags and the user stack gets argc, argv, envp and the auxiliary
+//vector the way the system v abi describes them. Static position independent executables (what
+//x86_64-unknown-none builds) are placed at PIE_BASE and their relative relocations applied
+use crate::memory::frame_allocator::FRAME_SIZE;
+use crate::memory::vmm::{AddressSpace, AreaKind, VmError, USER_SPACE_END, USER_SPACE_START};
+use crate::multitasking::round_robin;
+use crate::multitasking::user_mode::UserContext;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const TYPE_SHARED: u16 = 3;
+const MACHINE_X86_64: u16 = 0x3E;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+// dynamic section tags and relocation types
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+const DT_RELR: u64 = 36;
+const DYNAMIC_ENTRY_SIZE: usize = 16;
+const RELA_ENTRY_SIZE: usize = 24;
+const R_X86_64_NONE: u64 = 0;
+const R_X86_64_RELATIVE: u64 = 8;
+
+// auxiliary vector entries
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+// the stack ends one page below the end of the user half, the page above it stays unmapped
+pub const USER_STACK_TOP: u64 = USER_SPACE_END - FRAME_SIZE;
+pub const USER_STACK_SIZE: u64 = 256 * 1024;
+// where position independent executables are loaded, they are linked for address 0
+pub const PIE_BASE: u64 = 0x40_0000;
+
+#[derive(Debug)]
+pub enum ElfError {
+    NotElf,
+    // a valid elf file, but not a static x86_64 executable or with relocations other than relative ones
+    Unsupported,
+    // a header or segment points outside the file or the user half
+    Malformed,
+    // argv and envp don't fit on the stack
+    TooManyArguments,
+    // no task can be started for the program, there is no free stack slot or no memory for it
+    NoTask,
+    Vm(VmError),
+}
+
+impl From<VmError> for ElfError {
+    fn from(error: VmError) -> Self {
+        ElfError::Vm(error)
+    }
+}
+
+pub struct Program {
+    pub address_space: AddressSpace,
+    pub entry: VirtAddr,
+    // points at argc
+    pub stack_pointer: VirtAddr,
+}
+
+struct Segment {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    file_size: u64,
+    memory_size: u64,
+}
+
+// the offsets come from the file, every sum of them is checked
+fn read_bytes<const N: usize>(image: &[u8], at: usize) -> Result<[u8; N], ElfError> {
+    let end = at.checked_add(N).ok_or(ElfError::Malformed)?;
+    let bytes = image.get(at..end).ok_or(ElfError::Malformed)?;
+    Ok(bytes.try_into().unwrap())
+}
+
+fn read_u16(image: &[u8], at: usize) -> Result<u16, ElfError> {
+    read_bytes(image, at).map(u16::from_le_bytes)
+}
+
+fn read_u32(image: &[u8], at: usize) -> Result<u32, ElfError> {
+    read_bytes(image, at).map(u32::from_le_bytes)
+}
+
+fn read_u64(image: &[u8], at: usize) -> Result<u64, ElfError> {
+    read_bytes(image, at).map(u64::from_le_bytes)
+}
+
+// the offset of entry 'index' of a table at 'table', the fields of the entry can be added without overflow
+fn entry_offset(table: usize, index: usize, entry_size: usize) -> Result<usize, ElfError> {
+    let at = index
+        .checked_mul(entry_size)
+        .and_then(|offset| table.checked_add(offset))
+        .ok_or(ElfError::Malformed)?;
+    at.checked_add(entry_size).ok_or(ElfError::Malformed)?;
+    Ok(at)
+}
+
+fn segments(image: &[u8], table: usize, count: usize) -> Result<Vec<Segment>, ElfError> {
+    (0..count)
+        .map(|i| {
+            let at = entry_offset(table, i, PROGRAM_HEADER_SIZE)?;
+            Ok(Segment {
+                kind: read_u32(image, at)?,
+                flags: read_u32(image, at + 4)?,
+                offset: read_u64(image, at + 8)?,
+                vaddr: read_u64(image, at + 16)?,
+                file_size: read_u64(image, at + 32)?,
+                memory_size: read_u64(image, at + 40)?,
+            })
+        })
+        .collect()
+}
+
+/*
+this function maps the pages covering 'segment' and copies its file part, the rest up to its memory
+size stays zero (the bss). segments sharing a page with another one are rejected as Overlap
+ */
+fn load_segment(space: &mut AddressSpace, image: &[u8], segment: &Segment, base: u64) -> Result<(), ElfError> {
+    let vaddr = segment.vaddr.checked_add(base).ok_or(ElfError::Malformed)?;
+    let file_end = segment.offset.checked_add(segment.file_size).ok_or(ElfError::Malformed)?;
+    let memory_end = vaddr.checked_add(segment.memory_size).ok_or(ElfError::Malformed)?;
+    if segment.file_size > segment.memory_size
+        || file_end > image.len() as u64
+        || vaddr < USER_SPACE_START
+        || memory_end > USER_STACK_TOP - USER_STACK_SIZE
+    {
+        return Err(ElfError::Malformed);
+    }
+    if segment.memory_size == 0 {
+        return Ok(());
+    }
+    let start = VirtAddr::new(vaddr).align_down(FRAME_SIZE);
+    let end = VirtAddr::new(memory_end).align_up(FRAME_SIZE);
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if segment.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if segment.flags & PF_X == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    space.map(start, end - start, flags, AreaKind::Anonymous)?;
+    let data = &image[segment.offset as usize..file_end as usize];
+    space.write_bytes(VirtAddr::new(vaddr), data)?;
+    Ok(())
+}
+
+// the file offset of the linked address 'vaddr'
+fn file_offset(segments: &[Segment], vaddr: u64) -> Result<u64, ElfError> {
+    segments
+        .iter()
+        .find(|segment| {
+            segment.kind == PT_LOAD && vaddr >= segment.vaddr && vaddr - segment.vaddr < segment.file_size
+        })
+        .and_then(|segment| segment.offset.checked_add(vaddr - segment.vaddr))
+        .ok_or(ElfError::Malformed)
+}
+
+/*
+this function applies the relocations of a position independent executable loaded at 'base', a static
+one only has relative relocations: the word at the target becomes base + addend
+ */
+fn relocate(space: &mut AddressSpace, image: &[u8], segments: &[Segment], base: u64) -> Result<(), ElfError> {
+    let Some(dynamic) = segments.iter().find(|segment| segment.kind == PT_DYNAMIC) else {
+        return Ok(());
+    };
+    let (mut table, mut size, mut entry_size) = (None, 0, 0);
+    for i in 0..(dynamic.file_size / DYNAMIC_ENTRY_SIZE as u64) as usize {
+        let at = entry_offset(dynamic.offset as usize, i, DYNAMIC_ENTRY_SIZE)?;
+        match (read_u64(image, at)?, read_u64(image, at + 8)?) {
+            (DT_NULL, _) => break,
+            (DT_RELA, value) => table = Some(value),
+            (DT_RELASZ, value) => size = value,
+            (DT_RELAENT, value) => entry_size = value,
+            (DT_REL | DT_RELR, _) => return Err(ElfError::Unsupported),
+            _ => {}
+        }
+    }
+    let Some(table) = table else {
+        return Ok(());
+    };
+    if entry_size < RELA_ENTRY_SIZE as u64 {
+        return Err(ElfError::Malformed);
+    }
+    let offset = file_offset(segments, table)? as usize;
+    for i in 0..(size / entry_size) as usize {
+        let at = entry_offset(offset, i, entry_size as usize)?;
+        let (target, info, addend) = (read_u64(image, at)?, read_u64(image, at + 8)?, read_u64(image, at + 16)?);
+        match info & 0xFFFF_FFFF {
+            R_X86_64_NONE => {}
+            R_X86_64_RELATIVE => {
+                let target = VirtAddr::try_new(base.wrapping_add(target)).map_err(|_| ElfError::Malformed)?;
+                space.write_bytes(target, &base.wrapping_add(addend).to_le_bytes())?;
+            }
+            _ => return Err(ElfError::Unsupported),
+        }
+    }
+    Ok(())
+}
+
+/*
+this function builds the initial stack below USER_STACK_TOP: the strings of 'argv' and 'envp' at the
+top, below them the auxiliary vector, the envp and argv pointer arrays and argc. returns the stack
+pointer, which is 16 byte aligned
+ */
+fn build_stack(
+    space: &mut AddressSpace,
+    argv: &[&str],
+    envp: &[&str],
+    aux: &[(u64, u64)],
+) -> Result<VirtAddr, ElfError> {
+    let strings: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (aux.len() + 1);
+    let size = (strings + words * 8).next_multiple_of(16) as u64;
+    if size > USER_STACK_SIZE / 2 {
+        return Err(ElfError::TooManyArguments);
+    }
+    let bottom = USER_STACK_TOP - size;
+
+    let mut pointers = Vec::new();
+    let mut text = Vec::new();
+    let text_start = USER_STACK_TOP - strings as u64;
+    for s in argv.iter().chain(envp) {
+        pointers.push(text_start + text.len() as u64);
+        text.extend_from_slice(s.as_bytes());
+        text.push(0);
+    }
+
+    let mut table: Vec<u64> = Vec::with_capacity(words);
+    table.push(argv.len() as u64);
+    table.extend_from_slice(&pointers[..argv.len()]);
+    table.push(0);
+    table.extend_from_slice(&pointers[argv.len()..]);
+    table.push(0);
+    for &(key, value) in aux.iter().chain(&[(AT_NULL, 0)]) {
+        table.push(key);
+        table.push(value);
+    }
+    let bytes: Vec<u8> = table.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    space.write_bytes(VirtAddr::new(bottom), &bytes)?;
+    space.write_bytes(VirtAddr::new(text_start), &text)?;
+    Ok(VirtAddr::new(bottom))
+}
+
+// load the executable in 'image' into a new address space, argv[0] is the program name by convention
+pub fn load(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, ElfError> {
+    if image.len() < HEADER_SIZE || image[0..4] != MAGIC {
+        return Err(ElfError::NotElf);
+    }
+    if image[4] != CLASS_64 || image[5] != LITTLE_ENDIAN || read_u16(image, 18)? != MACHINE_X86_64 {
+        return Err(ElfError::Unsupported);
+    }
+    let base = match read_u16(image, 16)? {
+        TYPE_EXECUTABLE => 0,
+        TYPE_SHARED => PIE_BASE,
+        _ => return Err(ElfError::Unsupported),
+    };
+    let entry = read_u64(image, 24)?.checked_add(base).ok_or(ElfError::Malformed)?;
+    let table = read_u64(image, 32)? as usize;
+    let entry_size = read_u16(image, 54)? as usize;
+    let count = read_u16(image, 56)? as usize;
+    if entry_size != PROGRAM_HEADER_SIZE {
+        return Err(ElfError::Malformed);
+    }
+
+    let mut space = AddressSpace::new()?;
+    let segments = segments(image, table, count)?;
+    let mut headers = None;
+    for segment in segments.iter().filter(|segment| segment.kind == PT_LOAD) {
+        load_segment(&mut space, image, segment, base)?;
+        // the program headers are visible to the program when a segment loads them
+        if (segment.offset..segment.offset + segment.file_size).contains(&(table as u64)) {
+            headers = Some(base + segment.vaddr + (table as u64 - segment.offset));
+        }
+    }
+    if base != 0 {
+        relocate(&mut space, image, &segments, base)?;
+    }
+    let executable = space
+        .find_area(VirtAddr::try_new(entry).map_err(|_| ElfError::Malformed)?)
+        .is_some_and(|area| !area.flags.contains(PageTableFlags::NO_EXECUTE));
+    if !executable {
+        return Err(ElfError::Malformed);
+    }
+
+    let stack_flags = PageTableFlags::PRESENT
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE;
+    space.reserve(
+        VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE),
+        USER_STACK_SIZE,
+        stack_flags,
+        AreaKind::Stack,
+    )?;
+    let mut aux = Vec::new();
+    if let Some(headers) = headers {
+        aux.extend_from_slice(&[(AT_PHDR, headers), (AT_PHENT, PROGRAM_HEADER_SIZE as u64), (AT_PHNUM, count as u64)]);
+    }
+    aux.extend_from_slice(&[(AT_PAGESZ, FRAME_SIZE), (AT_ENTRY, entry)]);
+    let stack_pointer = build_stack(&mut space, argv, envp, &aux)?;
+
+    Ok(Program {
+        address_space: space,
+        entry: VirtAddr::new(entry),
+        stack_pointer,
+    })
+}
+
+// load 'image' and start it as a child process of the running task, returns its pid
+pub fn spawn(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<usize, ElfError> {
+    let program = load(image, argv, envp)?;
+    round_robin::add_user_task(
+        argv.first().copied().unwrap_or("program"),
+        Arc::new(Mutex::new(program.address_space)),
+        UserContext::start(program.entry, program.stack_pointer),
+    )
+    .ok_or(ElfError::NoTask)
+}
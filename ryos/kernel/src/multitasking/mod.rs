@@ -1 +1,6 @@
-pub mod round_robin;
\ No newline at end of file
+pub mod elf;
+pub mod process;
+pub mod round_robin;
+pub mod signal;
+pub mod user_mode;
+pub mod wait_list;
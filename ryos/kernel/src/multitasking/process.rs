@@ -0,0 +1,326 @@
+//PROCESSES
+//Every task belongs to the process with the same id. A process knows its parent and owns the address
+//space of its task, its working directory, its file descriptors and its capabilities. A new process
+//starts with copies of its parent's directory and files, only a forked one also gets the capabilities.
+//When it exits it keeps only its exit code and stays a zombie until the parent
+//waits for it and its parent gets SIGCHLD. The children of an exiting process are handed to
+//process 0, the kernel and shell
+use crate::file_system::file_table::FileTable;
+use crate::ipc::capability::CapabilityTable;
+use crate::memory::vmm::{self, AddressSpace};
+use crate::multitasking::round_robin;
+use crate::multitasking::signal::{Action, Delivery, SignalError, SignalState, SIGCHLD};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+// the process of the main kernel task, it adopts orphans and never exits
+pub const KERNEL_PID: usize = 0;
+// the exit code of processes killed by an exception
+pub const KILLED_EXIT_CODE: i32 = -1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    Zombie(i32),
+}
+
+pub struct Process {
+    pub pid: usize,
+    pub parent: usize,
+    pub name: String,
+    pub state: State,
+    // absolute path ending in '/', relative paths of the process start here
+    pub working_dir: String,
+    // dropped on exit, a zombie holds no memory and no files
+    address_space: Option<Arc<Mutex<AddressSpace>>>,
+    files: FileTable,
+    capabilities: CapabilityTable,
+    signals: SignalState,
+}
+
+// what wait returns about a reaped child
+pub struct Exited {
+    pub pid: usize,
+    pub name: String,
+    pub code: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitError {
+    // the process has no (matching) child to wait for
+    NoChild,
+    // a signal arrived before a child exited
+    Interrupted,
+}
+
+static PROCESSES: Mutex<BTreeMap<usize, Process>> = Mutex::new(BTreeMap::new());
+
+// the process table is also used from tasks that get preempted, the timer must not switch away while it is locked
+fn with_processes<R>(f: impl FnOnce(&mut BTreeMap<usize, Process>) -> R) -> R {
+    without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        if !processes.contains_key(&KERNEL_PID) {
+            processes.insert(
+                KERNEL_PID,
+                Process {
+                    pid: KERNEL_PID,
+                    parent: KERNEL_PID,
+                    name: String::from("kernel"),
+                    state: State::Running,
+                    working_dir: String::from("/"),
+                    address_space: None,
+                    files: FileTable::with_console(),
+                    capabilities: CapabilityTable::new(),
+                    signals: SignalState::new(),
+                },
+            );
+        }
+        f(&mut processes)
+    })
+}
+
+/*
+this function is called by the task manager for each new task, the process inherits the working
+directory and the files of 'parent'. a 'forked' process runs the parent's program and also keeps
+its signal handlers and capabilities
+ */
+pub fn create(
+    pid: usize,
+    parent: usize,
+    name: &str,
+    address_space: Option<Arc<Mutex<AddressSpace>>>,
+    forked: bool,
+) {
+    with_processes(|processes| {
+        let (working_dir, files, mut signals) = match processes.get(&parent) {
+            Some(parent) => (parent.working_dir.clone(), parent.files.clone(), parent.signals.inherited()),
+            None => (String::from("/"), FileTable::with_console(), SignalState::new()),
+        };
+        let capabilities = match (forked, processes.get(&parent)) {
+            (true, Some(parent)) => parent.capabilities.clone(),
+            _ => CapabilityTable::new(),
+        };
+        if !forked {
+            signals.reset_handlers();
+        }
+        processes.insert(
+            pid,
+            Process {
+                pid,
+                parent,
+                name: String::from(name),
+                state: State::Running,
+                working_dir,
+                address_space,
+                files,
+                capabilities,
+                signals,
+            },
+        );
+    });
+}
+
+// the process of the running task, kernel code outside of any task counts as the kernel process
+pub fn current_pid() -> usize {
+    round_robin::current_task().map_or(KERNEL_PID, |task| task.id)
+}
+
+pub fn current_dir() -> String {
+    let pid = current_pid();
+    with_processes(|processes| processes.get(&pid).map(|process| process.working_dir.clone()))
+        .unwrap_or_else(|| String::from("/"))
+}
+
+// 'dir' has to be an absolute path ending in '/'
+pub fn set_current_dir(dir: String) {
+    let pid = current_pid();
+    with_processes(|processes| {
+        if let Some(process) = processes.get_mut(&pid) {
+            process.working_dir = dir;
+        }
+    });
+}
+
+// run 'f' on the file descriptors of the running process, None if it has no process
+pub fn with_files<R>(f: impl FnOnce(&mut FileTable) -> R) -> Option<R> {
+    let pid = current_pid();
+    with_processes(|processes| processes.get_mut(&pid).map(|process| f(&mut process.files)))
+}
+
+/*
+this function runs 'f' on the capabilities of the running process. capabilities 'f' takes out of
+the table have to be dropped after it returned, dropping one may close a port
+ */
+pub fn with_capabilities<R>(f: impl FnOnce(&mut CapabilityTable) -> R) -> Option<R> {
+    let pid = current_pid();
+    with_processes(|processes| processes.get_mut(&pid).map(|process| f(&mut process.capabilities)))
+}
+
+// after exec 'pid' runs another program, returns the old address space for the caller to drop
+pub fn replace_image(
+    pid: usize,
+    name: &str,
+    address_space: Arc<Mutex<AddressSpace>>,
+) -> Option<Arc<Mutex<AddressSpace>>> {
+    with_processes(|processes| {
+        let process = processes.get_mut(&pid)?;
+        process.name = String::from(name);
+        process.signals.reset_handlers();
+        process.address_space.replace(address_space)
+    })
+}
+
+/*
+this function turns 'pid' into a zombie with 'code' and gives its children to the kernel process,
+the task itself is removed by the caller
+ */
+pub fn exit(pid: usize, code: i32) {
+    if pid == KERNEL_PID {
+        return;
+    }
+    let capabilities = with_processes(|processes| {
+        for process in processes.values_mut().filter(|process| process.parent == pid) {
+            process.parent = KERNEL_PID;
+        }
+        let process = processes.get_mut(&pid)?;
+        process.state = State::Zombie(code);
+        process.address_space = None;
+        process.files = FileTable::new();
+        let capabilities = core::mem::replace(&mut process.capabilities, CapabilityTable::new());
+        let parent = process.parent;
+        if let Some(parent) = processes.get_mut(&parent) {
+            parent.signals.raise(SIGCHLD);
+        }
+        Some(capabilities)
+    });
+    // closing the ports frees the pages of their queued calls, which needs the frame allocator
+    drop(capabilities);
+}
+
+pub fn is_running(pid: usize) -> bool {
+    with_processes(|processes| processes.get(&pid).is_some_and(|process| process.state == State::Running))
+}
+
+pub fn parent_of(pid: usize) -> Option<usize> {
+    with_processes(|processes| processes.get(&pid).map(|process| process.parent))
+}
+
+// mark 'signal' pending for the running process 'pid'
+pub fn send(pid: usize, signal: u8) -> Result<(), SignalError> {
+    with_processes(|processes| match processes.get_mut(&pid) {
+        Some(process) if process.state == State::Running => {
+            process.signals.raise(signal);
+            // a task parked on a wait list notices the signal
+            round_robin::unpark(pid);
+            Ok(())
+        }
+        _ => Err(SignalError::NoProcess),
+    })
+}
+
+// the pending signal that ends 'pid', if there is one
+pub fn terminating_signal(pid: usize) -> Option<u8> {
+    with_processes(|processes| processes.get(&pid).and_then(|process| process.signals.terminating()))
+}
+
+// blocking system calls give up when this is true, so the signal is delivered
+pub fn signal_pending(pid: usize) -> bool {
+    with_processes(|processes| processes.get(&pid).is_some_and(|process| process.signals.any_pending()))
+}
+
+pub fn take_signal(pid: usize) -> Option<Delivery> {
+    with_processes(|processes| processes.get_mut(&pid).and_then(|process| process.signals.take()))
+}
+
+pub fn set_signal_action(pid: usize, signal: u8, action: Action) -> Result<Action, SignalError> {
+    with_processes(|processes| {
+        let process = processes.get_mut(&pid).ok_or(SignalError::NoProcess)?;
+        process.signals.set_action(signal, action)
+    })
+}
+
+// the running children of 'parent'
+pub fn running_children(parent: usize) -> Vec<usize> {
+    with_processes(|processes| {
+        processes
+            .values()
+            .filter(|process| process.pid != parent && process.parent == parent && process.state == State::Running)
+            .map(|process| process.pid)
+            .collect()
+    })
+}
+
+pub fn name_of(pid: usize) -> Option<String> {
+    with_processes(|processes| processes.get(&pid).map(|process| process.name.clone()))
+}
+
+/*
+this function reaps a zombie child of 'parent', 'target' None takes any child. Ok(None) means there
+are children but none of them has exited yet
+ */
+pub fn try_wait(parent: usize, target: Option<usize>) -> Result<Option<Exited>, WaitError> {
+    with_processes(|processes| {
+        let mut children = processes
+            .values()
+            .filter(|process| process.pid != parent && process.parent == parent)
+            .filter(|process| target.is_none_or(|pid| process.pid == pid))
+            .peekable();
+        if children.peek().is_none() {
+            return Err(WaitError::NoChild);
+        }
+        let Some((pid, code)) = children.find_map(|process| match process.state {
+            State::Zombie(code) => Some((process.pid, code)),
+            State::Running => None,
+        }) else {
+            return Ok(None);
+        };
+        let process = processes.remove(&pid).expect("zombie vanished from the process table");
+        Ok(Some(Exited { pid, name: process.name, code }))
+    })
+}
+
+// wait until a child of 'parent' (or the child 'target') exits and reap it, a signal sent to 'parent' ends the wait
+pub fn wait(parent: usize, target: Option<usize>) -> Result<Exited, WaitError> {
+    loop {
+        if let Some(exited) = try_wait(parent, target)? {
+            return Ok(exited);
+        }
+        if signal_pending(parent) {
+            return Err(WaitError::Interrupted);
+        }
+        without_interrupts(round_robin::schedule);
+        vmm::reclaim_retired();
+    }
+}
+
+// what ps shows about a process
+pub struct ProcessInfo {
+    pub pid: usize,
+    pub parent: usize,
+    pub name: String,
+    pub state: State,
+    pub working_dir: String,
+    pub open_files: usize,
+    pub capabilities: usize,
+}
+
+pub fn list() -> Vec<ProcessInfo> {
+    with_processes(|processes| {
+        processes
+            .values()
+            .map(|process| ProcessInfo {
+                pid: process.pid,
+                parent: process.parent,
+                name: process.name.clone(),
+                state: process.state,
+                working_dir: process.working_dir.clone(),
+                open_files: process.files.open_count(),
+                capabilities: process.capabilities.count(),
+            })
+            .collect()
+    })
+}
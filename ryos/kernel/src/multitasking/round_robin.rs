@@ -1,5 +1,12 @@
-use crate::{print, println};
-use alloc::boxed::Box;
+use crate::interrupts::gdt;
+use crate::memory::stack::{TaskStack, DEFAULT_STACK_SIZE};
+use crate::multitasking::{process, signal};
+use crate::multitasking::user_mode::{self, UserContext};
+use crate::memory::vmm::{self, AddressSpace};
+use crate::syscall;
+use crate::{eprintln, print, println};
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::{asm, naked_asm};
 use core::ptr::null_mut;
@@ -7,38 +14,82 @@ use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use x86_64::instructions::interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::VirtAddr;
 
-const STACK_SIZE: usize = 512;
+// return address, entry point, 15 registers and the flags pushed by switch_context
+const INITIAL_FRAME: usize = 18;
 #[repr(align(16))]
 pub struct Task {
-    stack: Option<Box<[u64; STACK_SIZE]>>,
+    stack: Option<TaskStack>,
     id: usize,
     pub rsp: u64,
+    // None for tasks that only run in the kernel address space
+    address_space: Option<Arc<Mutex<AddressSpace>>>,
+    pml4: Option<PhysFrame>,
+    // the registers a program starts with, the task enters user mode with them
+    user_context: Option<UserContext>,
 }
 impl Task {
-    pub fn new(func: extern "C" fn(), id: usize) -> Self {
-        let mut stack = Box::new([0; STACK_SIZE]);
-        stack[STACK_SIZE - 1] = remove_task as u64;
-        stack[STACK_SIZE - 2] = func as u64;
+    // None when no stack can be allocated for the task
+    pub fn new(func: extern "C" fn(), id: usize, stack_size: usize) -> Option<Self> {
+        let stack = TaskStack::allocate(stack_size, id)?;
+        let frame_start = stack.top().as_mut_ptr::<u64>().wrapping_sub(INITIAL_FRAME);
+        let frame = unsafe { core::slice::from_raw_parts_mut(frame_start, INITIAL_FRAME) };
+        frame[INITIAL_FRAME - 1] = task_returned as u64;
+        frame[INITIAL_FRAME - 2] = func as u64;
         for i in 0..16 {
-            stack[STACK_SIZE - 3 - i] = 0
+            frame[INITIAL_FRAME - 3 - i] = 0
         }
-        stack[STACK_SIZE - 18] = 0x202;
-        Task {
-            rsp: stack.as_ptr() as u64 + (((STACK_SIZE - 18) as u64) * 8),
+        frame[0] = 0x202;
+        Some(Task {
+            rsp: frame_start as u64,
             stack: Some(stack),
             id,
+            address_space: None,
+            pml4: None,
+            user_context: None,
+        })
+    }
+
+    // run the task inside 'address_space' instead of the kernel one
+    pub fn with_address_space(mut self, address_space: Arc<Mutex<AddressSpace>>) -> Self {
+        self.pml4 = Some(address_space.lock().pml4());
+        self.address_space = Some(address_space);
+        self
+    }
+    // load the address space of the task and the kernel stack used when it is interrupted in user mode
+    // or makes a system call
+    fn activate(&self) {
+        vmm::switch_to(self.pml4);
+        if let Some(stack) = &self.stack {
+            gdt::set_kernel_stack(stack.top());
+            syscall::entry::set_kernel_stack(stack.top());
         }
     }
+
     fn new_main() -> Self {
         Task {
             rsp: 0,
             stack: None,
             id: 0,
+            address_space: None,
+            pml4: None,
+            user_context: None,
         }
     }
 }
 
+// what the exception handlers need to know about the running task
+pub struct CurrentTask {
+    pub id: usize,
+    // None for the main kernel task, which can't be killed
+    pub stack_top: Option<VirtAddr>,
+    pub address_space: Option<Arc<Mutex<AddressSpace>>>,
+    pub user_context: Option<UserContext>,
+}
+
 pub struct TaskManager {
     tasks: Vec<Task>,
     current_task: u32,
@@ -60,12 +111,81 @@ impl TaskManager {
         }
     }
 
+    fn current(&self) -> CurrentTask {
+        let task = &self.tasks[self.running as usize];
+        CurrentTask {
+            id: task.id,
+            stack_top: task.stack.as_ref().map(|stack| stack.top()),
+            address_space: task.address_space.clone(),
+            user_context: task.user_context,
+        }
+    }
+
     fn delete_current(&mut self) {
         self.delete = Some(self.running);
     }
-    pub fn add_task(&mut self, function: extern "C" fn()) {
-        self.tasks.push(Task::new(function, self.next_id as usize));
+
+    /*
+    this function starts 'task' as a child process of the running task, its process is registered
+    before the task can run and exit
+     */
+    fn push(&mut self, task: Task, name: &str, forked: bool) -> usize {
+        let id = task.id;
+        let parent = self.tasks[self.running as usize].id;
+        process::create(id, parent, name, task.address_space.clone(), forked);
+        self.tasks.push(task);
         self.next_id += 1;
+        id
+    }
+
+    pub fn add_task(&mut self, function: extern "C" fn(), stack_size: usize) -> Option<usize> {
+        let task = Task::new(function, self.next_id as usize, stack_size)?;
+        Some(self.push(task, "kernel task", false))
+    }
+
+    pub fn add_task_in(
+        &mut self,
+        function: extern "C" fn(),
+        address_space: Arc<Mutex<AddressSpace>>,
+    ) -> Option<usize> {
+        let task = Task::new(function, self.next_id as usize, DEFAULT_STACK_SIZE)?.with_address_space(address_space);
+        Some(self.push(task, "kernel task", false))
+    }
+
+    pub fn add_user_task(
+        &mut self,
+        name: &str,
+        address_space: Arc<Mutex<AddressSpace>>,
+        context: UserContext,
+        forked: bool,
+    ) -> Option<usize> {
+        let mut task = Task::new(user_mode::start_program, self.next_id as usize, DEFAULT_STACK_SIZE)?
+            .with_address_space(address_space);
+        task.user_context = Some(context);
+        Some(self.push(task, name, forked))
+    }
+
+    fn replace_address_space(&mut self, address_space: Arc<Mutex<AddressSpace>>) -> Option<Arc<Mutex<AddressSpace>>> {
+        let task = &mut self.tasks[self.running as usize];
+        task.pml4 = Some(address_space.lock().pml4());
+        let old = task.address_space.replace(address_space);
+        vmm::switch_to(task.pml4);
+        old
+    }
+
+    /*
+    this function returns the index of the task after 'current_task' that runs next. parked tasks and
+    the task that is being deleted are skipped. the main task (the shell) takes its turn like every other
+    task, so it keeps running next to background jobs. when every task is parked the main task runs
+     */
+    fn next_task(&self) -> u32 {
+        let count = self.tasks.len() as u32;
+        with_parked(|parked| {
+            (1..=count)
+                .map(|step| (self.current_task + step) % count)
+                .find(|&index| self.delete != Some(index) && !parked.contains(&self.tasks[index as usize].id))
+                .unwrap_or(0)
+        })
     }
 
     pub fn schedule(&mut self) {
@@ -73,20 +193,31 @@ impl TaskManager {
             return;
         }
 
+        let next = self.next_task();
+        // no other task can run, a parked task that gets here checks what it waits for again
+        if next == self.running && self.delete.is_none() {
+            return;
+        }
         let old_task_rsp: *mut u64 = &mut self.tasks[self.running as usize].rsp;
-        self.current_task = (self.current_task + 1) % self.tasks.len() as u32;
+        self.current_task = next;
         let new_rsp = self.tasks[self.current_task as usize].rsp;
+        let mut deleted = false;
 
         // in case that one index has been deleted last schedule
         if let Some(delete_index) = self.delete.take() {
             if delete_index < self.tasks.len() as u32 {
-                self.tasks.remove(delete_index as usize);
+                deleted = true;
+                // the task's address space is torn down later in task context, not here with the task manager locked
+                if let Some(space) = self.tasks.remove(delete_index as usize).address_space {
+                    vmm::retire(space);
+                }
                 //Adjust current task index if necessary(remove shift left by one all the indexes that greater than the removed index
                 if self.current_task > delete_index {
                     self.current_task -= 1;
                 }
                 // in case the main is the only task that remain run the main
                 if self.tasks.len() == 1 {
+                    self.tasks[0].activate();
                     unsafe {
                         unsafe { TASK_MANAGER.force_unlock() };
                         switch_context(self.tasks[0].rsp, null_mut());
@@ -97,6 +228,7 @@ impl TaskManager {
                 if (self.running == 1 ) && self.tasks.len() == 2 {
                     self.running = 1;
                     self.current_task = 1;
+                    self.tasks[1].activate();
                     unsafe {
                         unsafe { TASK_MANAGER.force_unlock() };
                         switch_context(self.tasks[1].rsp, null_mut());
@@ -105,17 +237,10 @@ impl TaskManager {
             }
         }
 
-        if self.tasks.len() == 2 && self.running == 1 {
-            self.current_task = 1;
-            return;
-        }
-        // in case of the current task is the main and there is more tasks skip
-        if self.current_task == 0{
-            schedule();
-            return;
-        }
-
+        // the slot of a deleted task may belong to another task now, its context isn't saved
+        let old_task_rsp = if deleted { null_mut() } else { old_task_rsp };
         interrupts::without_interrupts(|| {
+            self.tasks[self.current_task as usize].activate();
             unsafe { TASK_MANAGER.force_unlock() };
             self.running = self.current_task;
             unsafe {
@@ -189,8 +314,114 @@ fn remove_task() {
     TASK_MANAGER.lock().delete_current();
     schedule();
 }
-pub fn add_task(func: extern "C" fn()) {
-    TASK_MANAGER.lock().add_task(func);
+
+// the ids of the tasks waiting on a wait list, the scheduler skips them until they are unparked
+static PARKED: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
+// the scheduler and interrupt handlers (a signal unparks its task) take the lock as well
+fn with_parked<R>(f: impl FnOnce(&mut BTreeSet<usize>) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut PARKED.lock()))
+}
+
+/*
+this function parks the running task and returns its id, it keeps running until it gives up the cpu
+with schedule. the main task is parked as well, it then runs again when no other task can
+ */
+pub fn park_current() -> Option<usize> {
+    let id = current_task()?.id;
+    with_parked(|parked| parked.insert(id));
+    Some(id)
+}
+
+// the task 'id' can be scheduled again
+pub fn unpark(id: usize) {
+    with_parked(|parked| parked.remove(&id));
+}
+
+// called from exception handlers, so it never waits for the task manager
+pub fn current_task() -> Option<CurrentTask> {
+    TASK_MANAGER.try_lock().map(|manager| manager.current())
+}
+
+// end the running task from its own context with 'code' as the exit code of its process, the exit
+// system call leaves through here
+pub fn exit_current(code: i32) -> ! {
+    if let Some(task) = current_task() {
+        // changes to mapped files that were never synced, the teardown of the address space doesn't write
+        if let Some(space) = &task.address_space {
+            if let Err(e) = space.lock().write_back_all() {
+                eprintln!("task {}: writing back mapped files failed {:?}", task.id, e);
+            }
+        }
+        process::exit(task.id, code);
+    }
+    vmm::reclaim_retired();
+    remove_task();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+// task functions return here
+extern "C" fn task_returned() -> ! {
+    exit_current(0)
+}
+
+// tasks killed by an exception or, in user mode, by a signal
+pub extern "C" fn exit_killed_task() -> ! {
+    let code = current_task()
+        .and_then(|task| process::terminating_signal(task.id))
+        .map_or(process::KILLED_EXIT_CODE, signal::exit_code);
+    exit_current(code)
+}
+
+/*
+this function makes the interrupted task exit instead of going back to the
+instruction that faulted, 'stack_top' is the top of its kernel stack (the faulting stack pointer may
+be inside the guard area or in user memory). tasks interrupted in user mode return to ring 0
+ */
+pub fn kill_current(stack_frame: &mut InterruptStackFrame, stack_top: VirtAddr) {
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.instruction_pointer = VirtAddr::new(exit_killed_task as u64);
+            frame.stack_pointer = stack_top - 8u64;
+            frame.code_segment = gdt::kernel_code_selector();
+            frame.stack_segment = gdt::kernel_data_selector();
+        });
+    }
+}
+// the task functions return the id of the new task, which is also the pid of its process, or None
+// when there is no free stack slot or no memory for its stack
+pub fn add_task(func: extern "C" fn()) -> Option<usize> {
+    interrupts::without_interrupts(|| TASK_MANAGER.lock().add_task(func, DEFAULT_STACK_SIZE))
+}
+
+// 'stack_size' is rounded up to whole pages and may be up to MAX_STACK_SIZE
+pub fn add_task_with_stack(func: extern "C" fn(), stack_size: usize) -> Option<usize> {
+    interrupts::without_interrupts(|| TASK_MANAGER.lock().add_task(func, stack_size))
+}
+
+pub fn add_task_in(func: extern "C" fn(), address_space: Arc<Mutex<AddressSpace>>) -> Option<usize> {
+    vmm::make_swappable(&address_space);
+    interrupts::without_interrupts(|| TASK_MANAGER.lock().add_task_in(func, address_space))
+}
+
+// start a task that runs a program in user mode from 'context', 'name' is shown by ps
+pub fn add_user_task(name: &str, address_space: Arc<Mutex<AddressSpace>>, context: UserContext) -> Option<usize> {
+    vmm::make_swappable(&address_space);
+    interrupts::without_interrupts(|| TASK_MANAGER.lock().add_user_task(name, address_space, context, false))
+}
+
+// fork: like add_user_task, but the child keeps the signal handlers of the running process
+pub fn add_forked_task(name: &str, address_space: Arc<Mutex<AddressSpace>>, context: UserContext) -> Option<usize> {
+    vmm::make_swappable(&address_space);
+    interrupts::without_interrupts(|| TASK_MANAGER.lock().add_user_task(name, address_space, context, true))
+}
+
+// exec: the running task switches to 'address_space', the caller drops the old one outside of any lock
+pub fn replace_address_space(address_space: Arc<Mutex<AddressSpace>>) -> Option<Arc<Mutex<AddressSpace>>> {
+    vmm::make_swappable(&address_space);
+    interrupts::without_interrupts(|| TASK_MANAGER.lock().replace_address_space(address_space))
 }
 
 
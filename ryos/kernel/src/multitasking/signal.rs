@@ -0,0 +1,262 @@
+//SIGNALS
+//Signals are sent to processes with kill, Ctrl+C sends SIGINT to the foreground job of the shell.
+//Without a handler SIGCHLD is ignored and every other signal ends the process with exit code
+//128 + signal. Programs run their handlers when they return from a system call, a program that is
+//busy in user mode is only ended right away. Kernel tasks can't have handlers and end at the
+//checkpoints they call exit_if_terminated from
+use crate::memory::vmm::USER_SPACE_END;
+use crate::multitasking::process::{self, KERNEL_PID};
+use crate::multitasking::round_robin;
+use crate::multitasking::user_mode::{UserContext, STATUS_FLAGS, USER_FLAGS};
+use crate::syscall::user_memory::{copy_from_user, copy_to_user};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::PrivilegeLevel;
+
+pub const SIGINT: u8 = 2;
+pub const SIGKILL: u8 = 9;
+// sent to a process that writes to a pipe nobody reads anymore
+pub const SIGPIPE: u8 = 13;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+// signal numbers go from 1 to SIGNAL_COUNT - 1
+pub const SIGNAL_COUNT: u8 = 32;
+
+// what the keyboard decodes Ctrl+C to
+pub const INTERRUPT_KEY: char = '\u{3}';
+
+// the handler values of sigaction that aren't addresses
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+// the stack below a program's stack pointer that its compiler may use without moving it
+const RED_ZONE: u64 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Default,
+    Ignore,
+    // 'restorer' is where the handler returns to, it has to make the sigreturn system call
+    Handler { handler: u64, restorer: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalError {
+    InvalidSignal,
+    NoProcess,
+    NotPermitted,
+}
+
+// what a process does about the signals it was sent
+pub enum Delivery {
+    Terminate(u8),
+    Handle { signal: u8, handler: u64, restorer: u64 },
+}
+
+#[derive(Clone)]
+pub struct SignalState {
+    pending: u32,
+    actions: [Action; SIGNAL_COUNT as usize],
+}
+
+fn valid(signal: u8) -> bool {
+    (1..SIGNAL_COUNT).contains(&signal)
+}
+
+// the exit code of a process ended by 'signal'
+pub fn exit_code(signal: u8) -> i32 {
+    128 + signal as i32
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        SignalState {
+            pending: 0,
+            actions: [Action::Default; SIGNAL_COUNT as usize],
+        }
+    }
+
+    // what a child started with fork gets: the same actions and nothing pending
+    pub fn inherited(&self) -> Self {
+        SignalState { pending: 0, actions: self.actions }
+    }
+
+    // exec starts a program that doesn't have the old handlers, ignored signals stay ignored
+    pub fn reset_handlers(&mut self) {
+        for action in self.actions.iter_mut() {
+            if matches!(action, Action::Handler { .. }) {
+                *action = Action::Default;
+            }
+        }
+    }
+
+    pub fn action(&self, signal: u8) -> Action {
+        self.actions[signal as usize]
+    }
+
+    // returns the old action, SIGKILL always ends the process
+    pub fn set_action(&mut self, signal: u8, action: Action) -> Result<Action, SignalError> {
+        if !valid(signal) || signal == SIGKILL {
+            return Err(SignalError::InvalidSignal);
+        }
+        Ok(core::mem::replace(&mut self.actions[signal as usize], action))
+    }
+
+    fn ignored(&self, signal: u8) -> bool {
+        match self.action(signal) {
+            _ if signal == SIGKILL => false,
+            Action::Ignore => true,
+            Action::Default => signal == SIGCHLD,
+            Action::Handler { .. } => false,
+        }
+    }
+
+    // mark 'signal' pending unless the process ignores it
+    pub fn raise(&mut self, signal: u8) {
+        if !self.ignored(signal) {
+            self.pending |= 1 << signal;
+        }
+    }
+
+    pub fn any_pending(&self) -> bool {
+        self.pending != 0
+    }
+
+    // the lowest pending signal that ends the process
+    pub fn terminating(&self) -> Option<u8> {
+        (1..SIGNAL_COUNT).find(|&signal| {
+            self.pending & (1 << signal) != 0 && (signal == SIGKILL || self.action(signal) == Action::Default)
+        })
+    }
+
+    // the next signal to deliver, it isn't pending anymore afterwards
+    pub fn take(&mut self) -> Option<Delivery> {
+        if let Some(signal) = self.terminating() {
+            return Some(Delivery::Terminate(signal));
+        }
+        let signal = (1..SIGNAL_COUNT).find(|&signal| self.pending & (1 << signal) != 0)?;
+        self.pending &= !(1 << signal);
+        match self.action(signal) {
+            Action::Handler { handler, restorer } => Some(Delivery::Handle { signal, handler, restorer }),
+            // the action changed to ignore after the signal was sent
+            _ => None,
+        }
+    }
+}
+
+// the processes the shell waits for, Ctrl+C interrupts them and their children. empty while the
+// shell reads a command
+static FOREGROUND: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+// set when Ctrl+C interrupted the foreground job, the shell echoes ^C. printing from the keyboard
+// interrupt could wait for the writer lock the interrupted code holds
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_foreground(pids: Vec<usize>) {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    without_interrupts(|| *FOREGROUND.lock() = pids);
+}
+
+// true once after Ctrl+C interrupted the foreground job
+pub fn take_interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::Relaxed)
+}
+
+// called by the keyboard interrupt for Ctrl+C
+pub fn interrupt_foreground() {
+    let pids = FOREGROUND.lock().clone();
+    if pids.is_empty() {
+        return;
+    }
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    let mut pids = pids;
+    while let Some(pid) = pids.pop() {
+        let _ = process::send(pid, SIGINT);
+        pids.extend(process::running_children(pid));
+    }
+}
+
+// send 'signal' to 'pid', the kernel process and the shell in it can't be signalled
+pub fn kill(pid: usize, signal: u8) -> Result<(), SignalError> {
+    if !valid(signal) {
+        return Err(SignalError::InvalidSignal);
+    }
+    if pid == KERNEL_PID {
+        return Err(SignalError::NotPermitted);
+    }
+    process::send(pid, signal)
+}
+
+// a checkpoint for kernel tasks, they end here when a signal terminated them
+pub fn exit_if_terminated() {
+    if let Some(signal) = process::terminating_signal(process::current_pid()) {
+        round_robin::exit_current(exit_code(signal));
+    }
+}
+
+/*
+this function is called by the timer interrupt, a task interrupted in user mode holds no kernel
+locks and is ended right away when a signal terminated it
+ */
+pub fn terminate_interrupted(stack_frame: &mut InterruptStackFrame) {
+    if stack_frame.code_segment.rpl() != PrivilegeLevel::Ring3 {
+        return;
+    }
+    let Some(task) = round_robin::current_task() else {
+        return;
+    };
+    if let (Some(stack_top), Some(_)) = (task.stack_top, process::terminating_signal(task.id)) {
+        round_robin::kill_current(stack_frame, stack_top);
+    }
+}
+
+/*
+this function runs before a system call returns to the program: it ends the process for a
+terminating signal, or makes it continue in the handler of a caught signal. the handler gets the
+signal number in rdi and returns to the restorer, with the interrupted registers saved above it
+ */
+pub fn deliver(registers: &mut UserContext) {
+    let pid = process::current_pid();
+    let Some(delivery) = process::take_signal(pid) else {
+        return;
+    };
+    match delivery {
+        Delivery::Terminate(signal) => round_robin::exit_current(exit_code(signal)),
+        Delivery::Handle { signal, handler, restorer } => {
+            let frame = (registers.rsp.wrapping_sub(RED_ZONE + size_of::<UserContext>() as u64)) & !15;
+            let saved = unsafe {
+                core::slice::from_raw_parts(registers as *const UserContext as *const u8, size_of::<UserContext>())
+            };
+            let pushed = copy_to_user(frame, saved).and_then(|_| copy_to_user(frame - 8, &restorer.to_le_bytes()));
+            if pushed.is_err() {
+                // no room for the frame on the stack, the signal can't be handled
+                round_robin::exit_current(exit_code(signal));
+            }
+            registers.rip = handler;
+            registers.rsp = frame - 8;
+            registers.rdi = signal as u64;
+        }
+    }
+}
+
+/*
+this function is sigreturn: it loads the registers deliver saved at the stack pointer the restorer
+was entered with and returns the saved rax. a process whose frame is unreadable or points outside
+the user half (sysret can't return there) is ended
+ */
+pub fn restore(registers: &mut UserContext) -> u64 {
+    let Ok(data) = copy_from_user(registers.rsp, size_of::<UserContext>() as u64) else {
+        round_robin::exit_current(process::KILLED_EXIT_CODE);
+    };
+    let saved = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const UserContext) };
+    if saved.rip >= USER_SPACE_END {
+        round_robin::exit_current(process::KILLED_EXIT_CODE);
+    }
+    *registers = UserContext {
+        rflags: (saved.rflags & STATUS_FLAGS) | USER_FLAGS,
+        ..saved
+    };
+    saved.rax
+}
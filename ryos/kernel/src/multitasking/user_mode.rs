@@ -0,0 +1,150 @@
+//USER MODE
+//Tasks run their programs in ring 3, the kernel is only entered again through interrupts,
+//exceptions and system calls, which switch to the task's kernel stack from the tss
+use crate::interrupts::gdt;
+use crate::multitasking::round_robin;
+use core::arch::{asm, naked_asm};
+use core::mem::offset_of;
+use x86_64::VirtAddr;
+
+// interrupts enabled, bit 1 is always set
+pub const USER_FLAGS: u64 = 0x202;
+// carry, parity, adjust, zero, sign, direction and overflow
+pub const STATUS_FLAGS: u64 = 0xcd5;
+
+/*
+the registers of a program in user mode, laid out the way both system call entries push them: rax
+holds the call number on entry and the result on return, rip, rsp and rflags say where the program
+continues. a task resumes from a copy of it after fork
+ */
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct UserContext {
+    pub rax: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbx: u64,
+    pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub r11: u64,
+    pub rcx: u64,
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+}
+
+impl UserContext {
+    // a program about to run its first instruction, all other registers are zero
+    pub fn start(entry: VirtAddr, stack_pointer: VirtAddr) -> Self {
+        UserContext {
+            rip: entry.as_u64(),
+            rsp: stack_pointer.as_u64(),
+            rflags: USER_FLAGS,
+            ..Default::default()
+        }
+    }
+}
+
+/*
+this function leaves the kernel and continues at 'entry' in ring 3 with the stack pointer at
+'stack_top', both have to be mapped user accessible in the address space of the running task.
+unsafe because everything on the current kernel stack is abandoned
+ */
+pub unsafe fn enter_user_mode(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    let code = gdt::user_code_selector().0 as u64;
+    let data = gdt::user_data_selector().0 as u64;
+    unsafe {
+        asm!(
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            // the frame iretq pops: rip, cs, rflags, rsp, ss
+            "push {data}",
+            "push {stack}",
+            "push {flags}",
+            "push {code}",
+            "push {entry}",
+            "iretq",
+            data = in(reg) data,
+            stack = in(reg) stack_top.as_u64(),
+            flags = in(reg) USER_FLAGS,
+            code = in(reg) code,
+            entry = in(reg) entry.as_u64(),
+            options(noreturn)
+        );
+    }
+}
+
+/*
+this function continues in ring 3 with every register taken from 'context'. only the status and
+direction flags of its rflags are used and interrupts are always on, so the program can't raise its
+io privilege or single step the kernel this way
+ */
+#[naked]
+unsafe extern "C" fn resume(context: *const UserContext, code: u64, data: u64) -> ! {
+    naked_asm!(
+        "mov ds, dx",
+        "mov es, dx",
+        // the frame iretq pops: rip, cs, rflags, rsp, ss
+        "push rdx",
+        "push qword ptr [rdi + {rsp}]",
+        "mov rax, [rdi + {rflags}]",
+        "and rax, {status_flags}",
+        "or rax, {user_flags}",
+        "push rax",
+        "push rsi",
+        "push qword ptr [rdi + {rip}]",
+        "mov rax, [rdi + {rax}]",
+        "mov r9, [rdi + {r9}]",
+        "mov r8, [rdi + {r8}]",
+        "mov r10, [rdi + {r10}]",
+        "mov rdx, [rdi + {rdx}]",
+        "mov rsi, [rdi + {rsi}]",
+        "mov rbx, [rdi + {rbx}]",
+        "mov rbp, [rdi + {rbp}]",
+        "mov r12, [rdi + {r12}]",
+        "mov r13, [rdi + {r13}]",
+        "mov r14, [rdi + {r14}]",
+        "mov r15, [rdi + {r15}]",
+        "mov r11, [rdi + {r11}]",
+        "mov rcx, [rdi + {rcx}]",
+        "mov rdi, [rdi + {rdi}]",
+        "iretq",
+        status_flags = const STATUS_FLAGS,
+        user_flags = const USER_FLAGS,
+        rax = const offset_of!(UserContext, rax),
+        r9 = const offset_of!(UserContext, r9),
+        r8 = const offset_of!(UserContext, r8),
+        r10 = const offset_of!(UserContext, r10),
+        rdx = const offset_of!(UserContext, rdx),
+        rsi = const offset_of!(UserContext, rsi),
+        rdi = const offset_of!(UserContext, rdi),
+        rbx = const offset_of!(UserContext, rbx),
+        rbp = const offset_of!(UserContext, rbp),
+        r12 = const offset_of!(UserContext, r12),
+        r13 = const offset_of!(UserContext, r13),
+        r14 = const offset_of!(UserContext, r14),
+        r15 = const offset_of!(UserContext, r15),
+        r11 = const offset_of!(UserContext, r11),
+        rcx = const offset_of!(UserContext, rcx),
+        rip = const offset_of!(UserContext, rip),
+        rsp = const offset_of!(UserContext, rsp),
+        rflags = const offset_of!(UserContext, rflags),
+    );
+}
+
+// the kernel side of a task made by add_user_task, it continues the program the task was created with
+pub extern "C" fn start_program() {
+    let context = round_robin::current_task()
+        .and_then(|task| task.user_context)
+        .expect("task has no program to start");
+    let code = gdt::user_code_selector().0 as u64;
+    let data = gdt::user_data_selector().0 as u64;
+    unsafe { resume(&context, code, data) }
+}
@@ -0,0 +1,34 @@
+//WAIT LIST
+//Tasks that wait for an event park on a wait list instead of polling, the scheduler skips them until
+//the event wakes the list. A woken task checks its condition again, so a wake too many is harmless
+use crate::multitasking::round_robin;
+use alloc::vec::Vec;
+
+pub struct WaitList {
+    tasks: Vec<usize>,
+}
+
+impl WaitList {
+    pub const fn new() -> Self {
+        WaitList { tasks: Vec::new() }
+    }
+
+    /*
+    this function parks the running task on the list. the caller holds the lock of the state it waits
+    for, so a wake can't come between its check and the park, and gives up the cpu with schedule after
+    releasing it
+     */
+    pub fn park(&mut self) {
+        if let Some(id) = round_robin::park_current() {
+            if !self.tasks.contains(&id) {
+                self.tasks.push(id);
+            }
+        }
+    }
+
+    pub fn wake_all(&mut self) {
+        for id in self.tasks.drain(..) {
+            round_robin::unpark(id);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//PCI BUS
+//Enumeration and configuration space access through the legacy 0xcf8/0xcfc ports
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+// configuration space offsets
+const VENDOR_ID_OFFSET: u8 = 0x00;
+const COMMAND_OFFSET: u8 = 0x04;
+const CLASS_OFFSET: u8 = 0x08;
+const HEADER_TYPE_OFFSET: u8 = 0x0c;
+const BAR0_OFFSET: u8 = 0x10;
+const INTERRUPT_LINE_OFFSET: u8 = 0x3c;
+
+// command register bits
+const COMMAND_IO_SPACE: u16 = 0b001;
+const COMMAND_MEMORY_SPACE: u16 = 0b010;
+const COMMAND_BUS_MASTER: u16 = 0b100;
+
+const NO_DEVICE: u16 = 0xffff;
+
+// only one access can be in flight because the address and data ports are shared
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Io(u16),
+    Memory(u64),
+}
+
+impl PciDevice {
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        read_config(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        write_config(self.bus, self.device, self.function, offset, value)
+    }
+
+    // decode base address register 'index', 64 bit memory bars take the next slot too
+    pub fn bar(&self, index: u8) -> Bar {
+        let offset = BAR0_OFFSET + index * 4;
+        let low = self.read_u32(offset);
+        if low & 1 == 1 {
+            return Bar::Io((low & !0b11) as u16);
+        }
+        let mut address = (low & !0b1111) as u64;
+        // type 0b10 is a 64 bit bar
+        if (low >> 1) & 0b11 == 0b10 {
+            address |= (self.read_u32(offset + 4) as u64) << 32;
+        }
+        Bar::Memory(address)
+    }
+
+    // the legacy pic line the firmware routed this device's interrupt pin to
+    pub fn interrupt_line(&self) -> u8 {
+        self.read_u32(INTERRUPT_LINE_OFFSET) as u8
+    }
+
+    // let the device decode its bars and act as a bus master for dma
+    pub fn enable(&self) {
+        let command = self.read_u32(COMMAND_OFFSET);
+        let enabled = command as u16 | COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER;
+        // the upper half is the status register, writing zeros there leaves it unchanged
+        self.write_u32(COMMAND_OFFSET, enabled as u32);
+    }
+}
+
+fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    0x8000_0000
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xfc)
+}
+
+pub fn read_config(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    let _lock = CONFIG_LOCK.lock();
+    let mut address: Port<u32> = Port::new(CONFIG_ADDRESS);
+    let mut data: Port<u32> = Port::new(CONFIG_DATA);
+    unsafe {
+        address.write(config_address(bus, device, function, offset));
+        data.read()
+    }
+}
+
+pub fn write_config(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    let _lock = CONFIG_LOCK.lock();
+    let mut address: Port<u32> = Port::new(CONFIG_ADDRESS);
+    let mut data: Port<u32> = Port::new(CONFIG_DATA);
+    unsafe {
+        address.write(config_address(bus, device, function, offset));
+        data.write(value);
+    }
+}
+
+fn probe_function(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+    let id = read_config(bus, device, function, VENDOR_ID_OFFSET);
+    let vendor_id = id as u16;
+    if vendor_id == NO_DEVICE {
+        return None;
+    }
+    let class = read_config(bus, device, function, CLASS_OFFSET);
+    Some(PciDevice {
+        bus,
+        device,
+        function,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+    })
+}
+
+// brute force scan of every bus/device/function
+pub fn enumerate() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let Some(first) = probe_function(bus, device, 0) else {
+                continue;
+            };
+            devices.push(first);
+
+            // bit 7 of the header type marks a multi function device
+            let header_type = (read_config(bus, device, 0, HEADER_TYPE_OFFSET) >> 16) as u8;
+            if header_type & 0x80 != 0 {
+                for function in 1..8u8 {
+                    if let Some(found) = probe_function(bus, device, function) {
+                        devices.push(found);
+                    }
+                }
+            }
+        }
+    }
+    devices
+}
+
+pub fn find(vendor_id: u16, device_id: u16) -> Vec<PciDevice> {
+    enumerate()
+        .into_iter()
+        .filter(|d| d.vendor_id == vendor_id && d.device_id == device_id)
+        .collect()
+}
@@ -0,0 +1,202 @@
+//MEMORY SELF TESTS
+//Address spaces, guarded task stacks, demand paging, copy on write fork and memory mapped files
+use crate::file_system::mount::{self, PROGRAMS_PATH};
+use super::{exit_code, expect_eq, failed, new_space, start_in, TestResult};
+use crate::file_system::disk_driver::SECTOR_SIZE;
+use crate::file_system::fat16::with_volume;
+use crate::memory::paging::FRAME_ALLOCATOR;
+use crate::memory::vmm::{self, AreaKind};
+use crate::multitasking::process::{current_dir, KILLED_EXIT_CODE};
+use crate::multitasking::round_robin;
+use crate::syscall::user_memory;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+
+// the test tasks run in the kernel, their faults on the test areas are only handled in user_memory::copy
+fn read_u64(addr: u64) -> u64 {
+    let mut value = 0u64;
+    unsafe { user_memory::copy(&mut value as *mut u64 as *mut u8, addr as *const u8, size_of::<u64>()) };
+    value
+}
+
+fn write_u64(addr: u64, value: u64) {
+    unsafe { user_memory::copy(addr as *mut u8, &value as *const u64 as *const u8, size_of::<u64>()) };
+}
+
+// the same virtual address holds a different value in every address space
+const VM_TEST_ADDRESS: u64 = 0x1000_0000;
+extern "C" fn exit_with_vm_test_value() {
+    round_robin::exit_current(read_u64(VM_TEST_ADDRESS) as i32)
+}
+
+pub fn address_spaces() -> TestResult {
+    let mut pids = [0; 2];
+    for (pid, value) in pids.iter_mut().zip([1u64, 2]) {
+        let mut space = new_space()?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        space
+            .map(VirtAddr::new(VM_TEST_ADDRESS), PAGE_SIZE, flags, AreaKind::Anonymous)
+            .map_err(failed("can't map the test page"))?;
+        without_interrupts(|| {
+            space.activate();
+            unsafe { core::ptr::write_volatile(VM_TEST_ADDRESS as *mut u64, value) };
+            vmm::switch_to(None);
+        });
+        *pid = start_in(exit_with_vm_test_value, space)?;
+    }
+    expect_eq("value of the first space", exit_code(pids[0])?, 1)?;
+    expect_eq("value of the second space", exit_code(pids[1])?, 2)
+}
+
+// recurses until it runs into the guard area below its stack
+extern "C" fn overflow_stack() {
+    fn recurse(depth: u64) -> u64 {
+        let buffer = [depth; 64];
+        core::hint::black_box(&buffer);
+        recurse(depth + 1) + buffer[0]
+    }
+    recurse(0);
+}
+
+pub fn stack_overflow() -> TestResult {
+    let pid = round_robin::add_task_with_stack(overflow_stack, 8 * 1024).ok_or("can't start the test task")?;
+    expect_eq("exit code of the overflowing task", exit_code(pid)?, KILLED_EXIT_CODE)
+}
+
+// touches a lazily backed page, which is mapped on demand, and then an unmapped one, which kills it
+const FAULT_TEST_ADDRESS: u64 = 0x2000_0000;
+static FAULT_TEST_VALUE: AtomicU64 = AtomicU64::new(0);
+extern "C" fn touch_memory() {
+    write_u64(FAULT_TEST_ADDRESS, 42);
+    FAULT_TEST_VALUE.store(read_u64(FAULT_TEST_ADDRESS), Ordering::Relaxed);
+    write_u64(FAULT_TEST_ADDRESS + 0x10_0000, 1);
+    round_robin::exit_current(0)
+}
+
+pub fn page_faults() -> TestResult {
+    let mut space = new_space()?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    space
+        .reserve(VirtAddr::new(FAULT_TEST_ADDRESS), 16 * PAGE_SIZE, flags, AreaKind::Anonymous)
+        .map_err(failed("can't reserve the test area"))?;
+    FAULT_TEST_VALUE.store(0, Ordering::Relaxed);
+    let code = exit_code(start_in(touch_memory, space)?)?;
+    expect_eq("demand paged value", FAULT_TEST_VALUE.load(Ordering::Relaxed), 42)?;
+    expect_eq("exit code after the unmapped access", code, KILLED_EXIT_CODE)
+}
+
+// both tasks start with the same value, with copy on write each increments its own copy.
+// the exit code holds the value before and after the increment
+const FORK_TEST_ADDRESS: u64 = 0x3000_0000;
+extern "C" fn increment_fork_test_value() {
+    let before = read_u64(FORK_TEST_ADDRESS);
+    write_u64(FORK_TEST_ADDRESS, before + 1);
+    let after = read_u64(FORK_TEST_ADDRESS);
+    round_robin::exit_current((before * 10 + after) as i32)
+}
+
+pub fn fork() -> TestResult {
+    let mut parent = new_space()?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    parent
+        .map(VirtAddr::new(FORK_TEST_ADDRESS), 4 * PAGE_SIZE, flags, AreaKind::Anonymous)
+        .map_err(failed("can't map the test area"))?;
+    without_interrupts(|| {
+        parent.activate();
+        unsafe { core::ptr::write_volatile(FORK_TEST_ADDRESS as *mut u64, 1) };
+        vmm::switch_to(None);
+    });
+    let child = parent.fork().map_err(failed("fork failed"))?;
+    let shared = FRAME_ALLOCATOR.lock().as_ref().map_or(0, |allocator| allocator.stats().shared);
+    if shared == 0 {
+        return Err("no frame is shared after the fork".into());
+    }
+    let parent = start_in(increment_fork_test_value, parent)?;
+    let child = start_in(increment_fork_test_value, child)?;
+    expect_eq("parent value before and after", exit_code(parent)?, 12)?;
+    expect_eq("child value before and after", exit_code(child)?, 12)
+}
+
+// upper cases the mapped file and writes it back with sync
+const MMAP_TEST_ADDRESS: u64 = 0x4000_0000;
+const MMAP_TEST_FILE: &str = "mmaptest";
+const MMAP_TEST_TEXT: &[u8] = b"written through a mapping";
+extern "C" fn upper_case_mapped_file() {
+    let mut data = [0u8; SECTOR_SIZE];
+    unsafe { user_memory::copy(data.as_mut_ptr(), MMAP_TEST_ADDRESS as *const u8, SECTOR_SIZE) };
+    data.make_ascii_uppercase();
+    unsafe { user_memory::copy(MMAP_TEST_ADDRESS as *mut u8, data.as_ptr(), SECTOR_SIZE) };
+    let Some(space) = round_robin::current_task().and_then(|task| task.address_space) else {
+        round_robin::exit_current(1)
+    };
+    let synced = space.lock().sync(VirtAddr::new(MMAP_TEST_ADDRESS), PAGE_SIZE);
+    round_robin::exit_current(if synced.is_ok() { 0 } else { 2 })
+}
+
+// the volume isn't locked while the task runs
+pub fn mmap() -> TestResult {
+    let dir = current_dir();
+    let mut buffer = [0u8; SECTOR_SIZE];
+    buffer[..MMAP_TEST_TEXT.len()].copy_from_slice(MMAP_TEST_TEXT);
+    with_volume(|fs| fs.add_file(&dir, MMAP_TEST_FILE).and_then(|_| fs.change_data(&dir, MMAP_TEST_FILE, &buffer)))
+        .map_err(failed("can't create the test file"))?;
+    let result = mmap_file(&dir);
+    let removed =
+        with_volume(|fs| fs.remove_entry(&dir, MMAP_TEST_FILE)).map_err(failed("can't remove the test file"));
+    result.and(removed)
+}
+
+fn mmap_file(dir: &str) -> TestResult {
+    let file = with_volume(|fs| fs.map_file(dir, MMAP_TEST_FILE)).map_err(failed("can't open the test file"))?;
+    expect_eq("mapped size", file.size(), SECTOR_SIZE as u64)?;
+    let mut space = new_space()?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    space
+        .map_file(VirtAddr::new(MMAP_TEST_ADDRESS), PAGE_SIZE, flags, Arc::new(file), 0)
+        .map_err(failed("can't map the test file"))?;
+    expect_eq("exit code of the task", exit_code(start_in(upper_case_mapped_file, space)?)?, 0)?;
+
+    let data = with_volume(|fs| fs.get_data(dir, MMAP_TEST_FILE)).map_err(failed("can't read the test file"))?;
+    expect_eq("file content", &data[..MMAP_TEST_TEXT.len()], MMAP_TEST_TEXT.to_ascii_uppercase().as_slice())
+}
+
+// adds up the bytes of the first page of a read only mapping of a program, the kernel compares the
+// sum with the one of the same bytes read through the file system
+const MMAP_EXT2_ADDRESS: u64 = 0x4800_0000;
+static MMAP_EXT2_SIZE: AtomicU64 = AtomicU64::new(0);
+extern "C" fn sum_mapped_program() {
+    let size = MMAP_EXT2_SIZE.load(Ordering::Relaxed) as usize;
+    let sum = {
+        let mut data = vec![0u8; size];
+        unsafe { user_memory::copy(data.as_mut_ptr(), MMAP_EXT2_ADDRESS as *const u8, size) };
+        byte_sum(&data)
+    };
+    round_robin::exit_current(sum)
+}
+
+fn byte_sum(data: &[u8]) -> i32 {
+    data.iter().fold(0i32, |sum, &byte| sum.wrapping_add(byte as i32))
+}
+
+pub fn mmap_ext2() -> TestResult {
+    let path = alloc::format!("{}/hello", PROGRAMS_PATH);
+    let (fs, path) = mount::resolve(&path).ok_or("no programs are mounted at /bin")?;
+    let file = fs.map_file(&path).map_err(failed("can't open hello"))?;
+    let size = file.size().min(PAGE_SIZE);
+    let inode = fs.open_file(&path).map_err(failed("can't open hello"))?;
+    let expected = fs.read_at(&inode, 0, size as usize).map_err(failed("can't read hello"))?;
+
+    let mut space = new_space()?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    space
+        .map_file(VirtAddr::new(MMAP_EXT2_ADDRESS), PAGE_SIZE, flags, Arc::new(file), 0)
+        .map_err(failed("can't map hello"))?;
+    MMAP_EXT2_SIZE.store(size, Ordering::Relaxed);
+    expect_eq("byte sum of the mapped page", exit_code(start_in(sum_mapped_program, space)?)?, byte_sum(&expected))
+}
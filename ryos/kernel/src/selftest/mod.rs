@@ -0,0 +1,120 @@
+//SELF TESTS
+//Kernel tests that run inside the booted system, every test starts tasks and checks what they did
+//through their exit codes, their output or the memory they changed. The selftest command runs them
+mod memory;
+mod user;
+
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::file_table::OpenFile;
+use crate::memory::vmm::AddressSpace;
+use crate::multitasking::{process, round_robin};
+use crate::{change_writer_color, eprintln, print, println};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use spin::Mutex;
+use crate::terminal::output::framebuffer::{Color, DEFAULT_COLOR};
+
+const PASS_COLOR: Color = Color::new(40, 170, 60);
+const FAIL_COLOR: Color = Color::new(200, 40, 40);
+
+// a test returns why it failed
+type TestResult = Result<(), String>;
+
+/*
+this function runs every test whose name contains 'filter' (all of them without one) and prints a
+line per test and a summary, it returns false when a test failed
+ */
+pub fn run(filter: Option<&str>) -> bool {
+    let tests: [(&str, &mut dyn FnMut() -> TestResult); 9] = [
+        ("address spaces", &mut memory::address_spaces),
+        ("stack overflow", &mut memory::stack_overflow),
+        ("page faults", &mut memory::page_faults),
+        ("fork", &mut memory::fork),
+        ("mmap", &mut memory::mmap),
+        ("mmap ext2", &mut memory::mmap_ext2),
+        ("user mode", &mut user::user_mode),
+        ("syscalls", &mut user::syscalls),
+        ("elf", &mut user::elf),
+    ];
+    let (mut passed, mut failed) = (0, 0);
+    for (name, test) in tests {
+        if filter.is_some_and(|filter| !name.contains(filter)) {
+            continue;
+        }
+        print!("{} ... ", name);
+        match test() {
+            Ok(()) => {
+                passed += 1;
+                change_writer_color(PASS_COLOR);
+                println!("ok");
+            }
+            Err(reason) => {
+                failed += 1;
+                change_writer_color(FAIL_COLOR);
+                println!("FAILED: {}", reason);
+            }
+        }
+        change_writer_color(DEFAULT_COLOR);
+    }
+    if passed + failed == 0 {
+        eprintln!("no test matches");
+        return false;
+    }
+    println!("{} passed, {} failed", passed, failed);
+    failed == 0
+}
+
+// turns an error into the reason a test failed
+fn failed<E: Debug>(what: &'static str) -> impl FnOnce(E) -> String {
+    move |e| format!("{} {:?}", what, e)
+}
+
+fn expect_eq<T: PartialEq + Debug>(what: &str, found: T, expected: T) -> TestResult {
+    match found == expected {
+        true => Ok(()),
+        false => Err(format!("{}: expected {:?}, found {:?}", what, expected, found)),
+    }
+}
+
+fn new_space() -> Result<AddressSpace, String> {
+    AddressSpace::new().map_err(failed("can't create an address space"))
+}
+
+// wait for the task 'pid' a test started and return its exit code
+fn exit_code(pid: usize) -> Result<i32, String> {
+    process::wait(process::current_pid(), Some(pid))
+        .map(|exited| exited.code)
+        .map_err(failed("wait failed"))
+}
+
+fn start_in(function: extern "C" fn(), space: AddressSpace) -> Result<usize, String> {
+    round_robin::add_task_in(function, Arc::new(Mutex::new(space))).ok_or_else(|| "can't start a test task".into())
+}
+
+/*
+this function calls 'start' with a pipe as standard output, so the tasks it starts inherit the pipe,
+and returns what 'start' returned with everything the tasks wrote until they closed the pipe
+ */
+fn capture_output<R>(start: impl FnOnce() -> R) -> Result<(R, Vec<u8>), FileSystemError> {
+    let (reader, writer) = OpenFile::pipe();
+    let saved = process::with_files(|files| {
+        let saved = files.clone();
+        files.set(1, Arc::new(writer));
+        saved
+    });
+    let started = start();
+    if let Some(saved) = saved {
+        process::with_files(|files| *files = saved);
+    }
+    let mut output = Vec::new();
+    loop {
+        let data = reader.read(256)?;
+        if data.is_empty() {
+            return Ok((started, output));
+        }
+        output.extend_from_slice(&data);
+    }
+}
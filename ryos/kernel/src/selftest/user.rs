@@ -0,0 +1,151 @@
+//USER MODE SELF TESTS
+//Small hand assembled ring 3 programs for the privilege checks, both system call entries and the elf loader
+use super::{capture_output, exit_code, expect_eq, failed, new_space, start_in, TestResult};
+use crate::memory::vmm::{self, AreaKind};
+use crate::multitasking::elf;
+use crate::multitasking::process::KILLED_EXIT_CODE;
+use crate::multitasking::user_mode;
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+const USER_TEST_CODE: u64 = 0x50_0000;
+const USER_TEST_STACK: u64 = 0x7000_0000;
+
+// counts to 100 million and then runs cli, which only the kernel may do, so the task is killed with
+// a general protection fault while the kernel keeps running
+const USER_TEST_PROGRAM: [u8; 15] = [
+    0x48, 0x31, 0xC0, // xor rax, rax
+    0x48, 0xFF, 0xC0, // inc rax
+    0x48, 0x3D, 0x00, 0xE1, 0xF5, 0x05, // cmp rax, 100000000
+    0x75, 0xF5, // jne inc
+    0xFA, // cli
+];
+
+// writes a line with the syscall instruction and one through int 0x80, then exits with the result of
+// the second write. the two messages follow the code
+const SYSCALL_TEST_PROGRAM: [u8; 52] = [
+    0x48, 0x8D, 0x35, 0x2D, 0x00, 0x00, 0x00, // lea rsi, [rip + first message]
+    0xBF, 0x01, 0x00, 0x00, 0x00, // mov edi, 1 (stdout)
+    0xBA, 0x13, 0x00, 0x00, 0x00, // mov edx, 19
+    0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1 (write)
+    0x0F, 0x05, // syscall
+    0x48, 0x8D, 0x35, 0x28, 0x00, 0x00, 0x00, // lea rsi, [rip + second message]
+    0xBA, 0x14, 0x00, 0x00, 0x00, // mov edx, 20
+    0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1 (write)
+    0xCD, 0x80, // int 0x80
+    0x89, 0xC7, // mov edi, eax
+    0xB8, 0x04, 0x00, 0x00, 0x00, // mov eax, 4 (exit)
+    0x0F, 0x05, // syscall
+];
+const SYSCALL_TEST_OUTPUT: &[u8] = b"hello from syscall\nhello from int 0x80\n";
+
+// prints a greeting and its argv[0], then exits with argc as the exit code
+const ELF_TEST_PROGRAM: [u8; 102] = [
+    0x48, 0x8B, 0x1C, 0x24, // mov rbx, [rsp] (argc)
+    0x48, 0x8D, 0x35, 0x47, 0x00, 0x00, 0x00, // lea rsi, [rip + greeting]
+    0xBF, 0x01, 0x00, 0x00, 0x00, // mov edi, 1 (stdout)
+    0xBA, 0x13, 0x00, 0x00, 0x00, // mov edx, 19
+    0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1 (write)
+    0x0F, 0x05, // syscall
+    0x48, 0x8B, 0x74, 0x24, 0x08, // mov rsi, [rsp + 8] (argv[0])
+    0x31, 0xD2, // xor edx, edx
+    0x80, 0x3C, 0x16, 0x00, // cmp byte ptr [rsi + rdx], 0
+    0x74, 0x05, // je write
+    0x48, 0xFF, 0xC2, // inc rdx
+    0xEB, 0xF5, // jmp cmp
+    0xB8, 0x01, 0x00, 0x00, 0x00, // write: mov eax, 1 (write)
+    0x0F, 0x05, // syscall
+    0x48, 0x8D, 0x35, 0x29, 0x00, 0x00, 0x00, // lea rsi, [rip + newline]
+    0xBA, 0x01, 0x00, 0x00, 0x00, // mov edx, 1
+    0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1 (write)
+    0x0F, 0x05, // syscall
+    0x48, 0x89, 0xDF, // mov rdi, rbx
+    0xB8, 0x04, 0x00, 0x00, 0x00, // mov eax, 4 (exit)
+    0x0F, 0x05, // syscall
+    b'h', b'e', b'l', b'l', b'o', b' ', b'f', b'r', b'o', b'm', b' ', b'a', b'n', b' ', b'e', b'l', b'f', b':', b' ',
+    b'\n',
+];
+
+extern "C" fn run_user_test_program() {
+    unsafe {
+        user_mode::enter_user_mode(VirtAddr::new(USER_TEST_CODE), VirtAddr::new(USER_TEST_STACK + 4 * 4096))
+    }
+}
+
+// copy the concatenated 'parts' to USER_TEST_CODE of a new address space and run them in a task
+fn start_user_test_program(parts: &[&[u8]]) -> Result<usize, String> {
+    let mut space = new_space()?;
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let stack_flags = user | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    space
+        .map(VirtAddr::new(USER_TEST_CODE), 4096, user | PageTableFlags::WRITABLE, AreaKind::Anonymous)
+        .and_then(|_| space.reserve(VirtAddr::new(USER_TEST_STACK), 4 * 4096, stack_flags, AreaKind::Stack))
+        .map_err(failed("can't map the user program"))?;
+    without_interrupts(|| {
+        space.activate();
+        let mut target = USER_TEST_CODE as *mut u8;
+        for part in parts {
+            unsafe {
+                core::ptr::copy_nonoverlapping(part.as_ptr(), target, part.len());
+                target = target.add(part.len());
+            }
+        }
+        vmm::switch_to(None);
+    });
+    start_in(run_user_test_program, space)
+}
+
+pub fn user_mode() -> TestResult {
+    let pid = start_user_test_program(&[&USER_TEST_PROGRAM])?;
+    expect_eq("exit code after cli", exit_code(pid)?, KILLED_EXIT_CODE)
+}
+
+pub fn syscalls() -> TestResult {
+    let parts: [&[u8]; 3] = [&SYSCALL_TEST_PROGRAM, b"hello from syscall\n", b"hello from int 0x80\n"];
+    let (pid, output) =
+        capture_output(|| start_user_test_program(&parts)).map_err(failed("can't read the output"))?;
+    let code = exit_code(pid?)?;
+    expect_eq("output", output.as_slice(), SYSCALL_TEST_OUTPUT)?;
+    expect_eq("bytes written through int 0x80", code, 20)
+}
+
+// a minimal static executable around ELF_TEST_PROGRAM
+fn elf_image() -> Vec<u8> {
+    const BASE: u64 = 0x40_0000;
+    const HEADERS: u64 = 64 + 56;
+    let size = HEADERS + ELF_TEST_PROGRAM.len() as u64;
+    let mut image = Vec::new();
+    // 64 bit, little endian, version 1, system v abi
+    image.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    image.extend_from_slice(&2u16.to_le_bytes()); // executable
+    image.extend_from_slice(&0x3Eu16.to_le_bytes()); // x86_64
+    image.extend_from_slice(&1u32.to_le_bytes());
+    for value in [BASE + HEADERS, 64, 0] {
+        // entry, program headers, no section headers
+        image.extend_from_slice(&value.to_le_bytes());
+    }
+    image.extend_from_slice(&0u32.to_le_bytes());
+    for value in [64u16, 56, 1, 64, 0, 0] {
+        image.extend_from_slice(&value.to_le_bytes());
+    }
+    // one readable and executable segment with the whole file
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&5u32.to_le_bytes());
+    for value in [0, BASE, BASE, size, size, 4096] {
+        image.extend_from_slice(&value.to_le_bytes());
+    }
+    image.extend_from_slice(&ELF_TEST_PROGRAM);
+    image
+}
+
+pub fn elf() -> TestResult {
+    let image = elf_image();
+    let (pid, output) = capture_output(|| elf::spawn(&image, &["elftest", "a", "b"], &[]))
+        .map_err(failed("can't read the output"))?;
+    let code = exit_code(pid.map_err(failed("can't load the image"))?)?;
+    expect_eq("output", output.as_slice(), b"hello from an elf: elftest\n".as_slice())?;
+    expect_eq("exit code (argc)", code, 3)
+}
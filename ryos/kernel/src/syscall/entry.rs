@@ -0,0 +1,171 @@
+//SYSCALL ENTRY
+//The syscall instruction and the int 0x80 gate both save the caller's registers on the kernel stack
+//of the running task as a UserContext and hand it to the dispatcher, which writes the result into rax
+use crate::interrupts::gdt;
+use crate::multitasking::user_mode::{self, UserContext};
+use crate::syscall;
+use core::arch::naked_asm;
+use core::mem::offset_of;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+pub const INT80_VECTOR: u8 = 0x80;
+
+// syscall doesn't switch stacks, the entry loads the kernel stack of the running task from here
+static mut KERNEL_STACK: u64 = 0;
+// the user stack pointer between entering the kernel and pushing it on the kernel stack
+static mut USER_STACK: u64 = 0;
+
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { (&raw mut KERNEL_STACK).write_volatile(top.as_u64()) };
+}
+
+/*
+this function enables the syscall instruction. the cpu takes the kernel segments from STAR and
+jumps to syscall_entry with interrupts, single stepping and the direction flag cleared
+ */
+pub fn init() {
+    Star::write(
+        gdt::user_code_selector(),
+        gdt::user_data_selector(),
+        gdt::kernel_code_selector(),
+        gdt::kernel_data_selector(),
+    )
+    .expect("the gdt layout doesn't fit syscall and sysret");
+    LStar::write(VirtAddr::new(syscall_entry as u64));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG);
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+// other tasks keep running while a system call waits for input or sleeps
+extern "C" fn syscall_dispatch(registers: &mut UserContext) {
+    interrupts::enable();
+    syscall::dispatch(registers);
+}
+
+#[naked]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "mov [rip + {user_stack}], rsp",
+        "mov rsp, [rip + {kernel_stack}]",
+        // where the program continues: rflags (in r11), the user stack and rip (in rcx)
+        "push r11",
+        "push qword ptr [rip + {user_stack}]",
+        "push rcx",
+        // everything else, see UserContext. the 18 registers keep the stack 16 byte aligned
+        "push rcx",
+        "push r11",
+        "push r15",
+        "push r14",
+        "push r13",
+        "push r12",
+        "push rbp",
+        "push rbx",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        "push rax",
+        "mov rdi, rsp",
+        "call {dispatch}",
+        // nothing may interrupt the kernel while it runs on the user stack
+        "cli",
+        "pop rax",
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop rbx",
+        "pop rbp",
+        "pop r12",
+        "pop r13",
+        "pop r14",
+        "pop r15",
+        // rcx and r11 get the return address and the flags for sysret instead
+        "add rsp, 16",
+        "pop rcx",
+        "mov r11, [rsp + 8]",
+        "pop rsp",
+        "sysretq",
+        user_stack = sym USER_STACK,
+        kernel_stack = sym KERNEL_STACK,
+        dispatch = sym syscall_dispatch,
+    );
+}
+
+/*
+the cpu already switched to the kernel stack from the tss and pushed the interrupt frame. the entry
+copies rip, rsp and rflags from it into the same layout as syscall_entry and writes them back before
+iretq, so exec can change them for both entries
+ */
+#[naked]
+pub unsafe extern "C" fn int80_entry() {
+    naked_asm!(
+        // padding, the interrupt frame leaves the stack 8 bytes off 16 byte alignment
+        "sub rsp, 8",
+        "push qword ptr [rsp + {frame_rflags}]",
+        "push qword ptr [rsp + {frame_rsp} + 8]",
+        "push qword ptr [rsp + {frame_rip} + 16]",
+        "push rcx",
+        "push r11",
+        "push r15",
+        "push r14",
+        "push r13",
+        "push r12",
+        "push rbp",
+        "push rbx",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        "push rax",
+        "mov rdi, rsp",
+        "call {dispatch}",
+        "cli",
+        "mov rax, [rsp + {rip}]",
+        "mov [rsp + {context_size} + {frame_rip}], rax",
+        "mov rax, [rsp + {rsp}]",
+        "mov [rsp + {context_size} + {frame_rsp}], rax",
+        "mov rax, [rsp + {rflags}]",
+        "and rax, {status_flags}",
+        "or rax, {user_flags}",
+        "mov [rsp + {context_size} + {frame_rflags}], rax",
+        "pop rax",
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop rbx",
+        "pop rbp",
+        "pop r12",
+        "pop r13",
+        "pop r14",
+        "pop r15",
+        "pop r11",
+        "pop rcx",
+        // rip, rsp, rflags and the padding
+        "add rsp, 32",
+        "iretq",
+        // offsets in the interrupt frame above the padding
+        frame_rip = const 8,
+        frame_rflags = const 24,
+        frame_rsp = const 32,
+        context_size = const size_of::<UserContext>(),
+        rip = const offset_of!(UserContext, rip),
+        rsp = const offset_of!(UserContext, rsp),
+        rflags = const offset_of!(UserContext, rflags),
+        status_flags = const user_mode::STATUS_FLAGS,
+        user_flags = const user_mode::USER_FLAGS,
+        dispatch = sym syscall_dispatch,
+    );
+}
@@ -0,0 +1,712 @@
+//SYSTEM CALLS
+//User programs reach the kernel with the syscall instruction or int 0x80. The number goes in rax, the
+//arguments in rdi, rsi, rdx, r10, r8 and r9 and the result comes back in rax, a negative errno on
+//failure. The numbers below are the stable abi, new calls only ever get new numbers
+pub mod entry;
+pub mod user_memory;
+
+use crate::file_system::errors::FileSystemError;
+use crate::file_system::file_table::OpenFile;
+use crate::file_system::{mount, pipe};
+use crate::interrupts::interrupts::uptime_ms;
+use crate::ipc::capability::{self, Capability, CapabilityError, ALL_RIGHTS};
+use crate::ipc::port::{self, IpcError, Port, MAX_MESSAGE};
+use crate::memory::frame_allocator::FRAME_SIZE;
+use crate::memory::vmm::{AreaKind, SharedPages, VmError};
+use crate::multitasking::elf::{self, ElfError};
+use crate::multitasking::process::{self, WaitError};
+use crate::memory::vmm::USER_SPACE_END;
+use crate::multitasking::round_robin;
+use crate::multitasking::signal::{self, Action, SignalError, SIGPIPE, SIG_DFL, SIG_IGN};
+use crate::multitasking::user_mode::UserContext;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use user_memory::{copy_from_user, copy_to_user, current_space};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+// read(fd, buffer, len) -> bytes read
+pub const READ: u64 = 0;
+// write(fd, buffer, len) -> bytes written
+pub const WRITE: u64 = 1;
+// open(path, path_len, flags) -> fd, relative paths start at the working directory. only FIFOs can be
+// opened with O_WRONLY
+pub const OPEN: u64 = 2;
+// close(fd) -> 0
+pub const CLOSE: u64 = 3;
+// exit(code), doesn't return
+pub const EXIT: u64 = 4;
+// yield() -> 0
+pub const YIELD: u64 = 5;
+// getpid() -> pid
+pub const GETPID: u64 = 6;
+// mmap(addr, len, prot) -> start of the mapping, addr 0 lets the kernel choose
+pub const MMAP: u64 = 7;
+// munmap(addr, len) -> 0
+pub const MUNMAP: u64 = 8;
+// uptime() -> milliseconds since boot
+pub const UPTIME: u64 = 9;
+// sleep(ms) -> 0
+pub const SLEEP: u64 = 10;
+// wait(pid, status) -> pid of the reaped child, pid -1 waits for any child, the exit code is stored
+// as an i32 at status unless it is 0
+pub const WAIT: u64 = 11;
+// getppid() -> pid of the parent
+pub const GETPPID: u64 = 12;
+// spawn(path, path_len, args, args_len, env, env_len) -> pid of the child running the program at path,
+// args and env are strings that each end with a 0 byte, one after the other
+pub const SPAWN: u64 = 13;
+// fork() -> pid of the child in the parent, 0 in the child, which runs on a copy of the address space
+pub const FORK: u64 = 14;
+// exec(path, path_len, args, args_len, env, env_len), only returns on errors
+pub const EXEC: u64 = 15;
+// sigaction(signal, handler, restorer) -> the old handler, handler 0 is the default action and 1
+// ignores the signal, the handler returns to restorer, which has to call sigreturn
+pub const SIGACTION: u64 = 16;
+// kill(pid, signal) -> 0
+pub const KILL: u64 = 17;
+// sigreturn(), continues where the program was before its signal handler ran
+pub const SIGRETURN: u64 = 18;
+// pipe(fds) -> 0, stores the read end and the write end of a new pipe as two i32 at fds
+pub const PIPE: u64 = 19;
+// dup2(fd, new_fd) -> new_fd, which now refers to the same open file as fd
+pub const DUP2: u64 = 20;
+// mkfifo(path, path_len) -> 0, creates a named pipe
+pub const MKFIFO: u64 = 21;
+// port_create() -> handle of a new port with all rights
+pub const PORT_CREATE: u64 = 22;
+// port_register(handle, name, name_len) -> 0, needs the receive right
+pub const PORT_REGISTER: u64 = 23;
+// port_lookup(name, name_len) -> handle with the send right to the port registered under name
+pub const PORT_LOOKUP: u64 = 24;
+// ipc_call(handle, data, len, reply, reply_len, attachments) -> length of the reply, which may be
+// longer than reply_len. attachments is 0 or four u64: address, length and flags (IPC_GRANT_WRITE) of
+// pages to grant, and a handle to pass on (NO_CAPABILITY for none), which needs the grant right
+pub const IPC_CALL: u64 = 25;
+// ipc_receive(handle, buffer, len, info) -> token of the call, info gets five u64: the pid of the
+// sender, the length of the data, address and length of the granted pages and the passed handle
+pub const IPC_RECEIVE: u64 = 26;
+// ipc_reply(token, data, len) -> 0
+pub const IPC_REPLY: u64 = 27;
+// cap_restrict(handle, rights) -> new handle to the same port with only some of the rights
+pub const CAP_RESTRICT: u64 = 28;
+// cap_close(handle) -> 0
+pub const CAP_CLOSE: u64 = 29;
+
+// the prot bits of mmap
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+// the flags of open, the mounted file systems are read only so only FIFOs can be written
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+
+// the flags of granted pages in ipc_call, without IPC_GRANT_WRITE the server gets them read only
+pub const IPC_GRANT_WRITE: u64 = 2;
+// no capability in the attachments of ipc_call or the info of ipc_receive
+pub const NO_CAPABILITY: u64 = u64::MAX;
+
+// mmap without an address places the mapping at or above this one
+const MMAP_BASE: u64 = 0x1000_0000_0000;
+// bytes a single read or write moves at most, larger requests are short
+const MAX_TRANSFER: u64 = 64 * 1024;
+const MAX_PATH: u64 = 4096;
+// bytes of the args or env strings of spawn and exec
+const MAX_STRINGS: u64 = 64 * 1024;
+
+// the same values as on linux, so ports of c code can use their usual names
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u64)]
+pub enum Errno {
+    NotPermitted = 1,
+    NoEntry = 2,
+    NoProcess = 3,
+    Interrupted = 4,
+    Io = 5,
+    TooBig = 7,
+    NoExec = 8,
+    BadFd = 9,
+    NoChild = 10,
+    Again = 11,
+    NoMemory = 12,
+    Access = 13,
+    Fault = 14,
+    Exists = 17,
+    NotDir = 20,
+    IsDir = 21,
+    Invalid = 22,
+    TooManyFiles = 24,
+    ReadOnly = 30,
+    BrokenPipe = 32,
+    NoSys = 38,
+}
+
+impl From<ElfError> for Errno {
+    fn from(error: ElfError) -> Self {
+        match error {
+            ElfError::NotElf | ElfError::Unsupported | ElfError::Malformed => Errno::NoExec,
+            ElfError::TooManyArguments => Errno::TooBig,
+            ElfError::NoTask => Errno::Again,
+            ElfError::Vm(error) => error.into(),
+        }
+    }
+}
+
+impl From<FileSystemError> for Errno {
+    fn from(error: FileSystemError) -> Self {
+        match error {
+            FileSystemError::FileNotFound | FileSystemError::DirectoryNotFound => Errno::NoEntry,
+            FileSystemError::AccessDenied => Errno::Access,
+            FileSystemError::NotADirectory => Errno::NotDir,
+            FileSystemError::NotAFile => Errno::IsDir,
+            FileSystemError::Interrupted => Errno::Interrupted,
+            FileSystemError::BrokenPipe => Errno::BrokenPipe,
+            FileSystemError::FileAlreadyExists => Errno::Exists,
+            FileSystemError::FileTooLarge => Errno::TooBig,
+            _ => Errno::Io,
+        }
+    }
+}
+
+impl From<WaitError> for Errno {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::NoChild => Errno::NoChild,
+            WaitError::Interrupted => Errno::Interrupted,
+        }
+    }
+}
+
+impl From<SignalError> for Errno {
+    fn from(error: SignalError) -> Self {
+        match error {
+            SignalError::InvalidSignal => Errno::Invalid,
+            SignalError::NoProcess => Errno::NoProcess,
+            SignalError::NotPermitted => Errno::NotPermitted,
+        }
+    }
+}
+
+impl From<IpcError> for Errno {
+    fn from(error: IpcError) -> Self {
+        match error {
+            IpcError::Closed => Errno::BrokenPipe,
+            IpcError::TooBig => Errno::TooBig,
+            IpcError::Interrupted => Errno::Interrupted,
+            IpcError::NoCall => Errno::Invalid,
+            IpcError::NotPermitted => Errno::NotPermitted,
+            IpcError::NameExists => Errno::Exists,
+            IpcError::NameNotFound => Errno::NoEntry,
+        }
+    }
+}
+
+impl From<CapabilityError> for Errno {
+    fn from(error: CapabilityError) -> Self {
+        match error {
+            CapabilityError::BadHandle => Errno::BadFd,
+            CapabilityError::NotPermitted => Errno::NotPermitted,
+        }
+    }
+}
+
+impl From<VmError> for Errno {
+    fn from(error: VmError) -> Self {
+        match error {
+            VmError::OutOfMemory => Errno::NoMemory,
+            VmError::Io => Errno::Io,
+            VmError::InvalidRange | VmError::Overlap | VmError::NotMapped => Errno::Invalid,
+            VmError::AccessViolation => Errno::Fault,
+        }
+    }
+}
+
+pub fn dispatch(registers: &mut UserContext) {
+    let args = [registers.rdi, registers.rsi, registers.rdx, registers.r10, registers.r8, registers.r9];
+    let result = match registers.rax {
+        READ => read(args[0], args[1], args[2]),
+        WRITE => write(args[0], args[1], args[2]),
+        OPEN => open(args[0], args[1], args[2]),
+        CLOSE => close(args[0]),
+        EXIT => exit(args[0]),
+        YIELD => {
+            without_interrupts(round_robin::schedule);
+            Ok(0)
+        }
+        GETPID => current_pid().map(|pid| pid as u64),
+        MMAP => mmap(args[0], args[1], args[2]),
+        MUNMAP => munmap(args[0], args[1]),
+        UPTIME => Ok(uptime_ms()),
+        SLEEP => sleep(args[0]),
+        WAIT => wait(args[0], args[1]),
+        GETPPID => getppid(),
+        SPAWN => spawn(args),
+        FORK => fork(registers),
+        EXEC => exec(registers, args),
+        SIGACTION => sigaction(args[0], args[1], args[2]),
+        KILL => kill(args[0], args[1]),
+        SIGRETURN => Ok(signal::restore(registers)),
+        PIPE => pipe(args[0]),
+        DUP2 => dup2(args[0], args[1]),
+        MKFIFO => mkfifo(args[0], args[1]),
+        PORT_CREATE => port_create(),
+        PORT_REGISTER => port_register(args[0], args[1], args[2]),
+        PORT_LOOKUP => port_lookup(args[0], args[1]),
+        IPC_CALL => ipc_call(args),
+        IPC_RECEIVE => ipc_receive(args[0], args[1], args[2], args[3]),
+        IPC_REPLY => ipc_reply(args[0], args[1], args[2]),
+        CAP_RESTRICT => cap_restrict(args[0], args[1]),
+        CAP_CLOSE => cap_close(args[0]),
+        _ => Err(Errno::NoSys),
+    };
+    registers.rax = match result {
+        Ok(value) => value,
+        Err(errno) => (errno as u64).wrapping_neg(),
+    };
+    // pending signals are handled on the way back to the program
+    signal::deliver(registers);
+}
+
+// the open file behind 'fd' in the running process, the table isn't locked while it is used
+fn file(fd: u64) -> Result<Arc<OpenFile>, Errno> {
+    process::with_files(|files| files.get(fd as usize))
+        .flatten()
+        .ok_or(Errno::BadFd)
+}
+
+// the terminal reads one line, including its newline
+fn read(fd: u64, buffer: u64, len: u64) -> Result<u64, Errno> {
+    let file = file(fd)?;
+    if len == 0 {
+        return Ok(0);
+    }
+    // checked first, reading consumes the data of pipes and advances the offset of files
+    let len = len.min(MAX_TRANSFER);
+    user_memory::check(buffer, len, true)?;
+    let data = file.read(len as usize)?;
+    copy_to_user(buffer, &data)?;
+    Ok(data.len() as u64)
+}
+
+// a write to a pipe without readers also sends SIGPIPE
+fn write(fd: u64, buffer: u64, len: u64) -> Result<u64, Errno> {
+    let file = file(fd)?;
+    // copied first, a page fault while the writer is locked would deadlock on the error output
+    let data = copy_from_user(buffer, len.min(MAX_TRANSFER))?;
+    match file.write(&data) {
+        Ok(count) => Ok(count as u64),
+        Err(FileSystemError::BrokenPipe) => {
+            let _ = process::send(current_pid()?, SIGPIPE);
+            Err(Errno::BrokenPipe)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn user_string(addr: u64, len: u64) -> Result<String, Errno> {
+    if len > MAX_PATH {
+        return Err(Errno::Invalid);
+    }
+    String::from_utf8(copy_from_user(addr, len)?).map_err(|_| Errno::Invalid)
+}
+
+// the absolute path of the user string 'path', relative paths start at the working directory
+fn user_path(path: u64, path_len: u64) -> Result<String, Errno> {
+    let path = user_string(path, path_len)?;
+    Ok(match path.starts_with('/') {
+        true => path,
+        false => process::current_dir() + &path,
+    })
+}
+
+/*
+this function reads the whole file at the user string 'path' from a mounted file system. the ryos
+volume belongs to the shell, so paths outside the mounts aren't found
+ */
+fn read_file(path: u64, path_len: u64) -> Result<Vec<u8>, Errno> {
+    let path = user_path(path, path_len)?;
+    let (fs, path) = mount::resolve(&path).ok_or(Errno::NoEntry)?;
+    Ok(fs.read_file(&path)?)
+}
+
+// the strings of the args or env of spawn and exec, each one ends with a 0 byte
+fn read_strings(addr: u64, len: u64) -> Result<Vec<String>, Errno> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if len > MAX_STRINGS {
+        return Err(Errno::TooBig);
+    }
+    let data = copy_from_user(addr, len)?;
+    let Some((&0, data)) = data.split_last() else {
+        return Err(Errno::Invalid);
+    };
+    data.split(|&byte| byte == 0)
+        .map(|string| String::from_utf8(string.to_vec()).map_err(|_| Errno::Invalid))
+        .collect()
+}
+
+fn open(path: u64, path_len: u64, flags: u64) -> Result<u64, Errno> {
+    let path = user_path(path, path_len)?;
+    let write = match flags {
+        O_RDONLY => false,
+        O_WRONLY if pipe::is_fifo(&path) => true,
+        O_WRONLY => return Err(Errno::ReadOnly),
+        _ => return Err(Errno::Invalid),
+    };
+    insert_file(OpenFile::open(&path, write)?)
+}
+
+// the lowest free descriptor of the running process now refers to 'file'
+fn insert_file(file: OpenFile) -> Result<u64, Errno> {
+    let file = Arc::new(file);
+    process::with_files(|files| files.insert(file))
+        .flatten()
+        .map(|fd| fd as u64)
+        .ok_or(Errno::TooManyFiles)
+}
+
+fn close(fd: u64) -> Result<u64, Errno> {
+    match process::with_files(|files| files.close(fd as usize)) {
+        Some(true) => Ok(0),
+        _ => Err(Errno::BadFd),
+    }
+}
+
+fn pipe(fds: u64) -> Result<u64, Errno> {
+    let (reader, writer) = OpenFile::pipe();
+    let read_fd = insert_file(reader)?;
+    let write_fd = match insert_file(writer) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            close(read_fd)?;
+            return Err(errno);
+        }
+    };
+    let mut data = [0u8; 8];
+    data[..4].copy_from_slice(&(read_fd as i32).to_le_bytes());
+    data[4..].copy_from_slice(&(write_fd as i32).to_le_bytes());
+    if let Err(errno) = copy_to_user(fds, &data) {
+        close(read_fd)?;
+        close(write_fd)?;
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+fn dup2(fd: u64, new_fd: u64) -> Result<u64, Errno> {
+    let file = file(fd)?;
+    match process::with_files(|files| files.set(new_fd as usize, file)) {
+        Some(true) => Ok(new_fd),
+        _ => Err(Errno::BadFd),
+    }
+}
+
+fn mkfifo(path: u64, path_len: u64) -> Result<u64, Errno> {
+    let path = user_path(path, path_len)?;
+    pipe::mkfifo(&path)?;
+    Ok(0)
+}
+
+// the port behind 'handle' in the running process, if the capability has 'rights'
+fn port_of(handle: u64, rights: u64) -> Result<Arc<Port>, Errno> {
+    let port = process::with_capabilities(|capabilities| {
+        let capability = capabilities.get(handle)?;
+        capability.check(rights)?;
+        Ok::<_, CapabilityError>(capability.port().clone())
+    });
+    Ok(port.ok_or(Errno::BadFd)??)
+}
+
+// the lowest free handle of the running process now refers to 'capability'
+fn insert_capability(capability: Capability) -> Result<u64, Errno> {
+    let result = process::with_capabilities(|capabilities| capabilities.insert(capability));
+    match result {
+        Some(Ok(handle)) => Ok(handle),
+        // dropped here, without the process table locked
+        _ => Err(Errno::TooManyFiles),
+    }
+}
+
+fn port_create() -> Result<u64, Errno> {
+    insert_capability(Capability::new(Port::new(), ALL_RIGHTS))
+}
+
+// only a server, which has the receive right, can register its port
+fn port_register(handle: u64, name: u64, name_len: u64) -> Result<u64, Errno> {
+    let name = user_string(name, name_len)?;
+    if name.is_empty() {
+        return Err(Errno::Invalid);
+    }
+    port::register(&name, &port_of(handle, capability::RECEIVE)?)?;
+    Ok(0)
+}
+
+fn port_lookup(name: u64, name_len: u64) -> Result<u64, Errno> {
+    let name = user_string(name, name_len)?;
+    insert_capability(Capability::new(port::lookup(&name)?, capability::SEND))
+}
+
+/*
+this function sends the call in 'args' and waits for the reply. granted pages are shared with the
+server, not copied, and a passed capability is a copy the server gets in addition to the caller's
+ */
+fn ipc_call(args: [u64; 6]) -> Result<u64, Errno> {
+    let [handle, data, len, reply, reply_len, attachments] = args;
+    let port = port_of(handle, capability::SEND)?;
+    if len > MAX_MESSAGE as u64 {
+        return Err(Errno::TooBig);
+    }
+    let data = copy_from_user(data, len)?;
+
+    let (mut pages, mut passed) = (None, None);
+    if attachments != 0 {
+        let raw = copy_from_user(attachments, 32)?;
+        let field = |index: usize| u64::from_le_bytes(raw[index * 8..index * 8 + 8].try_into().unwrap());
+        let (grant_addr, grant_len, grant_flags, capability) = (field(0), field(1), field(2), field(3));
+        if grant_len != 0 {
+            let start = VirtAddr::try_new(grant_addr).map_err(|_| Errno::Invalid)?;
+            if !start.is_aligned(FRAME_SIZE) || grant_flags & !IPC_GRANT_WRITE != 0 {
+                return Err(Errno::Invalid);
+            }
+            let size = grant_len.checked_next_multiple_of(FRAME_SIZE).ok_or(Errno::Invalid)?;
+            let writable = grant_flags & IPC_GRANT_WRITE != 0;
+            pages = Some(current_space()?.lock().share_pages(start, size, writable)?);
+        }
+        if capability != NO_CAPABILITY {
+            let copy = process::with_capabilities(|capabilities| {
+                let capability = capabilities.get(capability)?;
+                capability.check(capability::GRANT)?;
+                Ok::<_, CapabilityError>(capability.clone())
+            });
+            passed = Some(copy.ok_or(Errno::BadFd)??);
+        }
+    }
+
+    let answer = port::call(&port, data, pages, passed)?;
+    copy_to_user(reply, &answer[..answer.len().min(reply_len as usize)])?;
+    Ok(answer.len() as u64)
+}
+
+/*
+this function waits for the next call on the port behind 'handle' and hands it to the running
+process: the data goes to 'buffer', granted pages are mapped at a free address and a passed
+capability gets a handle. a call that can't be handed over fails for its caller as well
+ */
+fn ipc_receive(handle: u64, buffer: u64, len: u64, info: u64) -> Result<u64, Errno> {
+    let port = port_of(handle, capability::RECEIVE)?;
+    // checked before a call is taken from the queue
+    copy_to_user(info, &[0u8; 40])?;
+    let message = port::receive(&port, len.min(MAX_MESSAGE as u64) as usize)?;
+    let token = message.token;
+    match accept(buffer, info, message) {
+        Ok(()) => Ok(token),
+        Err(errno) => {
+            let _ = port::abort(token, IpcError::Closed);
+            Err(errno)
+        }
+    }
+}
+
+fn accept(buffer: u64, info: u64, message: port::Message) -> Result<(), Errno> {
+    copy_to_user(buffer, &message.data)?;
+    let (grant_addr, grant_len) = match message.pages {
+        Some(pages) => map_granted(pages)?,
+        None => (0, 0),
+    };
+    let handle = match message.capability.map(insert_capability) {
+        Some(Ok(handle)) => handle,
+        Some(Err(errno)) => {
+            if grant_len != 0 {
+                current_space()?.lock().unmap(VirtAddr::new(grant_addr), grant_len)?;
+            }
+            return Err(errno);
+        }
+        None => NO_CAPABILITY,
+    };
+    let fields = [message.sender as u64, message.data.len() as u64, grant_addr, grant_len, handle];
+    let mut data = [0u8; 40];
+    for (chunk, field) in data.chunks_exact_mut(8).zip(fields) {
+        chunk.copy_from_slice(&field.to_le_bytes());
+    }
+    copy_to_user(info, &data)
+}
+
+// the granted pages stay mapped until the server unmaps them, the caller sees its writes
+fn map_granted(pages: SharedPages) -> Result<(u64, u64), Errno> {
+    let size = pages.size();
+    let flags = PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let space = current_space()?;
+    let mut space = space.lock();
+    let start = space.find_free(VirtAddr::new(MMAP_BASE), size).ok_or(Errno::NoMemory)?;
+    space.map_shared(start, pages, flags)?;
+    Ok((start.as_u64(), size))
+}
+
+fn ipc_reply(token: u64, data: u64, len: u64) -> Result<u64, Errno> {
+    if len > MAX_MESSAGE as u64 {
+        return Err(Errno::TooBig);
+    }
+    port::reply(token, copy_from_user(data, len)?)?;
+    Ok(0)
+}
+
+fn cap_restrict(handle: u64, rights: u64) -> Result<u64, Errno> {
+    if rights & !ALL_RIGHTS != 0 {
+        return Err(Errno::Invalid);
+    }
+    let restricted = process::with_capabilities(|capabilities| capabilities.get(handle)?.restrict(rights));
+    insert_capability(restricted.ok_or(Errno::BadFd)??)
+}
+
+fn cap_close(handle: u64) -> Result<u64, Errno> {
+    let capability = process::with_capabilities(|capabilities| capabilities.remove(handle));
+    // closing the last receive right closes the port, so it happens without the process table locked
+    drop(capability.ok_or(Errno::BadFd)??);
+    Ok(0)
+}
+
+fn exit(code: u64) -> Result<u64, Errno> {
+    round_robin::exit_current(code as i32)
+}
+
+fn current_pid() -> Result<usize, Errno> {
+    round_robin::current_task().map(|task| task.id).ok_or(Errno::Again)
+}
+
+fn wait(pid: u64, status: u64) -> Result<u64, Errno> {
+    let target = match pid as i64 {
+        -1 => None,
+        pid if pid > 0 => Some(pid as usize),
+        _ => return Err(Errno::Invalid),
+    };
+    let exited = process::wait(current_pid()?, target)?;
+    if status != 0 {
+        copy_to_user(status, &exited.code.to_le_bytes())?;
+    }
+    Ok(exited.pid as u64)
+}
+
+fn spawn(args: [u64; 6]) -> Result<u64, Errno> {
+    let image = read_file(args[0], args[1])?;
+    let argv = read_strings(args[2], args[3])?;
+    let envp = read_strings(args[4], args[5])?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    Ok(elf::spawn(&image, &argv, &envp)? as u64)
+}
+
+/*
+this function starts a child that continues from this system call on a copy on write copy of the
+address space, with the same registers except rax
+ */
+fn fork(registers: &UserContext) -> Result<u64, Errno> {
+    let space = current_space()?;
+    let child = space.lock().fork()?;
+    let name = process::name_of(current_pid()?).unwrap_or_default();
+    let context = UserContext { rax: 0, ..*registers };
+    let pid = round_robin::add_forked_task(&name, Arc::new(Mutex::new(child)), context).ok_or(Errno::Again)?;
+    Ok(pid as u64)
+}
+
+/*
+this function replaces the program of the running process, it keeps its pid, parent, working
+directory and files. on success the system call returns to the entry of the new program with its
+stack and all other registers zeroed
+ */
+fn exec(registers: &mut UserContext, args: [u64; 6]) -> Result<u64, Errno> {
+    let image = read_file(args[0], args[1])?;
+    let argv = read_strings(args[2], args[3])?;
+    let envp = read_strings(args[4], args[5])?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    let program = elf::load(&image, &argv, &envp)?;
+    // the changes of the old program to mapped files, it still gets the error while it runs
+    current_space()?.lock().write_back_all()?;
+
+    let pid = current_pid()?;
+    let space = Arc::new(Mutex::new(program.address_space));
+    let old_task_space = round_robin::replace_address_space(space.clone());
+    let old_process_space = process::replace_image(pid, argv.first().copied().unwrap_or("program"), space);
+    // the old program is gone only after both references are dropped, with no lock held
+    drop(old_task_space);
+    drop(old_process_space);
+    *registers = UserContext::start(program.entry, program.stack_pointer);
+    Ok(0)
+}
+
+fn sigaction(signal: u64, handler: u64, restorer: u64) -> Result<u64, Errno> {
+    let signal = u8::try_from(signal).map_err(|_| Errno::Invalid)?;
+    let action = match handler {
+        SIG_DFL => Action::Default,
+        SIG_IGN => Action::Ignore,
+        _ if handler >= USER_SPACE_END || restorer == 0 || restorer >= USER_SPACE_END => return Err(Errno::Invalid),
+        _ => Action::Handler { handler, restorer },
+    };
+    let old = process::set_signal_action(current_pid()?, signal, action)?;
+    Ok(match old {
+        Action::Default => SIG_DFL,
+        Action::Ignore => SIG_IGN,
+        Action::Handler { handler, .. } => handler,
+    })
+}
+
+fn kill(pid: u64, signal: u64) -> Result<u64, Errno> {
+    let signal = u8::try_from(signal).map_err(|_| Errno::Invalid)?;
+    if pid as i64 <= 0 {
+        return Err(Errno::Invalid);
+    }
+    signal::kill(pid as usize, signal)?;
+    Ok(0)
+}
+
+fn getppid() -> Result<u64, Errno> {
+    let pid = current_pid()?;
+    process::parent_of(pid).map(|parent| parent as u64).ok_or(Errno::Again)
+}
+
+fn mmap(addr: u64, len: u64, prot: u64) -> Result<u64, Errno> {
+    if len == 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::Invalid);
+    }
+    let size = len.checked_next_multiple_of(FRAME_SIZE).ok_or(Errno::Invalid)?;
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let space = current_space()?;
+    let mut space = space.lock();
+    let start = match addr {
+        0 => space.find_free(VirtAddr::new(MMAP_BASE), size).ok_or(Errno::NoMemory)?,
+        _ => VirtAddr::try_new(addr).map_err(|_| Errno::Invalid)?,
+    };
+    space.reserve(start, size, flags, AreaKind::Anonymous)?;
+    Ok(start.as_u64())
+}
+
+fn munmap(addr: u64, len: u64) -> Result<u64, Errno> {
+    let size = len.checked_next_multiple_of(FRAME_SIZE).ok_or(Errno::Invalid)?;
+    let start = VirtAddr::try_new(addr).map_err(|_| Errno::Invalid)?;
+    current_space()?.lock().unmap(start, size)?;
+    Ok(0)
+}
+
+// a signal ends the sleep early with -EINTR
+fn sleep(ms: u64) -> Result<u64, Errno> {
+    let pid = current_pid()?;
+    let end = uptime_ms().saturating_add(ms);
+    while uptime_ms() < end {
+        if process::signal_pending(pid) {
+            return Err(Errno::Interrupted);
+        }
+        without_interrupts(round_robin::schedule);
+    }
+    Ok(0)
+}
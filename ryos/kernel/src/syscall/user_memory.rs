@@ -0,0 +1,77 @@
+//USER MEMORY
+//Pointers passed by user programs are checked against the areas of the running task's address space
+//before the kernel touches them
+use crate::memory::vmm::AddressSpace;
+use crate::multitasking::round_robin;
+use crate::syscall::Errno;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::{asm, naked_asm};
+use spin::Mutex;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+pub fn current_space() -> Result<Arc<Mutex<AddressSpace>>, Errno> {
+    round_robin::current_task()
+        .and_then(|task| task.address_space)
+        .ok_or(Errno::Fault)
+}
+
+/*
+this function fails unless the 'len' bytes at 'addr' are user accessible (and writable when 'write'
+is set). the lock is released before the memory is touched, the page fault handler needs it to
+bring in lazy, copy on write and swapped pages
+ */
+pub(crate) fn check(addr: u64, len: u64, write: bool) -> Result<(), Errno> {
+    let mut flags = PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    let start = VirtAddr::try_new(addr).map_err(|_| Errno::Fault)?;
+    if current_space()?.lock().allows(start, len, flags) {
+        Ok(())
+    } else {
+        Err(Errno::Fault)
+    }
+}
+
+pub fn copy_from_user(addr: u64, len: u64) -> Result<Vec<u8>, Errno> {
+    check(addr, len, false)?;
+    let mut data = vec![0u8; len as usize];
+    unsafe { copy(data.as_mut_ptr(), addr as *const u8, data.len()) };
+    Ok(data)
+}
+
+pub fn copy_to_user(addr: u64, data: &[u8]) -> Result<(), Errno> {
+    check(addr, data.len() as u64, true)?;
+    unsafe { copy(addr as *mut u8, data.as_ptr(), data.len()) };
+    Ok(())
+}
+
+// the only kernel instruction that may fault on user memory, rcx bytes go from rsi to rdi
+#[naked]
+unsafe extern "C" fn copy_bytes() {
+    naked_asm!("rep movsb", "ret");
+}
+
+// the page fault handler brings in the pages a fault at this address needs, like for user code
+pub(crate) fn faulted_in_copy(instruction_pointer: VirtAddr) -> bool {
+    instruction_pointer.as_u64() == copy_bytes as *const () as u64
+}
+
+/*
+this function copies 'len' bytes between user and kernel memory. it has to be used for every kernel
+access to memory below USER_SPACE_END, other page faults of the kernel there are fatal
+ */
+pub(crate) unsafe fn copy(destination: *mut u8, source: *const u8, len: usize) {
+    unsafe {
+        asm!(
+            "call {copy}",
+            copy = sym copy_bytes,
+            inout("rdi") destination => _,
+            inout("rsi") source => _,
+            inout("rcx") len => _,
+        );
+    }
+}
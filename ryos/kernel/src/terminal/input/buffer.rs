@@ -51,7 +51,8 @@ impl InputBuffer {
         self.is_listening = false;
     }
 
-    fn listen(&mut self)
+    // false when 'stop' returned true before the line was finished
+    fn listen(&mut self, stop: impl Fn() -> bool) -> bool
     {
         self.buffer.clear();
         self.is_listening = true;
@@ -59,17 +60,30 @@ impl InputBuffer {
 
         unsafe { BUFFER.force_unlock() };
         while self.is_listening {
+            if stop() {
+                self.end_listening();
+                return false;
+            }
             x86_64::instructions::hlt();
         }
+        true
     }
     
     pub fn get_input(&mut self) -> String {
-        self.listen();
+        self.get_input_until(|| false).unwrap_or_default()
+    }
+
+    // like get_input, but gives up with None as soon as 'stop' returns true
+    pub fn get_input_until(&mut self, stop: impl Fn() -> bool) -> Option<String> {
+        if !self.listen(stop) {
+            self.buffer.clear();
+            return None;
+        }
 
         let input = self.buffer.clone();
         self.buffer.clear();
         self.history.push(input.clone());
-        input
+        Some(input)
     }
     pub fn arrow_up(&mut self)
     {
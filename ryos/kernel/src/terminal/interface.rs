@@ -1,32 +1,47 @@
+use crate::file_system::block_device;
 use crate::file_system::disk_driver::SECTOR_SIZE;
-use crate::file_system::fat16::FAtApi;
+use crate::file_system::ext2::{Ext2, FileKind};
+use crate::file_system::fat16::with_volume;
+use crate::file_system::file_table::OpenFile;
+use crate::file_system::{mount, pipe};
+use crate::heap_alloc;
+use crate::heap_alloc::slab::SIZE_CLASSES;
+use crate::ipc::port;
+use crate::selftest;
+use crate::memory::paging::{self, FRAME_ALLOCATOR};
+use crate::memory::{swap, vmm};
+use crate::multitasking::elf::{self, ElfError};
+use crate::multitasking::process::{self, current_dir, set_current_dir, Exited, State, KERNEL_PID};
+use crate::multitasking::signal::{self, SIGCHLD, SIGINT, SIGKILL, SIGTERM};
 use crate::terminal::input::buffer::BUFFER;
 use crate::terminal::output::framebuffer::{Color, DEFAULT_COLOR};
 use crate::{change_writer_color, eprintln, print, print_logo, println};
+use alloc::format;
 use alloc::string::{String};
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
-use spin::Mutex;
-use spin::lazy::Lazy;
 use crate::file_system::errors::FileSystemError;
 
 pub const OUTPUT_COLOR: Color = Color::new(255, 200, 35);
-pub static WORKING_DIR: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(String::from("/")));
-pub(crate) struct Terminal
-{
-    fs: FAtApi,
-}
+const DIR_COLOR: Color = Color::new(40, 110, 190);
+// bytes cat reads from a mounted file at a time
+const CAT_CHUNK: usize = 16 * 1024;
+// the ryos volume is shared with the system calls, see fat16::with_volume
+pub(crate) struct Terminal;
 
 impl Terminal
 {
-    pub fn new(fs: FAtApi) -> Terminal {
-        Terminal { fs }
+    pub fn new() -> Terminal {
+        Terminal
     }
     pub fn run(&mut self) {
-        print!("{}> ", WORKING_DIR.lock());
+        Self::reap_children();
+        print!("{}> ", current_dir());
         let input = BUFFER.lock().get_input();
         println!();
         self.handle_command(input.as_str());
-        self.fs.save().unwrap();
+        with_volume(|fs| fs.save()).unwrap();
     }
 
     pub fn handle_command(&mut self, command: &str) {
@@ -95,6 +110,13 @@ impl Terminal
                     eprintln!("mkdir: touch [name]")
                 }
             }
+            "mkfifo" => {
+                if let Some(name) = parts.get(1) {
+                    Self::mkfifo(name);
+                } else {
+                    eprintln!("Usage: mkfifo [name]")
+                }
+            }
             "rm" => {
                 if let Some(name) = parts.get(1) {
                     self.rm(name);
@@ -109,10 +131,46 @@ impl Terminal
                     eprintln!("Usage: cd [path]")
                 }
             }
+            "lsblk" => Self::lsblk(),
+            "heapbench" => crate::heap_alloc::bench::run(),
+            "free" => Self::free(),
+            "meminfo" => Self::meminfo(),
+            "mount" => match (parts.get(1), parts.get(2)) {
+                (Some(device), Some(path)) => Self::mount(device, path),
+                (None, _) => Self::list_mounts(),
+                _ => eprintln!("Usage: mount [device] [path]"),
+            },
+            "umount" => {
+                if let Some(path) = parts.get(1) {
+                    Self::umount(path);
+                } else {
+                    eprintln!("Usage: umount [path]")
+                }
+            }
+            "swapon" => match parts.get(1) {
+                Some(device) => Self::swapon(device),
+                None => Self::list_swap(),
+            },
+            "swapoff" => Self::swapoff(),
             "multitasking" => {
-                crate::test_multitasking();
+                Self::wait_foreground(crate::test_multitasking());
             }
-            _ => eprintln!("{}: command not found", parts[0]),
+            "selftest" => {
+                selftest::run(parts.get(1).copied());
+            }
+            "ps" => Self::ps(),
+            "ports" => Self::ports(),
+            "wait" => match parts.get(1).map(|pid| pid.parse::<usize>()) {
+                Some(Ok(pid)) => Self::wait(Some(pid)),
+                None => Self::wait(None),
+                Some(Err(_)) => eprintln!("Usage: wait [pid]"),
+            },
+            "kill" => match (parts.get(1), parts.get(2)) {
+                (Some(signal), Some(pid)) if signal.starts_with('-') => Self::kill(&signal[1..], pid),
+                (Some(pid), None) => Self::kill("TERM", pid),
+                _ => eprintln!("Usage: kill [-signal] [pid]"),
+            },
+            _ => self.run_program(command),
         }
         change_writer_color(DEFAULT_COLOR);
     }
@@ -148,6 +206,12 @@ impl Terminal
         }
     }
     fn cat(&self, name: &str) {
+        if let Some((fs, path)) = mount::resolve(&full_path(name)) {
+            if let Err(e) = Self::cat_mounted(&fs, &path) {
+                eprintln!("Error: {:?}", e);
+            }
+            return;
+        }
         let data = self.get_file_data(name);
         if data.is_none() {
             return;
@@ -167,8 +231,248 @@ impl Terminal
         println!(); // new line
     }
 
+    /*
+    this function runs a pipeline like 'hello | upper > fifo': every stage is a program with its words
+    as argv and its output goes through a pipe to the input of the next stage. '< path' and '> path'
+    connect the input or output of a stage to a file or FIFO instead. the shell waits for all of them
+    unless the command ends with '&'
+     */
+    fn run_program(&self, command: &str) {
+        let (command, background) = match command.trim_end().strip_suffix('&') {
+            Some(command) => (command, true),
+            None => (command, false),
+        };
+        let mut stages = Vec::new();
+        for stage in command.split('|') {
+            match Self::parse_stage(stage) {
+                Some(stage) => stages.push(stage),
+                None => {
+                    return eprintln!("Usage: [program] [args] [< input] [> output] [| program ...] [&]");
+                }
+            }
+        }
+        let mut images = Vec::new();
+        for (argv, _, _) in &stages {
+            match self.read_program(argv[0]) {
+                Ok(image) => images.push(image),
+                Err(FileSystemError::FileNotFound) if !argv[0].contains('/') => {
+                    return eprintln!("{}: command not found", argv[0]);
+                }
+                Err(e) => return eprintln!("{}: {:?}", argv[0], e),
+            }
+        }
+
+        let count = stages.len();
+        let mut pids = Vec::new();
+        let mut pipe_input = None;
+        for (index, ((argv, input, output), image)) in stages.into_iter().zip(images).enumerate() {
+            let (Some(input), Some(output)) = (Self::open_redirect(input, false), Self::open_redirect(output, true))
+            else {
+                break;
+            };
+            let stdin = input.or(pipe_input.take());
+            let mut stdout = output;
+            if index + 1 < count {
+                let (reader, writer) = OpenFile::pipe();
+                pipe_input = Some(Arc::new(reader));
+                stdout = stdout.or(Some(Arc::new(writer)));
+            }
+            match Self::spawn_with(&image, &argv, stdin, stdout) {
+                Ok(pid) if background => {
+                    println!("started {} as process {}", argv[0], pid);
+                    pids.push(pid);
+                }
+                Ok(pid) => pids.push(pid),
+                Err(e) => {
+                    eprintln!("{}: {:?}", argv[0], e);
+                    break;
+                }
+            }
+        }
+        // the shell holds no pipe ends anymore, so the stages see each other's end of file
+        drop(pipe_input);
+        if !background {
+            Self::wait_foreground(pids);
+        }
+    }
+
+    // the words of a pipeline stage and the paths after its '<' and '>', None when it is malformed
+    fn parse_stage(stage: &str) -> Option<(Vec<&str>, Option<&str>, Option<&str>)> {
+        let mut argv = Vec::new();
+        let (mut input, mut output) = (None, None);
+        let mut words = stage.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "<" => input = Some(words.next()?),
+                ">" => output = Some(words.next()?),
+                _ => argv.push(word),
+            }
+        }
+        (!argv.is_empty()).then_some((argv, input, output))
+    }
+
+    // Some(None) without a path, None when the file can't be opened
+    fn open_redirect(path: Option<&str>, write: bool) -> Option<Option<Arc<OpenFile>>> {
+        let Some(path) = path else {
+            return Some(None);
+        };
+        match OpenFile::open(&full_path(path), write) {
+            Ok(file) => Some(Some(Arc::new(file))),
+            Err(e) => {
+                eprintln!("{}: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    /*
+    this function starts a program with 'stdin' and 'stdout' as its descriptors 0 and 1 when they are
+    given. a new process copies the descriptors of the shell, so they are changed just for the spawn
+     */
+    fn spawn_with(
+        image: &[u8],
+        argv: &[&str],
+        stdin: Option<Arc<OpenFile>>,
+        stdout: Option<Arc<OpenFile>>,
+    ) -> Result<usize, ElfError> {
+        let pwd = format!("PWD={}", current_dir());
+        let saved = process::with_files(|files| {
+            let saved = files.clone();
+            for (fd, file) in [(0, stdin), (1, stdout)] {
+                if let Some(file) = file {
+                    files.set(fd, file);
+                }
+            }
+            saved
+        });
+        let result = elf::spawn(image, argv, &["PATH=/bin", &pwd]);
+        if let Some(saved) = saved {
+            process::with_files(|files| *files = saved);
+        }
+        result
+    }
+
+    fn report_exit(exited: &Exited) {
+        println!("[{}] {} exited with code {}", exited.pid, exited.name, exited.code);
+    }
+
+    // the shell runs in the kernel process, which also adopts orphans, so it reaps their zombies too
+    fn reap_children() {
+        while let Ok(Some(exited)) = process::try_wait(KERNEL_PID, None) {
+            Self::report_exit(&exited);
+        }
+    }
+
+    // Ctrl+C interrupts the processes the shell waits for
+    fn wait(pid: Option<usize>) {
+        signal::set_foreground(pid.map_or_else(|| process::running_children(KERNEL_PID), |pid| vec![pid]));
+        let result = process::wait(KERNEL_PID, pid);
+        if signal::take_interrupted() {
+            println!("^C");
+        }
+        signal::set_foreground(Vec::new());
+        match result {
+            Ok(exited) => Self::report_exit(&exited),
+            Err(e) => eprintln!("wait: {:?}", e),
+        }
+    }
+
+    // wait for all of 'pids', they are in the foreground together
+    fn wait_foreground(pids: Vec<usize>) {
+        signal::set_foreground(pids.clone());
+        for pid in pids {
+            let result = process::wait(KERNEL_PID, Some(pid));
+            if signal::take_interrupted() {
+                println!("^C");
+            }
+            match result {
+                Ok(exited) => Self::report_exit(&exited),
+                Err(e) => eprintln!("wait: {:?}", e),
+            }
+        }
+        signal::set_foreground(Vec::new());
+    }
+
+    // 'name' is a signal number or name without SIG, like 9 or KILL
+    fn kill(name: &str, pid: &str) {
+        let number = match name {
+            "INT" => Some(SIGINT),
+            "KILL" => Some(SIGKILL),
+            "TERM" => Some(SIGTERM),
+            "CHLD" => Some(SIGCHLD),
+            _ => name.parse::<u8>().ok(),
+        };
+        let (Some(number), Ok(pid)) = (number, pid.parse::<usize>()) else {
+            return eprintln!("Usage: kill [-signal] [pid]");
+        };
+        if let Err(e) = signal::kill(pid, number) {
+            eprintln!("kill: {:?}", e);
+        }
+    }
+
+    fn ps() {
+        println!(
+            "{:>5} {:>5} {:<8} {:>5} {:>4} {:<16} {}",
+            "pid", "ppid", "state", "files", "caps", "dir", "name"
+        );
+        for info in process::list() {
+            let state = match info.state {
+                State::Running => String::from("running"),
+                State::Zombie(code) => format!("exit {}", code),
+            };
+            println!(
+                "{:>5} {:>5} {:<8} {:>5} {:>4} {:<16} {}",
+                info.pid, info.parent, state, info.open_files, info.capabilities, info.working_dir, info.name
+            );
+        }
+    }
+
+    // closed ports are left out, their names can be registered again
+    fn ports() {
+        println!("{:>5} {:>7} {}", "id", "queued", "name");
+        for info in port::list() {
+            println!("{:>5} {:>7} {}", info.id, info.queued, info.name);
+        }
+    }
+
+    /*
+    this function reads the executable 'name': plain names from /bin, paths from the mounted file
+    systems and ./name also from the working directory of the ryos volume, whose files are a single
+    sector
+     */
+    fn read_program(&self, name: &str) -> Result<Vec<u8>, FileSystemError> {
+        let local = name.strip_prefix("./");
+        let path = match (local, name.contains('/')) {
+            (Some(local), _) => full_path(local),
+            (None, true) => full_path(name),
+            (None, false) => format!("/bin/{}", name),
+        };
+        if let Some((fs, path)) = mount::resolve(&path) {
+            return fs.read_file(&path);
+        }
+        match local {
+            Some(local) if !local.contains('/') => with_volume(|fs| fs.get_data(&current_dir(), local)).map(|data| data.to_vec()),
+            _ => Err(FileSystemError::FileNotFound),
+        }
+    }
+
+    // print a file of a mounted file system a part at a time, it may be larger than the heap
+    fn cat_mounted(fs: &Ext2, path: &str) -> Result<(), FileSystemError> {
+        let inode = fs.open_file(path)?;
+        let mut offset = 0;
+        loop {
+            let data = fs.read_at(&inode, offset, CAT_CHUNK)?;
+            if data.is_empty() {
+                println!();
+                return Ok(());
+            }
+            print!("{}", String::from_utf8_lossy(&data));
+            offset += data.len() as u64;
+        }
+    }
+
     fn get_file_data(&self, name: &str) -> Option<[u8; SECTOR_SIZE]> {
-        match self.fs.get_data(name) {
+        match with_volume(|fs| fs.get_data(&current_dir(), name)) {
             Ok(data) => Some(data),
             Err(e) => {
                 eprintln!("Error: {:?}", e);
@@ -177,7 +481,10 @@ impl Terminal
         }
     }
     fn write(&mut self,name: &str, buffer: [u8; SECTOR_SIZE]) {
-        match self.fs.change_data(name, &buffer) {
+        if Self::is_read_only(name) {
+            return;
+        }
+        match with_volume(|fs| fs.change_data(&current_dir(), name, &buffer)) {
             Ok(_) => {}
             Err(e) => eprintln!("Error {:?}", e),
         }
@@ -194,17 +501,201 @@ impl Terminal
         println!("touch - create a new file");
         println!("rm - remove file");
         println!("multitasking - test multitasking");
+        println!("selftest [name] - run the kernel self tests, or those whose name contains [name]");
+        println!("[program] [args] - run /bin/[program], or the program at a path (./name for the working directory)");
+        println!("[program] [args] & - run a program in the background");
+        println!("[program] | [program] - run programs with the output of each one as the input of the next");
+        println!("[program] < [path] > [fifo] - read the input from a file or FIFO, write the output to a FIFO");
+        println!("mkfifo - create a named pipe (FIFO)");
+        println!("ps - list the processes");
+        println!("ports - list the registered IPC ports and the calls waiting on them");
+        println!("wait - wait for a program (or the one with the given pid) to exit, Ctrl+C interrupts it");
+        println!("kill - send a signal to a process, kill -9 5 or kill -INT 5 (default TERM)");
         println!("append - add data to task");
         println!("mkdir - create a new directory");
+        println!("lsblk - list disks and partitions");
+        println!("mount - mount an ext2 device read only, or list the mounts");
+        println!("umount - unmount a file system");
+        println!("swapon - swap to a swap partition, without arguments show the swap space");
+        println!("swapoff - read everything back from swap and stop swapping");
+        println!("free - show used and free memory");
+        println!("meminfo - detailed memory statistics");
+        println!("heapbench - compare the slab and linked list heap allocators");
+    }
+
+    fn free() {
+        let Some(frames) = FRAME_ALLOCATOR.lock().as_ref().map(|allocator| allocator.stats()) else {
+            eprintln!("memory not initialized");
+            return;
+        };
+        let heap = heap_alloc::alloc::stats();
+        println!("{:>8} {:>12} {:>12} {:>12}", "", "total KiB", "used KiB", "free KiB");
+        println!(
+            "{:>8} {:>12} {:>12} {:>12}",
+            "Mem:",
+            frames.total * 4,
+            frames.used() * 4,
+            frames.free * 4
+        );
+        println!(
+            "{:>8} {:>12} {:>12} {:>12}",
+            "Heap:",
+            heap.size / 1024,
+            heap.used / 1024,
+            heap.free / 1024
+        );
+        if let Some(swap) = swap::stats() {
+            println!(
+                "{:>8} {:>12} {:>12} {:>12}",
+                "Swap:",
+                swap.slots * 4,
+                swap.used * 4,
+                (swap.slots - swap.used) * 4
+            );
+        }
+    }
+
+    fn meminfo() {
+        let Some(frames) = FRAME_ALLOCATOR.lock().as_ref().map(|allocator| allocator.stats()) else {
+            eprintln!("memory not initialized");
+            return;
+        };
+        let heap = heap_alloc::alloc::stats();
+        let slabs = heap_alloc::alloc::ALLOCATOR.slabs.stats();
+        let tables = paging::page_table_stats();
+
+        println!("physical frames (4 KiB)");
+        println!("  usable:             {}", frames.total);
+        println!("  free:               {}", frames.free);
+        println!("  used:               {}", frames.used());
+        println!("  shared:             {}", frames.shared);
+        println!("  bootloader:         {}", frames.bootloader);
+        println!("  firmware/reserved:  {}", frames.firmware);
+        println!("heap");
+        println!("  mapped:             {} KiB (max {} KiB)", heap.size / 1024, heap.max_size / 1024);
+        println!("  in use:             {} bytes", heap.in_use);
+        println!("  peak:               {} bytes", heap.peak);
+        println!("  free:               {} bytes", heap.free);
+        println!("  slab pages:         {}", slabs.pages);
+        for (class, count) in SIZE_CLASSES.iter().zip(slabs.allocated.iter()) {
+            if *count != 0 {
+                println!("    {:>4} byte objects: {}", class, count);
+            }
+        }
+        match swap::stats() {
+            Some(swap) => {
+                println!("swap ({})", swap.device);
+                println!("  slots:              {}", swap.slots);
+                println!("  used:               {}", swap.used);
+            }
+            None => println!("swap off"),
+        }
+        println!("page tables");
+        println!("  tables:             {} ({} KiB)", tables.tables, tables.tables * 4);
+        println!("  4 KiB pages:        {}", tables.pages_4k);
+        println!("  2 MiB pages:        {}", tables.pages_2m);
+        println!("  1 GiB pages:        {}", tables.pages_1g);
+    }
+
+    fn lsblk() {
+        for entry in block_device::DEVICES.lock().iter() {
+            let size_kib = entry.device.sector_count() * SECTOR_SIZE as u64 / 1024;
+            match entry.partition {
+                Some((kind, start)) => println!(
+                    "  {} {} KiB start {} {:?}",
+                    entry.name, size_kib, start, kind
+                ),
+                None => println!("{} {} KiB disk", entry.name, size_kib),
+            }
+        }
+    }
+
+
+    fn mount(device: &str, path: &str) {
+        match mount::mount(device, &full_path(path)) {
+            Ok(_) => println!("{} mounted at {}", device, full_path(path)),
+            Err(e) => eprintln!("Error mounting {}: {:?}", device, e),
+        }
+    }
+
+    fn umount(path: &str) {
+        if let Err(e) = mount::unmount(&full_path(path)) {
+            eprintln!("Error unmounting {}: {:?}", path, e);
+        }
+    }
+
+    fn swapon(device: &str) {
+        match swap::swapon(device) {
+            Ok(()) => println!("swapping to {}", device),
+            Err(e) => eprintln!("Error: swapon {}: {:?}", device, e),
+        }
+    }
+
+    // bring every page back into memory first, swap can only be turned off when nothing is left in it
+    fn swapoff() {
+        if let Err(e) = vmm::swap_in_all() {
+            eprintln!("Error: can't read every page back from swap {:?}", e);
+        }
+        if let Err(e) = swap::swapoff() {
+            eprintln!("Error: swapoff: {:?}", e);
+        }
+    }
+
+    fn list_swap() {
+        match swap::stats() {
+            Some(swap) => println!("{} {} KiB, {} KiB used", swap.device, swap.slots * 4, swap.used * 4),
+            None => println!("swap is off"),
+        }
     }
 
+    fn list_mounts() {
+        for mount in mount::MOUNTS.lock().iter() {
+            println!("{} on {} type ext2 (ro)", mount.device, mount.path);
+        }
+    }
+
+    // mounted file systems are read only, refuse to change anything inside them
+    fn is_read_only(name: &str) -> bool {
+        if mount::resolve(&full_path(name)).is_some() {
+            eprintln!("Error {:?}: read only file system", FileSystemError::AccessDenied);
+            return true;
+        }
+        false
+    }
 
     fn ls(&self) {
-        self.fs.list_dir();
+        let working_dir = current_dir();
+        if let Some((fs, path)) = mount::resolve(&working_dir) {
+            match fs.list(&path) {
+                Ok(entries) => {
+                    for (name, kind) in entries {
+                        if kind == FileKind::Directory {
+                            change_writer_color(DIR_COLOR);
+                        }
+                        println!("{}", name);
+                        change_writer_color(OUTPUT_COLOR);
+                    }
+                }
+                Err(e) => eprintln!("Error listing directory: {:?}", e),
+            }
+        } else if let Err(e) = with_volume(|fs| {
+            fs.list_dir(&working_dir);
+            Ok(())
+        }) {
+            eprintln!("Error listing directory: {:?}", e);
+        }
+        change_writer_color(DIR_COLOR);
+        for name in mount::mount_points_in(&working_dir) {
+            println!("{}: mount point", name);
+        }
+        change_writer_color(OUTPUT_COLOR);
     }
 
     fn touch(&mut self, name: &str) {
-        match self.fs.add_file(name)
+        if Self::is_read_only(name) {
+            return;
+        }
+        match with_volume(|fs| fs.add_file(&current_dir(), name))
         {
             Ok(_) => {},
             Err(e) => eprintln!("Error adding file {:?}", e)
@@ -212,14 +703,20 @@ impl Terminal
     }
 
     fn rm(&mut self, name: &str) {
-        match self.fs.remove_entry(name)
+        if Self::is_read_only(name) {
+            return;
+        }
+        match with_volume(|fs| fs.remove_entry(&current_dir(), name))
         {
-            Ok(_) => {},
+            Ok(_) => pipe::forget_fifo(&full_path(name)),
             Err(e) => eprintln!("Error removing file {:?}", e)
         }
     }
 
     fn append_data(&mut self, name: &str, new_data: [u8; SECTOR_SIZE]) {
+        if Self::is_read_only(name) {
+            return;
+        }
         let data = self.get_file_data(name);
         if data.is_none() {
             return;
@@ -235,13 +732,22 @@ impl Terminal
         self.write(name, data);
     }
     fn mkdir(&mut self, name: &str) {
-        match self.fs.new_dir(name)
+        if Self::is_read_only(name) {
+            return;
+        }
+        match with_volume(|fs| fs.new_dir(&current_dir(), name))
         {
             Ok(_) => {},
             Err(e) => eprintln!("Error adding dir {:?}", e)
         }
     }
 
+    fn mkfifo(name: &str) {
+        if let Err(e) = pipe::mkfifo(&full_path(name)) {
+            eprintln!("Error creating fifo {:?}", e);
+        }
+    }
+
     fn cd(&self, parm: &str) {
         if parm == ".." {
             Self::remove_last_path();
@@ -251,7 +757,7 @@ impl Terminal
         }
     }
     fn remove_last_path() {
-        let mut dir = WORKING_DIR.lock();
+        let mut dir = current_dir();
         dir.pop();
         if let Some(pos) = dir.rfind('/') {
             if pos == 0 {
@@ -261,11 +767,22 @@ impl Terminal
                 dir.truncate(pos+ 1);
             }
         }
+        set_current_dir(dir);
     }
 
     fn add_path(&self, dir_name: &str)
     {
-        match self.fs.search_directory(dir_name)
+        if let Some((fs, path)) = mount::resolve(&full_path(dir_name)) {
+            match fs.lookup(&path) {
+                Ok(inode) if inode.kind == FileKind::Directory => {
+                    set_current_dir(format!("{}{}/", current_dir(), dir_name));
+                }
+                Ok(_) => eprintln!("Error directory not found!"),
+                Err(e) => eprintln!("Error searching directory: {:?}", e),
+            }
+            return;
+        }
+        match with_volume(|fs| fs.search_directory(&current_dir(), dir_name))
         {
             Err(e) => eprintln!("Error searching directory: {:?}", e),
             Ok(found) => {
@@ -274,13 +791,20 @@ impl Terminal
                     eprintln!("Error directory not found!");
                     return;
                 }
-                *WORKING_DIR.lock() += dir_name ;
-                *WORKING_DIR.lock() += "/";
+                set_current_dir(format!("{}{}/", current_dir(), dir_name));
 
             }
         }
     }
 }
+// absolute path of 'name' inside the working directory
+fn full_path(name: &str) -> String {
+    if name.starts_with('/') {
+        return String::from(name);
+    }
+    format!("{}{}", current_dir(), name)
+}
+
 fn to_buffer(str: &str) -> [u8; SECTOR_SIZE] {
     let mut buffer: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
     for (index, char) in str.char_indices() {
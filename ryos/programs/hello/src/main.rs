@@ -0,0 +1,33 @@
+//HELLO
+//Example ryos program: greets, shows its arguments and environment, uses the heap and sleeps a moment
+#![no_std]
+#![no_main]
+
+use ryos_std::string::String;
+use ryos_std::time::{self, Duration};
+use ryos_std::vec::Vec;
+use ryos_std::{env, println, process};
+
+ryos_std::entry!(main);
+
+fn main() -> i32 {
+    println!("hello from ryos-std, running as process {} (parent {})", process::id(), process::parent_id());
+    for (i, arg) in env::args().enumerate() {
+        println!("argv[{}] = {}", i, arg);
+    }
+    for (name, value) in env::vars() {
+        println!("{}={}", name, value);
+    }
+
+    let squares: Vec<u64> = (1..=10).map(|n| n * n).collect();
+    let mut text = String::new();
+    for square in &squares {
+        text += &ryos_std::format!("{} ", square);
+    }
+    println!("squares on the heap: {}", text);
+
+    let start = time::uptime();
+    time::sleep(Duration::from_millis(500));
+    println!("slept {} ms", (time::uptime() - start).as_millis());
+    0
+}
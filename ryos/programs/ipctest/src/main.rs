@@ -0,0 +1,125 @@
+//IPCTEST
+//Example ryos program: registers the port "ipctest" and forks a server that answers calls on it. The
+//parent looks the port up like any client would, sends text, grants a page for the server to upper
+//case in place, passes a port handle and finally tells the server to quit
+#![no_std]
+#![no_main]
+
+use core::slice;
+use ryos_std::ipc::{self, Attachments, Port};
+use ryos_std::mem::{self, PROT_READ, PROT_WRITE};
+use ryos_std::syscall::Error;
+use ryos_std::{eprintln, format, println, process};
+
+ryos_std::entry!(main);
+
+const NAME: &str = "ipctest";
+const PAGE_SIZE: usize = 4096;
+
+fn main() -> i32 {
+    let port = match Port::create().and_then(|port| port.register(NAME).map(|_| port)) {
+        Ok(port) => port,
+        Err(error) => {
+            eprintln!("ipctest: can't register {}: {:?}", NAME, error);
+            return 1;
+        }
+    };
+    match process::fork() {
+        Ok(0) => serve(port),
+        Ok(server) => {
+            // only the server keeps the receive right, the port closes when it exits
+            drop(port);
+            let code = match client() {
+                Ok(()) => 0,
+                Err(error) => {
+                    eprintln!("ipctest: {:?}", error);
+                    1
+                }
+            };
+            if let Ok((_, server_code)) = process::wait(Some(server)) {
+                println!("server exited with code {}", server_code);
+            }
+            code
+        }
+        Err(error) => {
+            eprintln!("ipctest: fork failed: {:?}", error);
+            1
+        }
+    }
+}
+
+// answers every call with its data in upper case, granted pages are upper cased in place
+fn serve(port: Port) -> i32 {
+    let mut buffer = [0u8; ipc::MAX_MESSAGE];
+    loop {
+        let mut call = match port.receive(&mut buffer) {
+            Ok(call) => call,
+            Err(error) => {
+                eprintln!("server: receive failed: {:?}", error);
+                return 1;
+            }
+        };
+        let data = &mut buffer[..call.len];
+        if data == b"quit" {
+            return call.reply(b"bye").map_or(1, |_| 0);
+        }
+        let result = if let Some(grant) = call.grant.as_mut() {
+            grant.as_mut_slice().make_ascii_uppercase();
+            call.reply(b"page upper cased")
+        } else if let Some(capability) = call.capability.take() {
+            call.reply(format!("got a capability as handle {}", capability.handle()).as_bytes())
+        } else {
+            data.make_ascii_uppercase();
+            call.reply(data)
+        };
+        if let Err(error) = result {
+            eprintln!("server: reply failed: {:?}", error);
+        }
+    }
+}
+
+fn client() -> Result<(), Error> {
+    let server = Port::lookup(NAME)?;
+    let mut reply = [0u8; 128];
+
+    let len = server.call(b"hello through a port", &mut reply)?;
+    println!("reply: {}", text(&reply[..len]));
+
+    let page = mem::map(0, PAGE_SIZE, PROT_READ | PROT_WRITE)?;
+    let page = unsafe { slice::from_raw_parts_mut(page, PAGE_SIZE) };
+    let message = b"this page is shared, not copied";
+    page[..message.len()].copy_from_slice(message);
+    let attachments = Attachments {
+        grant: Some(&mut page[..]),
+        writable: true,
+        capability: None,
+    };
+    let len = server.call_with(b"", &mut reply, attachments)?;
+    println!("reply: {}, page now: {}", text(&reply[..len]), text(&page[..message.len()]));
+
+    // a looked up handle can only send, passing it on needs the grant right
+    let attachments = Attachments {
+        capability: Some(&server),
+        ..Attachments::default()
+    };
+    match server.call_with(b"", &mut reply, attachments) {
+        Err(Error::NotPermitted) => println!("passing a send only handle: not permitted"),
+        other => println!("passing a send only handle: {:?}", other),
+    }
+    let own = Port::create()?;
+    let passed = own.restrict(ipc::SEND | ipc::GRANT)?;
+    let attachments = Attachments {
+        capability: Some(&passed),
+        ..Attachments::default()
+    };
+    let len = server.call_with(b"", &mut reply, attachments)?;
+    println!("reply: {}", text(&reply[..len]));
+
+    let len = server.call(b"quit", &mut reply)?;
+    println!("reply: {}", text(&reply[..len]));
+    Ok(())
+}
+
+fn text(bytes: &[u8]) -> &str {
+    core::str::from_utf8(bytes).unwrap_or("<not utf-8>")
+}
@@ -0,0 +1,45 @@
+//SPAWNTEST
+//Example ryos program: starts /bin/hello with spawn, then forks a child that turns into hello with
+//exec, and waits for both
+#![no_std]
+#![no_main]
+
+use ryos_std::{eprintln, println, process};
+
+ryos_std::entry!(main);
+
+const HELLO: &str = "/bin/hello";
+const ENV: [&str; 1] = ["PATH=/bin"];
+
+fn main() -> i32 {
+    match process::spawn(HELLO, &["hello", "spawned"], &ENV) {
+        Ok(pid) => println!("spawned hello as process {}", pid),
+        Err(error) => {
+            eprintln!("spawn failed: {:?}", error);
+            return 1;
+        }
+    }
+    report(process::wait(None));
+
+    match process::fork() {
+        Ok(0) => {
+            let error = process::exec(HELLO, &["hello", "forked", "and", "executed"], &ENV);
+            eprintln!("exec failed: {:?}", error);
+            return 2;
+        }
+        Ok(pid) => println!("forked process {}", pid),
+        Err(error) => {
+            eprintln!("fork failed: {:?}", error);
+            return 1;
+        }
+    }
+    report(process::wait(None));
+    0
+}
+
+fn report(result: Result<(u64, i32), ryos_std::syscall::Error>) {
+    match result {
+        Ok((pid, code)) => println!("process {} exited with code {}", pid, code),
+        Err(error) => eprintln!("wait failed: {:?}", error),
+    }
+}
@@ -0,0 +1,41 @@
+//UPPER
+//Example ryos program: copies its input to its output in upper case until the end of the input, for
+//pipelines like 'hello | upper'. With a path argument it reads that file or FIFO instead
+#![no_std]
+#![no_main]
+
+use ryos_std::fs::File;
+use ryos_std::{eprintln, env, io};
+
+ryos_std::entry!(main);
+
+fn main() -> i32 {
+    let file = match env::args().nth(1) {
+        Some(path) => match File::open(path) {
+            Ok(file) => Some(file),
+            Err(error) => {
+                eprintln!("upper: {}: {:?}", path, error);
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let fd = file.as_ref().map_or(io::STDIN, File::fd);
+
+    let mut buffer = [0u8; 512];
+    loop {
+        let count = match io::read(fd, &mut buffer) {
+            Ok(0) => return 0,
+            Ok(count) => count,
+            Err(error) => {
+                eprintln!("upper: {:?}", error);
+                return 1;
+            }
+        };
+        buffer[..count].make_ascii_uppercase();
+        if let Err(error) = io::write_all(io::STDOUT, &buffer[..count]) {
+            eprintln!("upper: {:?}", error);
+            return 1;
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//ENVIRONMENT
+//The arguments and environment the kernel put on the stack of the program, see _start
+use core::ffi::CStr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+static ENVP: AtomicPtr<*const u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/*
+this function remembers argc, argv and envp from the initial stack: argc at 'stack', the argv pointers
+after it and the envp pointers after the null ending argv
+ */
+pub(crate) unsafe fn init(stack: *const u64) {
+    unsafe {
+        let argc = *stack as usize;
+        let argv = stack.add(1) as *mut *const u8;
+        ARGC.store(argc, Ordering::Relaxed);
+        ARGV.store(argv, Ordering::Relaxed);
+        ENVP.store(argv.add(argc + 1), Ordering::Relaxed);
+    }
+}
+
+unsafe fn string(pointer: *const u8) -> &'static str {
+    unsafe { CStr::from_ptr(pointer.cast()) }.to_str().unwrap_or("")
+}
+
+// the arguments, the first one is the name the program was started with
+pub fn args() -> impl Iterator<Item = &'static str> {
+    let argv = ARGV.load(Ordering::Relaxed);
+    (0..ARGC.load(Ordering::Relaxed)).map(move |i| unsafe { string(*argv.add(i)) })
+}
+
+// the environment variables as name and value
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    let mut next = ENVP.load(Ordering::Relaxed);
+    core::iter::from_fn(move || unsafe {
+        if next.is_null() || (*next).is_null() {
+            return None;
+        }
+        let entry = string(*next);
+        next = next.add(1);
+        Some(entry.split_once('=').unwrap_or((entry, "")))
+    })
+}
+
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find(|(key, _)| *key == name).map(|(_, value)| value)
+}
@@ -0,0 +1,48 @@
+//FILES
+//Files opened through the kernel, closed again when dropped. only files of the mounted ext2 file
+//systems and FIFOs can be opened, FIFOs are the only ones that can be written
+use crate::io;
+use crate::syscall::{self, result, Error};
+
+const O_RDONLY: u64 = 0;
+const O_WRONLY: u64 = 1;
+
+// create a named pipe, it stays until it is removed with rm in the shell or the machine restarts
+pub fn mkfifo(path: &str) -> Result<(), Error> {
+    result(unsafe { syscall::syscall2(syscall::MKFIFO, path.as_ptr() as u64, path.len() as u64) }).map(|_| ())
+}
+
+pub struct File {
+    fd: u64,
+}
+
+impl File {
+    pub fn open(path: &str) -> Result<File, Error> {
+        let fd = result(unsafe { syscall::syscall3(syscall::OPEN, path.as_ptr() as u64, path.len() as u64, O_RDONLY) })?;
+        Ok(File { fd })
+    }
+
+    // the write end of the FIFO at 'path'
+    pub fn open_write(path: &str) -> Result<File, Error> {
+        let fd = result(unsafe { syscall::syscall3(syscall::OPEN, path.as_ptr() as u64, path.len() as u64, O_WRONLY) })?;
+        Ok(File { fd })
+    }
+
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        io::read(self.fd, buffer)
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        io::write(self.fd, data)
+    }
+
+    pub fn fd(&self) -> u64 {
+        self.fd
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe { syscall::syscall1(syscall::CLOSE, self.fd) };
+    }
+}
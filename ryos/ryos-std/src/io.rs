@@ -0,0 +1,100 @@
+//CONSOLE
+//Standard input and output are the terminal unless the shell connected them to a pipe or FIFO: on
+//the terminal fd 0 reads whole lines, fd 1 and 2 print
+use crate::syscall::{self, result, Error};
+use alloc::string::String;
+use core::fmt;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+pub fn write(fd: u64, data: &[u8]) -> Result<usize, Error> {
+    result(unsafe { syscall::syscall3(syscall::WRITE, fd, data.as_ptr() as u64, data.len() as u64) })
+        .map(|count| count as usize)
+}
+
+pub fn read(fd: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+    result(unsafe { syscall::syscall3(syscall::READ, fd, buffer.as_mut_ptr() as u64, buffer.len() as u64) })
+        .map(|count| count as usize)
+}
+
+// write all of 'data', the kernel may take less than asked for
+pub fn write_all(fd: u64, mut data: &[u8]) -> Result<(), Error> {
+    while !data.is_empty() {
+        let written = write(fd, data)?;
+        if written == 0 {
+            return Err(Error::Invalid);
+        }
+        data = &data[written..];
+    }
+    Ok(())
+}
+
+// the read end and the write end of a new pipe
+pub fn pipe() -> Result<(u64, u64), Error> {
+    let mut fds = [0i32; 2];
+    result(unsafe { syscall::syscall1(syscall::PIPE, fds.as_mut_ptr() as u64) })?;
+    Ok((fds[0] as u64, fds[1] as u64))
+}
+
+// 'new_fd' refers to the same open file as 'fd' afterwards, like dup2 before exec to redirect a child
+pub fn dup2(fd: u64, new_fd: u64) -> Result<u64, Error> {
+    result(unsafe { syscall::syscall2(syscall::DUP2, fd, new_fd) })
+}
+
+pub fn close(fd: u64) -> Result<(), Error> {
+    result(unsafe { syscall::syscall1(syscall::CLOSE, fd) }).map(|_| ())
+}
+
+// wait for the user to enter a line, it is returned without the newline
+pub fn read_line() -> Result<String, Error> {
+    let mut line = alloc::vec::Vec::new();
+    let mut buffer = [0u8; 256];
+    loop {
+        let count = read(STDIN, &mut buffer)?;
+        line.extend_from_slice(&buffer[..count]);
+        if count == 0 || line.last() == Some(&b'\n') {
+            break;
+        }
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+pub struct Console(pub u64);
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(self.0, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: u64, args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Console(fd), args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
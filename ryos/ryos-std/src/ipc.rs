@@ -0,0 +1,157 @@
+//IPC
+//Message passing through ports. A client calls a port and waits for the reply, a server receives the
+//calls and replies to each one. A call can grant pages of the caller's memory, which the server then
+//shares until it drops them, and pass on a handle to another port
+use crate::mem;
+use crate::syscall::{self, result, Error};
+use core::slice;
+
+// the rights of a handle
+pub const SEND: u64 = 1;
+pub const RECEIVE: u64 = 2;
+// the handle may be passed on in a call
+pub const GRANT: u64 = 4;
+pub const ALL_RIGHTS: u64 = SEND | RECEIVE | GRANT;
+
+// bytes of the data of a call or a reply
+pub const MAX_MESSAGE: usize = 4096;
+
+const GRANT_WRITE: u64 = 2;
+const NO_CAPABILITY: u64 = u64::MAX;
+
+// a handle to a port, closed when dropped. the port closes with the last handle that can receive
+pub struct Port {
+    handle: u64,
+}
+
+impl Port {
+    // a new port, this handle has all rights
+    pub fn create() -> Result<Port, Error> {
+        let handle = result(syscall::syscall0(syscall::PORT_CREATE))?;
+        Ok(Port { handle })
+    }
+
+    // a handle with the send right to the port registered under 'name'
+    pub fn lookup(name: &str) -> Result<Port, Error> {
+        let handle = result(unsafe { syscall::syscall2(syscall::PORT_LOOKUP, name.as_ptr() as u64, name.len() as u64) })?;
+        Ok(Port { handle })
+    }
+
+    // clients find the port under 'name' until it closes, only a handle with the receive right can do it
+    pub fn register(&self, name: &str) -> Result<(), Error> {
+        let (name, len) = (name.as_ptr() as u64, name.len() as u64);
+        result(unsafe { syscall::syscall3(syscall::PORT_REGISTER, self.handle, name, len) }).map(|_| ())
+    }
+
+    // a second handle to the port with only some of the rights of this one
+    pub fn restrict(&self, rights: u64) -> Result<Port, Error> {
+        let handle = result(unsafe { syscall::syscall2(syscall::CAP_RESTRICT, self.handle, rights) })?;
+        Ok(Port { handle })
+    }
+
+    pub fn handle(&self) -> u64 {
+        self.handle
+    }
+
+    // send 'data' and wait for the reply, which is cut to 'reply'. returns the full length of the reply
+    pub fn call(&self, data: &[u8], reply: &mut [u8]) -> Result<usize, Error> {
+        self.call_with(data, reply, Attachments::default())
+    }
+
+    pub fn call_with(&self, data: &[u8], reply: &mut [u8], attachments: Attachments) -> Result<usize, Error> {
+        let (grant_addr, grant_len) = attachments
+            .grant
+            .map_or((0, 0), |grant| (grant.as_mut_ptr() as u64, grant.len() as u64));
+        let raw = [
+            grant_addr,
+            grant_len,
+            if attachments.writable { GRANT_WRITE } else { 0 },
+            attachments.capability.map_or(NO_CAPABILITY, Port::handle),
+        ];
+        let args = [
+            self.handle,
+            data.as_ptr() as u64,
+            data.len() as u64,
+            reply.as_mut_ptr() as u64,
+            reply.len() as u64,
+            raw.as_ptr() as u64,
+        ];
+        result(unsafe { syscall::syscall6(syscall::IPC_CALL, args) }).map(|len| len as usize)
+    }
+
+    // wait for the next call, its data goes to the start of 'buffer'. the call has to be replied to
+    pub fn receive(&self, buffer: &mut [u8]) -> Result<Call, Error> {
+        let mut info = [0u64; 5];
+        let args = [self.handle, buffer.as_mut_ptr() as u64, buffer.len() as u64, info.as_mut_ptr() as u64, 0, 0];
+        let token = result(unsafe { syscall::syscall6(syscall::IPC_RECEIVE, args) })?;
+        let [sender, len, grant_addr, grant_len, capability] = info;
+        Ok(Call {
+            token,
+            sender,
+            len: len as usize,
+            grant: (grant_len != 0).then_some(Grant {
+                addr: grant_addr as *mut u8,
+                len: grant_len as usize,
+            }),
+            capability: (capability != NO_CAPABILITY).then_some(Port { handle: capability }),
+        })
+    }
+}
+
+impl Drop for Port {
+    fn drop(&mut self) {
+        unsafe { syscall::syscall1(syscall::CAP_CLOSE, self.handle) };
+    }
+}
+
+// what a call carries besides its data
+#[derive(Default)]
+pub struct Attachments<'a> {
+    // page aligned memory the server gets to see, whole pages are shared
+    pub grant: Option<&'a mut [u8]>,
+    // the server may write to the granted memory
+    pub writable: bool,
+    // the server gets its own copy of the handle, which needs the grant right
+    pub capability: Option<&'a Port>,
+}
+
+// the memory a caller granted, unmapped when dropped
+pub struct Grant {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Grant {
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.addr, self.len) }
+    }
+
+    // writing to a grant that isn't writable ends the program
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.addr, self.len) }
+    }
+}
+
+impl Drop for Grant {
+    fn drop(&mut self) {
+        let _ = unsafe { mem::unmap(self.addr, self.len) };
+    }
+}
+
+// a call a server received
+pub struct Call {
+    token: u64,
+    pub sender: u64,
+    // bytes of data in the buffer given to receive
+    pub len: usize,
+    pub grant: Option<Grant>,
+    pub capability: Option<Port>,
+}
+
+impl Call {
+    // the caller continues with 'data', the grant of the call is unmapped
+    pub fn reply(self, data: &[u8]) -> Result<(), Error> {
+        let (data, len) = (data.as_ptr() as u64, data.len() as u64);
+        result(unsafe { syscall::syscall3(syscall::IPC_REPLY, self.token, data, len) }).map(|_| ())
+    }
+}
@@ -0,0 +1,58 @@
+//RYOS STD
+//Runtime for programs running on ryos: the entry point, a panic handler, a heap on top of mmap and
+//wrappers around the system calls. Programs are #![no_std] and #![no_main] and name their main
+//function with ryos_std::entry!
+#![no_std]
+
+extern crate alloc;
+
+pub mod env;
+pub mod fs;
+pub mod io;
+pub mod ipc;
+pub mod mem;
+pub mod process;
+pub mod signal;
+pub mod syscall;
+pub mod time;
+
+pub use alloc::{borrow, boxed, format, string, vec};
+use core::panic::PanicInfo;
+
+// 'main' is a fn() -> i32, its result becomes the exit code of the program
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[unsafe(no_mangle)]
+        fn __ryos_main() -> i32 {
+            let main: fn() -> i32 = $main;
+            main()
+        }
+    };
+}
+
+unsafe extern "Rust" {
+    fn __ryos_main() -> i32;
+}
+
+// the kernel starts the program with the stack pointer at argc, 16 byte aligned
+core::arch::global_asm!(
+    ".globl _start",
+    "_start:",
+    "mov rdi, rsp",
+    "call {start}",
+    "ud2",
+    start = sym start,
+);
+
+extern "C" fn start(stack: *const u64) -> ! {
+    unsafe { env::init(stack) };
+    let code = unsafe { __ryos_main() };
+    process::exit(code)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{}", info);
+    process::exit(101)
+}
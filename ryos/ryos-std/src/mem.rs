@@ -0,0 +1,81 @@
+//MEMORY
+//Mappings of anonymous memory and the heap of the program, which grows by mapping more pages right
+//after its end
+use crate::syscall::{self, result, Error};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+const PAGE_SIZE: usize = 4096;
+const HEAP_START: usize = 0x4000_0000;
+const HEAP_MAX_SIZE: usize = 256 * 1024 * 1024;
+// map at least this much at a time
+const HEAP_GROW_STEP: usize = 64 * 1024;
+
+// map 'len' bytes of zeroed memory at 'addr', 0 lets the kernel choose the address
+pub fn map(addr: usize, len: usize, prot: u64) -> Result<*mut u8, Error> {
+    result(unsafe { syscall::syscall3(syscall::MMAP, addr as u64, len as u64, prot) }).map(|start| start as *mut u8)
+}
+
+/// # Safety
+/// nothing may use the memory afterwards
+pub unsafe fn unmap(addr: *mut u8, len: usize) -> Result<(), Error> {
+    result(unsafe { syscall::syscall2(syscall::MUNMAP, addr as u64, len as u64) }).map(|_| ())
+}
+
+struct ProgramHeap {
+    heap: Mutex<Heap>,
+}
+
+impl ProgramHeap {
+    // map enough pages for 'layout' at the top of the heap, the first call maps the heap itself
+    fn grow(heap: &mut Heap, layout: Layout) -> bool {
+        let needed = layout.size() + layout.align();
+        let by = needed.max(HEAP_GROW_STEP).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        if heap.size() + by > HEAP_MAX_SIZE {
+            return false;
+        }
+        let top = match heap.size() {
+            0 => HEAP_START,
+            _ => heap.top() as usize,
+        };
+        if map(top, by, PROT_READ | PROT_WRITE).is_err() {
+            return false;
+        }
+        unsafe {
+            match heap.size() {
+                0 => heap.init(top as *mut u8, by),
+                _ => heap.extend(by),
+            }
+        }
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for ProgramHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        loop {
+            if let Ok(allocation) = heap.allocate_first_fit(layout) {
+                return allocation.as_ptr();
+            }
+            if !Self::grow(&mut heap, layout) {
+                return null_mut();
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout) };
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: ProgramHeap = ProgramHeap {
+    heap: Mutex::new(Heap::empty()),
+};
@@ -0,0 +1,73 @@
+//PROCESS
+//Leaving the program, giving up the processor and starting and waiting for children
+use crate::syscall::{self, result, Error};
+use alloc::vec::Vec;
+
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall::syscall1(syscall::EXIT, code as i64 as u64) };
+    unreachable!("exit returned")
+}
+
+pub fn id() -> u64 {
+    syscall::syscall0(syscall::GETPID)
+}
+
+pub fn parent_id() -> u64 {
+    syscall::syscall0(syscall::GETPPID)
+}
+
+// wait until the child 'pid' (any child for None) exits, returns its pid and exit code
+pub fn wait(pid: Option<u64>) -> Result<(u64, i32), Error> {
+    let mut code = 0i32;
+    let target = pid.unwrap_or(u64::MAX);
+    let pid = syscall::result(unsafe { syscall::syscall2(syscall::WAIT, target, &raw mut code as u64) })?;
+    Ok((pid, code))
+}
+
+// let the other tasks run before this one continues
+pub fn yield_now() {
+    syscall::syscall0(syscall::YIELD);
+}
+
+// the args or env strings as spawn and exec take them, each one followed by a 0 byte
+fn strings(list: &[&str]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for string in list {
+        buffer.extend_from_slice(string.as_bytes());
+        buffer.push(0);
+    }
+    buffer
+}
+
+fn program_args(path: &str, args: &[&str], env: &[&str]) -> (Vec<u8>, Vec<u8>, [u64; 6]) {
+    let (args, env) = (strings(args), strings(env));
+    let registers = [
+        path.as_ptr() as u64,
+        path.len() as u64,
+        args.as_ptr() as u64,
+        args.len() as u64,
+        env.as_ptr() as u64,
+        env.len() as u64,
+    ];
+    (args, env, registers)
+}
+
+// start the program at 'path' as a child, 'args' starts with its name. returns the pid of the child
+pub fn spawn(path: &str, args: &[&str], env: &[&str]) -> Result<u64, Error> {
+    let (_args, _env, registers) = program_args(path, args, env);
+    result(unsafe { syscall::syscall6(syscall::SPAWN, registers) })
+}
+
+// the pid of the child in the parent and 0 in the child
+pub fn fork() -> Result<u64, Error> {
+    result(syscall::syscall0(syscall::FORK))
+}
+
+// run the program at 'path' instead of this one, only returns when that fails
+pub fn exec(path: &str, args: &[&str], env: &[&str]) -> Error {
+    let (_args, _env, registers) = program_args(path, args, env);
+    match result(unsafe { syscall::syscall6(syscall::EXEC, registers) }) {
+        Ok(_) => unreachable!("exec returned"),
+        Err(error) => error,
+    }
+}
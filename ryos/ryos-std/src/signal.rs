@@ -0,0 +1,51 @@
+//SIGNAL
+//Catching, ignoring and sending signals. A handler runs when the program returns from its next system
+//call, with the signal number as its argument, and returns to the restorer below, which makes the
+//sigreturn call that continues the program where it was
+use crate::syscall::{self, result, Error};
+
+pub const SIGINT: u64 = 2;
+pub const SIGKILL: u64 = 9;
+pub const SIGTERM: u64 = 15;
+pub const SIGCHLD: u64 = 17;
+
+// the handler values sigaction takes besides function addresses
+const SIG_DFL: u64 = 0;
+const SIG_IGN: u64 = 1;
+
+unsafe extern "C" {
+    fn __ryos_restorer();
+}
+
+// the handler returns here, the kernel saved the interrupted registers at the stack pointer
+core::arch::global_asm!(
+    ".globl __ryos_restorer",
+    "__ryos_restorer:",
+    "mov eax, {sigreturn}",
+    "syscall",
+    "ud2",
+    sigreturn = const syscall::SIGRETURN,
+);
+
+fn sigaction(signal: u64, handler: u64) -> Result<(), Error> {
+    let restorer = __ryos_restorer as unsafe extern "C" fn() as u64;
+    result(unsafe { syscall::syscall3(syscall::SIGACTION, signal, handler, restorer) }).map(|_| ())
+}
+
+// run 'handler' for 'signal' instead of its default action, SIGKILL can't be caught
+pub fn set_handler(signal: u64, handler: extern "C" fn(u64)) -> Result<(), Error> {
+    sigaction(signal, handler as u64)
+}
+
+pub fn ignore(signal: u64) -> Result<(), Error> {
+    sigaction(signal, SIG_IGN)
+}
+
+// back to the default action: SIGCHLD is ignored, every other signal ends the program
+pub fn reset(signal: u64) -> Result<(), Error> {
+    sigaction(signal, SIG_DFL)
+}
+
+pub fn kill(pid: u64, signal: u64) -> Result<(), Error> {
+    result(unsafe { syscall::syscall2(syscall::KILL, pid, signal) }).map(|_| ())
+}
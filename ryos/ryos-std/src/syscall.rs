@@ -0,0 +1,146 @@
+//SYSTEM CALLS
+//The raw syscall instruction with the numbers of the ryos abi, the table is in the "System Calls"
+//section of the readme
+use core::arch::asm;
+
+pub const READ: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const OPEN: u64 = 2;
+pub const CLOSE: u64 = 3;
+pub const EXIT: u64 = 4;
+pub const YIELD: u64 = 5;
+pub const GETPID: u64 = 6;
+pub const MMAP: u64 = 7;
+pub const MUNMAP: u64 = 8;
+pub const UPTIME: u64 = 9;
+pub const SLEEP: u64 = 10;
+pub const WAIT: u64 = 11;
+pub const GETPPID: u64 = 12;
+pub const SPAWN: u64 = 13;
+pub const FORK: u64 = 14;
+pub const EXEC: u64 = 15;
+pub const SIGACTION: u64 = 16;
+pub const KILL: u64 = 17;
+pub const SIGRETURN: u64 = 18;
+pub const PIPE: u64 = 19;
+pub const DUP2: u64 = 20;
+pub const MKFIFO: u64 = 21;
+pub const PORT_CREATE: u64 = 22;
+pub const PORT_REGISTER: u64 = 23;
+pub const PORT_LOOKUP: u64 = 24;
+pub const IPC_CALL: u64 = 25;
+pub const IPC_RECEIVE: u64 = 26;
+pub const IPC_REPLY: u64 = 27;
+pub const CAP_RESTRICT: u64 = 28;
+pub const CAP_CLOSE: u64 = 29;
+
+// the errno values the kernel returns, negated, in rax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NotPermitted,
+    NoEntry,
+    NoProcess,
+    Interrupted,
+    Io,
+    TooBig,
+    NoExec,
+    BadFd,
+    NoChild,
+    Again,
+    NoMemory,
+    Access,
+    Fault,
+    Exists,
+    NotDir,
+    IsDir,
+    Invalid,
+    TooManyFiles,
+    ReadOnly,
+    BrokenPipe,
+    NoSys,
+    Other(u64),
+}
+
+impl Error {
+    fn from_errno(errno: u64) -> Self {
+        match errno {
+            1 => Error::NotPermitted,
+            2 => Error::NoEntry,
+            3 => Error::NoProcess,
+            4 => Error::Interrupted,
+            5 => Error::Io,
+            7 => Error::TooBig,
+            8 => Error::NoExec,
+            9 => Error::BadFd,
+            10 => Error::NoChild,
+            11 => Error::Again,
+            12 => Error::NoMemory,
+            13 => Error::Access,
+            14 => Error::Fault,
+            17 => Error::Exists,
+            20 => Error::NotDir,
+            21 => Error::IsDir,
+            22 => Error::Invalid,
+            24 => Error::TooManyFiles,
+            30 => Error::ReadOnly,
+            32 => Error::BrokenPipe,
+            38 => Error::NoSys,
+            other => Error::Other(other),
+        }
+    }
+}
+
+// results from -4095 to -1 are errors, like on linux
+pub fn result(value: u64) -> Result<u64, Error> {
+    let signed = value as i64;
+    if (-4095..0).contains(&signed) {
+        Err(Error::from_errno(signed.unsigned_abs()))
+    } else {
+        Ok(value)
+    }
+}
+
+/// # Safety
+/// pointer arguments have to be valid for what the call does with them
+pub unsafe fn syscall6(number: u64, args: [u64; 6]) -> u64 {
+    let value;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number => value,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            in("r10") args[3],
+            in("r8") args[4],
+            in("r9") args[5],
+            // syscall keeps the return address and the flags in them
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    value
+}
+
+/// # Safety
+/// see syscall6
+pub unsafe fn syscall3(number: u64, arg0: u64, arg1: u64, arg2: u64) -> u64 {
+    unsafe { syscall6(number, [arg0, arg1, arg2, 0, 0, 0]) }
+}
+
+/// # Safety
+/// see syscall3
+pub unsafe fn syscall2(number: u64, arg0: u64, arg1: u64) -> u64 {
+    unsafe { syscall3(number, arg0, arg1, 0) }
+}
+
+/// # Safety
+/// see syscall3
+pub unsafe fn syscall1(number: u64, arg0: u64) -> u64 {
+    unsafe { syscall3(number, arg0, 0, 0) }
+}
+
+pub fn syscall0(number: u64) -> u64 {
+    unsafe { syscall3(number, 0, 0, 0) }
+}
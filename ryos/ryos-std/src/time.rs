@@ -0,0 +1,13 @@
+//TIME
+//The kernel counts time in timer interrupts, about 55 ms each, so everything here has that resolution
+use crate::syscall;
+pub use core::time::Duration;
+
+// time since the kernel started
+pub fn uptime() -> Duration {
+    Duration::from_millis(syscall::syscall0(syscall::UPTIME))
+}
+
+pub fn sleep(duration: Duration) {
+    unsafe { syscall::syscall1(syscall::SLEEP, duration.as_millis() as u64) };
+}
@@ -1,9 +1,23 @@
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::process::Command;
+use std::env;
 
 pub const DISK_IMAGE: &str = "disk.img";
 const DISK_SIZE: u64 = 32 * 1024 * 1024; // 32MB virtual disk
+const SECTOR_SIZE: u64 = 512;
+
+// partition layout of the data disk
+const RYOS_PARTITION_TYPE: u8 = 0x7F;
+const RYOS_PARTITION_START: u64 = 2048; // 1MB aligned
+const RYOS_PARTITION_SECTORS: u64 = 16 * 1024 * 1024 / SECTOR_SIZE; // 16MB, the rest is left for other partitions
+const SWAP_PARTITION_TYPE: u8 = 0x82; // linux swap, used by swapon
+const SWAP_PARTITION_START: u64 = RYOS_PARTITION_START + RYOS_PARTITION_SECTORS;
+const SWAP_PARTITION_SECTORS: u64 = 8 * 1024 * 1024 / SECTOR_SIZE; // 8MB
+const PROGRAMS_PARTITION_TYPE: u8 = 0x83; // linux, an ext2 file system the kernel mounts at /bin
+const PROGRAMS_PARTITION_START: u64 = SWAP_PARTITION_START + SWAP_PARTITION_SECTORS;
+const PROGRAMS_PARTITION_SECTORS: u64 = 4 * 1024 * 1024 / SECTOR_SIZE; // 4MB
 
 pub fn create_disk_if_not_exists() {
     if !Path::new(DISK_IMAGE).exists() {
@@ -18,5 +32,56 @@ pub fn create_disk_if_not_exists() {
         for _ in 0..(DISK_SIZE / 512) {
             file.write_all(&zeros).unwrap();
         }
+
+        // The kernel formats the ryos partition on first boot
+        let mut mbr = [0u8; SECTOR_SIZE as usize];
+        write_mbr_entry(&mut mbr, 0, RYOS_PARTITION_TYPE, RYOS_PARTITION_START, RYOS_PARTITION_SECTORS);
+        write_mbr_entry(&mut mbr, 1, SWAP_PARTITION_TYPE, SWAP_PARTITION_START, SWAP_PARTITION_SECTORS);
+        write_mbr_entry(&mut mbr, 2, PROGRAMS_PARTITION_TYPE, PROGRAMS_PARTITION_START, PROGRAMS_PARTITION_SECTORS);
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&mbr).unwrap();
+    }
+}
+
+fn write_mbr_entry(mbr: &mut [u8], index: usize, partition_type: u8, start: u64, sectors: u64) {
+    let entry = &mut mbr[446 + index * 16..][..16];
+    // chs fields are unused, mark them as "use lba"
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = partition_type;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&(start as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&(sectors as u32).to_le_bytes());
+}
+
+/*
+this function formats the programs partition as ext2 holding 'programs' (the name in /bin and the path
+of the built executable), so every run sees the current builds. needs mke2fs from e2fsprogs
+ */
+pub fn install_programs(programs: &[(&str, &str)]) {
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    File::open(DISK_IMAGE).unwrap().read_exact(&mut mbr).unwrap();
+    if mbr[446 + 2 * 16 + 4] != PROGRAMS_PARTITION_TYPE {
+        println!("{} has no programs partition, delete it to get one", DISK_IMAGE);
+        return;
+    }
+
+    let directory = env::temp_dir().join("ryos-programs");
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    for (name, path) in programs {
+        fs::copy(path, directory.join(name)).unwrap();
+    }
+    let status = Command::new("mke2fs")
+        .args(["-q", "-F", "-t", "ext2", "-b", "1024", "-d"])
+        .arg(&directory)
+        .arg("-E")
+        .arg(format!("offset={}", PROGRAMS_PARTITION_START * SECTOR_SIZE))
+        .arg(DISK_IMAGE)
+        .arg(format!("{}k", PROGRAMS_PARTITION_SECTORS * SECTOR_SIZE / 1024))
+        .status();
+    if !status.is_ok_and(|status| status.success()) {
+        println!("mke2fs failed, the programs in /bin are not updated");
     }
-}
\ No newline at end of file
+}
@@ -9,14 +9,36 @@ mod create_disk;
 
 fn main() {
     create_disk::create_disk_if_not_exists();
+    create_disk::install_programs(&[
+        ("hello", env!("HELLO_PROGRAM")),
+        ("spawntest", env!("SPAWNTEST_PROGRAM")),
+        ("upper", env!("UPPER_PROGRAM")),
+        ("ipctest", env!("IPCTEST_PROGRAM")),
+    ]);
     let mut qemu = Command::new("qemu-system-x86_64");
     qemu.arg("-drive");
     qemu.arg(format!("format=raw,file={}", env!("UEFI_IMAGE")));
     qemu.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
-    // Virtual disk with index=1
+    // q35 replaces the legacy ide controller with an ahci one, both drives become sata disks
+    if env::args().any(|arg| arg == "--ahci") {
+        qemu.arg("-machine").arg("q35");
+    }
+
+    // Virtual disk, on the ide bus with index=1 or as a virtio-blk device with --virtio
     qemu.arg("-drive");
-    qemu.arg(format!("format=raw,file={},if=ide,index=1", create_disk::DISK_IMAGE));
+    if env::args().any(|arg| arg == "--virtio") {
+        qemu.arg(format!("format=raw,file={},if=virtio", create_disk::DISK_IMAGE));
+    } else {
+        qemu.arg(format!("format=raw,file={},if=ide,index=1", create_disk::DISK_IMAGE));
+    }
+
 
+    // Read only content image (for example an ext2 image made by mke2fs -d) with --content <image>
+    let args: Vec<String> = env::args().collect();
+    if let Some(image) = args.iter().position(|arg| arg == "--content").and_then(|i| args.get(i + 1)) {
+        qemu.arg("-drive");
+        qemu.arg(format!("format=raw,file={},if=virtio,readonly=on", image));
+    }
 
     let exit_status = qemu.status().unwrap();
     process::exit(exit_status.code().unwrap_or(-1));